@@ -0,0 +1,568 @@
+use serde_json::{json, Value as Json};
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+    ops::Range,
+};
+use tml::{
+    document::Document,
+    highlighter::{spans, SpanKind},
+    interpreter::Settings,
+    lint::{lint, Lint},
+    query::{self, CompletionKind},
+    rename,
+};
+
+/* Language server for tml worksheets, speaking JSON-RPC over stdio. Each open file is an
+incremental document evaluated on every change to publish its diagnostics, the other features
+query the cached evaluation. */
+
+/** Semantic token types, indexed by the encoded tokens */
+const TOKEN_TYPES: [&str; 6] = [
+    "number", "operator", "function", "variable", "string", "comment",
+];
+
+fn token_type(kind: SpanKind) -> Option<u32> {
+    Some(match kind {
+        SpanKind::Nb => 0,
+        SpanKind::Op => 1,
+        SpanKind::Fun => 2,
+        SpanKind::Var => 3,
+        SpanKind::Str => 4,
+        SpanKind::Comment => 5,
+        SpanKind::Error => return None,
+    })
+}
+
+/** Convert byte offsets of a text to LSP positions, whose characters are UTF-16 units */
+struct LineIndex<'a> {
+    text: &'a str,
+    starts: Vec<usize>,
+}
+
+impl<'a> LineIndex<'a> {
+    fn new(text: &'a str) -> Self {
+        let starts = std::iter::once(0)
+            .chain(text.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { text, starts }
+    }
+
+    fn line_of(&self, offset: usize) -> usize {
+        self.starts.partition_point(|start| *start <= offset) - 1
+    }
+
+    /** Line and UTF-16 character of an offset */
+    fn position(&self, offset: usize) -> (u32, u32) {
+        let line = self.line_of(offset);
+        let character = self.text[self.starts[line]..offset].encode_utf16().count();
+        (line as u32, character as u32)
+    }
+
+    /** Offset of a position, clamped to its line */
+    fn offset(&self, line: usize, character: usize) -> usize {
+        let start = match self.starts.get(line) {
+            Some(start) => *start,
+            None => return self.text.len(),
+        };
+        let end = self
+            .starts
+            .get(line + 1)
+            .map_or(self.text.len(), |end| end - 1);
+        let mut utf16 = 0;
+        for (idx, c) in self.text[start..end].char_indices() {
+            if utf16 >= character {
+                return start + idx;
+            }
+            utf16 += c.len_utf16();
+        }
+        end
+    }
+
+    fn position_json(&self, offset: usize) -> Json {
+        let (line, character) = self.position(offset);
+        json!({ "line": line, "character": character })
+    }
+
+    fn range(&self, span: Range<usize>) -> Json {
+        json!({ "start": self.position_json(span.start), "end": self.position_json(span.end) })
+    }
+
+    fn offset_of(&self, position: &Json) -> usize {
+        let field = |name| position[name].as_u64().unwrap_or(0) as usize;
+        self.offset(field("line"), field("character"))
+    }
+}
+
+/** An open file */
+struct File {
+    text: String,
+    document: Document,
+}
+
+impl File {
+    /** Start offset of each statement of the document */
+    fn statement_starts(&self, index: &LineIndex) -> Vec<usize> {
+        let mut line = 0;
+        self.document
+            .statements()
+            .iter()
+            .map(|statement| {
+                let start = index.starts[line];
+                line += statement.lines();
+                start
+            })
+            .collect()
+    }
+
+    /** Errors of the statements followed by the lint warnings */
+    fn diagnostics(&self, allow: &[Lint]) -> Json {
+        let index = LineIndex::new(&self.text);
+        let starts = self.statement_starts(&index);
+        let mut diagnostics: Vec<_> = self
+            .document
+            .statements()
+            .iter()
+            .zip(starts)
+            .filter_map(|(statement, start)| {
+                let error = statement.result().err()?;
+                let span = (error.span.clone()).unwrap_or(0..statement.source().len());
+                Some(json!({
+                    "range": index.range(start + span.start..start + span.end),
+                    "severity": 1,
+                    "source": "tml",
+                    "message": error.message,
+                }))
+            })
+            .collect();
+        diagnostics.extend(lint(&self.text, allow).into_iter().map(|warning| {
+            json!({
+                "range": index.range(warning.span),
+                "severity": 2,
+                "code": warning.lint.id(),
+                "source": "tml",
+                "message": warning.message,
+            })
+        }));
+        Json::from(diagnostics)
+    }
+
+    /** Tokens encoded relative to the previous one, as the protocol expects */
+    fn semantic_tokens(&self) -> Json {
+        let index = LineIndex::new(&self.text);
+        let starts = self.statement_starts(&index);
+        let mut data = Vec::new();
+        let (mut prev_line, mut prev_char) = (0, 0);
+        for (statement, start) in self.document.statements().iter().zip(starts) {
+            for (kind, span) in spans(statement.source()) {
+                let kind = match token_type(kind) {
+                    Some(kind) => kind,
+                    None => continue,
+                };
+                let (line, character) = index.position(start + span.start);
+                let (_, end) = index.position(start + span.end);
+                let delta_char = if line == prev_line {
+                    character - prev_char
+                } else {
+                    character
+                };
+                data.extend([line - prev_line, delta_char, end - character, kind, 0]);
+                (prev_line, prev_char) = (line, character);
+            }
+        }
+        json!({ "data": data })
+    }
+
+    /** Result of each statement, displayed after its last line */
+    fn inlay_hints(&self) -> Json {
+        let index = LineIndex::new(&self.text);
+        let starts = self.statement_starts(&index);
+        let hints: Vec<_> = self
+            .document
+            .statements()
+            .iter()
+            .zip(starts)
+            .filter_map(|(statement, start)| {
+                let result = statement
+                    .result()
+                    .ok()
+                    .filter(|result| !result.is_empty())?;
+                Some(json!({
+                    "position": index.position_json(start + statement.source().len()),
+                    "label": format!("=> {}", result),
+                    "paddingLeft": true,
+                }))
+            })
+            .collect();
+        Json::from(hints)
+    }
+}
+
+#[derive(Default)]
+struct Server {
+    settings: Settings,
+    // Lints disabled for every file
+    allow: Vec<Lint>,
+    files: HashMap<String, File>,
+    shutdown: bool,
+    exit: bool,
+}
+
+impl Server {
+    /** Handle a message, returning the response and notifications to send */
+    fn handle(&mut self, message: &Json) -> Vec<Json> {
+        let method = message["method"].as_str().unwrap_or_default();
+        let params = &message["params"];
+        let id = match message.get("id") {
+            Some(id) => id.clone(),
+            None => return self.notify(method, params),
+        };
+        let result = match method {
+            "initialize" => Ok(self.initialize(params)),
+            "shutdown" => {
+                self.shutdown = true;
+                Ok(Json::Null)
+            }
+            _ if method.starts_with("textDocument/") => match self
+                .files
+                .get_mut(params["textDocument"]["uri"].as_str().unwrap_or(""))
+            {
+                Some(file) => Self::request(file, method, params),
+                None => Err((-32602, "Unknown document".to_string())),
+            },
+            _ => Err((-32601, format!("Unsupported method '{}'", method))),
+        };
+        vec![match result {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+            Err((code, message)) => json!({
+                "jsonrpc": "2.0",
+                "id": id,
+                "error": { "code": code, "message": message },
+            }),
+        }]
+    }
+
+    fn initialize(&mut self, params: &Json) -> Json {
+        // Options like digits or notation, as in the REPL, and the lints to allow
+        if let Some(options) = params["initializationOptions"].as_object() {
+            for (option, value) in options {
+                if option == "allow" {
+                    let ids = value.as_array().into_iter().flatten();
+                    self.allow = ids.filter_map(|id| Lint::from_id(id.as_str()?)).collect();
+                    continue;
+                }
+                let value = value
+                    .as_str()
+                    .map_or_else(|| value.to_string(), String::from);
+                if let Err(err) = self.settings.set(option, &value) {
+                    eprintln!("{}", err);
+                }
+            }
+        }
+        json!({
+            "capabilities": {
+                "textDocumentSync": 1,
+                "completionProvider": {},
+                "hoverProvider": true,
+                "definitionProvider": true,
+                "renameProvider": { "prepareProvider": true },
+                "inlayHintProvider": true,
+                "semanticTokensProvider": {
+                    "legend": { "tokenTypes": TOKEN_TYPES, "tokenModifiers": [] },
+                    "full": true,
+                },
+            },
+            "serverInfo": { "name": "tml-lsp", "version": env!("CARGO_PKG_VERSION") },
+        })
+    }
+
+    fn notify(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+        let text = match method {
+            "exit" => {
+                self.exit = true;
+                return Vec::new();
+            }
+            "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+            // Full synchronisation, the last change holds the whole text
+            "textDocument/didChange" => params["contentChanges"]
+                .as_array()
+                .and_then(|changes| changes.last())
+                .and_then(|change| change["text"].as_str()),
+            "textDocument/didClose" => {
+                self.files.remove(uri);
+                return vec![publish(uri, json!([]))];
+            }
+            _ => None,
+        };
+        let text = match text {
+            Some(text) => text.to_string(),
+            None => return Vec::new(),
+        };
+        let settings = self.settings;
+        let file = self.files.entry(uri.into()).or_insert_with(|| File {
+            text: String::new(),
+            document: Document::new(settings),
+        });
+        file.document.update(&text);
+        file.text = text;
+        vec![publish(uri, file.diagnostics(&self.allow))]
+    }
+
+    fn request(file: &mut File, method: &str, params: &Json) -> Result<Json, (i64, String)> {
+        let index = LineIndex::new(&file.text);
+        let offset = index.offset_of(&params["position"]);
+        Ok(match method {
+            "textDocument/semanticTokens/full" => file.semantic_tokens(),
+            "textDocument/inlayHint" => file.inlay_hints(),
+            "textDocument/completion" => {
+                let items: Vec<_> = query::complete(&mut file.document, &file.text, offset)
+                    .into_iter()
+                    .map(|completion| {
+                        let kind = match completion.kind {
+                            CompletionKind::Variable => 6,
+                            CompletionKind::Constant => 21,
+                            CompletionKind::Function => 3,
+                        };
+                        json!({
+                            "label": completion.label,
+                            "kind": kind,
+                            "detail": completion.detail,
+                        })
+                    })
+                    .collect();
+                Json::from(items)
+            }
+            "textDocument/hover" => match query::hover(&mut file.document, &file.text, offset) {
+                Some(hover) => json!({
+                    "contents": { "kind": "plaintext", "value": hover.contents },
+                    "range": index.range(hover.span),
+                }),
+                None => Json::Null,
+            },
+            "textDocument/definition" => match query::definition(&file.text, offset) {
+                Some(span) => json!({
+                    "uri": params["textDocument"]["uri"],
+                    "range": index.range(span),
+                }),
+                None => Json::Null,
+            },
+            "textDocument/prepareRename" => match rename::prepare_rename(&file.text, offset) {
+                Ok(span) => index.range(span),
+                Err(err) => return Err((-32803, err)),
+            },
+            "textDocument/rename" => {
+                let name = params["newName"].as_str().unwrap_or_default();
+                let edits: Vec<_> = rename::rename(&file.text, offset, name)
+                    .map_err(|err| (-32803, err))?
+                    .into_iter()
+                    .map(|edit| json!({ "range": index.range(edit.span), "newText": edit.text }))
+                    .collect();
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                json!({ "changes": { uri: edits } })
+            }
+            _ => return Err((-32601, format!("Unsupported method '{}'", method))),
+        })
+    }
+}
+
+fn publish(uri: &str, diagnostics: Json) -> Json {
+    json!({
+        "jsonrpc": "2.0",
+        "method": "textDocument/publishDiagnostics",
+        "params": { "uri": uri, "diagnostics": diagnostics },
+    })
+}
+
+/** Read a message framed by a Content-Length header, None at the end of the input */
+fn read_message(input: &mut impl BufRead) -> io::Result<Option<Json>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+    let length = length.ok_or_else(|| io::Error::other("Missing Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> io::Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()
+}
+
+fn main() -> io::Result<()> {
+    let mut input = io::stdin().lock();
+    let mut output = io::stdout().lock();
+    let mut server = Server::default();
+    while let Some(message) = read_message(&mut input)? {
+        for reply in server.handle(&message) {
+            write_message(&mut output, &reply)?;
+        }
+        if server.exit {
+            break;
+        }
+    }
+    // Exiting without a shutdown request is an error
+    if !server.shutdown {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn notification(method: &str, params: Json) -> Json {
+        json!({ "jsonrpc": "2.0", "method": method, "params": params })
+    }
+
+    fn request(server: &mut Server, method: &str, params: Json) -> Json {
+        let message = json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params });
+        server.handle(&message).remove(0)["result"].clone()
+    }
+
+    #[test]
+    fn test_line_index() {
+        let index = LineIndex::new("a = 1\né = \"😀\" + x\n");
+        assert_eq!(index.position(0), (0, 0));
+        assert_eq!(index.position(6), (1, 0));
+        // 'é' is two bytes but one UTF-16 unit, '😀' four bytes and two units
+        let x = "a = 1\né = \"😀\" + x".len() - 1;
+        assert_eq!(index.position(x), (1, 11));
+        assert_eq!(index.offset(1, 11), x);
+        assert_eq!(index.offset(1, 99), x + 1);
+        assert_eq!(index.offset(5, 0), index.text.len());
+    }
+
+    #[test]
+    fn test_server() {
+        let mut server = Server::default();
+        let capabilities = request(&mut server, "initialize", json!({}));
+        assert_eq!(capabilities["capabilities"]["hoverProvider"], true);
+        let uri = "file:///sheet.tml";
+        let text = "rate = 0.05\ntotal = 1000 * (1 +\n  rate)\nbad = 1 + * 2\n2 * missing";
+        let published = server.handle(&notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": uri, "text": text } }),
+        ));
+        let diagnostics = &published[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 4);
+        assert_eq!(
+            diagnostics[0]["range"],
+            json!({ "start": { "line": 3, "character": 10 }, "end": { "line": 3, "character": 11 } })
+        );
+        assert_eq!(diagnostics[1]["message"], "Unknown variable 'missing'");
+        assert_eq!(
+            diagnostics[1]["range"],
+            json!({ "start": { "line": 4, "character": 4 }, "end": { "line": 4, "character": 11 } })
+        );
+        assert_eq!(diagnostics[2]["code"], "unused-variable");
+        assert_eq!(
+            diagnostics[2]["message"],
+            "'total' is assigned but never used"
+        );
+        assert_eq!(diagnostics[3]["severity"], 2);
+
+        let document = json!({ "uri": uri });
+        let hints = request(
+            &mut server,
+            "textDocument/inlayHint",
+            json!({ "textDocument": document }),
+        );
+        assert_eq!(hints[1]["label"], "=> total = 1050");
+        assert_eq!(hints[1]["position"], json!({ "line": 2, "character": 7 }));
+
+        let tokens = request(
+            &mut server,
+            "textDocument/semanticTokens/full",
+            json!({ "textDocument": document }),
+        );
+        // rate, =, 0.05 then total on the next line
+        assert_eq!(
+            tokens["data"].as_array().unwrap()[..20],
+            json!([0, 0, 4, 3, 0, 0, 5, 1, 1, 0, 0, 2, 4, 0, 0, 1, 0, 5, 3, 0])
+                .as_array()
+                .unwrap()[..]
+        );
+
+        let position = json!({ "line": 2, "character": 3 });
+        let params = json!({ "textDocument": document, "position": position });
+        let hover = request(&mut server, "textDocument/hover", params.clone());
+        assert_eq!(hover["contents"]["value"], "rate = 0.05");
+        let definition = request(&mut server, "textDocument/definition", params);
+        assert_eq!(
+            definition["range"]["start"],
+            json!({ "line": 0, "character": 0 })
+        );
+        let params = json!({ "textDocument": document, "position": { "line": 4, "character": 0 } });
+        let completions = request(&mut server, "textDocument/completion", params);
+        assert_eq!(completions[0]["label"], "rate");
+        assert_eq!(completions[1]["label"], "total");
+
+        let position = json!({ "line": 0, "character": 2 });
+        let params = json!({ "textDocument": document, "position": position, "newName": "r" });
+        let range = request(&mut server, "textDocument/prepareRename", params.clone());
+        assert_eq!(range["end"], json!({ "line": 0, "character": 4 }));
+        let edit = request(&mut server, "textDocument/rename", params);
+        let edits = edit["changes"][uri].as_array().unwrap();
+        assert_eq!(edits.len(), 2);
+        assert_eq!(
+            edits[1]["range"]["start"],
+            json!({ "line": 2, "character": 2 })
+        );
+        assert_eq!(edits[1]["newText"], "r");
+        let params = json!({ "textDocument": document, "position": position, "newName": "total" });
+        let message = json!({
+            "jsonrpc": "2.0", "id": 3, "method": "textDocument/rename", "params": params,
+        });
+        assert_eq!(server.handle(&message)[0]["error"]["code"], -32803);
+
+        let message = json!({ "jsonrpc": "2.0", "id": 2, "method": "unknown", "params": {} });
+        assert_eq!(server.handle(&message)[0]["error"]["code"], -32601);
+        request(&mut server, "shutdown", Json::Null);
+        server.handle(&notification("exit", Json::Null));
+        assert!(server.shutdown && server.exit);
+    }
+
+    #[test]
+    fn test_allow() {
+        let mut server = Server::default();
+        let options = json!({ "allow": ["unused-variable"], "digits": "3" });
+        request(
+            &mut server,
+            "initialize",
+            json!({ "initializationOptions": options }),
+        );
+        assert_eq!(server.allow, [Lint::UnusedVariable]);
+        let published = server.handle(&notification(
+            "textDocument/didOpen",
+            json!({ "textDocument": { "uri": "file:///a.tml", "text": "a = 1\nb = a/0" } }),
+        ));
+        let diagnostics = &published[0]["params"]["diagnostics"];
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        assert_eq!(diagnostics[0]["code"], "division-by-zero");
+    }
+
+    #[test]
+    fn test_framing() {
+        let mut output = Vec::new();
+        write_message(&mut output, &json!({ "id": 1 })).unwrap();
+        assert_eq!(output, b"Content-Length: 8\r\n\r\n{\"id\":1}");
+        let mut input = &output[..];
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "id": 1 })));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+    }
+}
@@ -0,0 +1,452 @@
+use crate::{
+    interpreter::{function, Context, Value},
+    lexer::Lexer,
+    parser::{parse, BinOp, Expression, Line, Literal, UnOp},
+};
+use std::cell::RefCell;
+
+/* Compilation of numerical expressions to a compact bytecode run by a stack machine, for an
+expression computed many times like an integrand or a plotted function. Names are resolved when
+compiling: builtin functions to their function pointer, parameters and local bindings to slots,
+other variables and constants to their current value. Only expressions computing a number
+without error can be compiled, anything else like strings or numerical methods is refused so the
+caller falls back to the interpreter. A program performs the operations of the interpreter in
+the same order and gives the same bits. */
+
+/** Operand of an operation, when compiling */
+#[derive(Debug, Clone, Copy)]
+enum Arg {
+    Pop,
+    Slot(usize),
+    Nb(f64),
+}
+
+/** Instruction, the binary operations apply to the top value and an operand popped from the
+stack, read from a slot or given as a number */
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Nb(f64),              // Push a number
+    Load(usize),          // Push the value of a slot
+    Store(usize),         // Pop a value into a slot
+    Call(fn(f64) -> f64), // Apply a builtin to the top value
+    Neg,
+    Add,
+    AddSlot(usize),
+    AddNb(f64),
+    Sub,
+    SubSlot(usize),
+    SubNb(f64),
+    Mul,
+    MulSlot(usize),
+    MulNb(f64),
+    Div,
+    DivSlot(usize),
+    DivNb(f64),
+    Mod,
+    ModSlot(usize),
+    ModNb(f64),
+    Pow,
+    PowSlot(usize),
+    PowNb(f64),
+}
+
+/** Instruction of a binary operation */
+fn binary(op: BinOp, arg: Arg) -> Op {
+    match (op, arg) {
+        (BinOp::Add, Arg::Pop) => Op::Add,
+        (BinOp::Add, Arg::Slot(slot)) => Op::AddSlot(slot),
+        (BinOp::Add, Arg::Nb(nb)) => Op::AddNb(nb),
+        (BinOp::Sub, Arg::Pop) => Op::Sub,
+        (BinOp::Sub, Arg::Slot(slot)) => Op::SubSlot(slot),
+        (BinOp::Sub, Arg::Nb(nb)) => Op::SubNb(nb),
+        (BinOp::Mul, Arg::Pop) => Op::Mul,
+        (BinOp::Mul, Arg::Slot(slot)) => Op::MulSlot(slot),
+        (BinOp::Mul, Arg::Nb(nb)) => Op::MulNb(nb),
+        (BinOp::Div, Arg::Pop) => Op::Div,
+        (BinOp::Div, Arg::Slot(slot)) => Op::DivSlot(slot),
+        (BinOp::Div, Arg::Nb(nb)) => Op::DivNb(nb),
+        (BinOp::Mod, Arg::Pop) => Op::Mod,
+        (BinOp::Mod, Arg::Slot(slot)) => Op::ModSlot(slot),
+        (BinOp::Mod, Arg::Nb(nb)) => Op::ModNb(nb),
+        (BinOp::Pow, Arg::Pop) => Op::Pow,
+        (BinOp::Pow, Arg::Slot(slot)) => Op::PowSlot(slot),
+        (BinOp::Pow, Arg::Nb(nb)) => Op::PowNb(nb),
+    }
+}
+
+/** A compiled expression, computed from the values of its parameters */
+#[derive(Debug, Clone)]
+pub struct Program {
+    code: Vec<Op>,
+    params: usize,
+    // Parameters followed by the local bindings
+    slots: usize,
+    // Slots then the stack, allocated once as runs are short
+    memory: RefCell<Vec<f64>>,
+}
+
+impl Program {
+    /** Compute the expression with its parameters bound to the arguments */
+    pub fn run(&self, args: &[f64]) -> Result<f64, String> {
+        if args.len() != self.params {
+            return Err(format!(
+                "Program takes {} argument(s) but {} were given",
+                self.params,
+                args.len()
+            ));
+        }
+        let mut memory = self.memory.borrow_mut();
+        for (slot, arg) in memory.iter_mut().zip(args) {
+            *slot = *arg;
+        }
+        let (slots, stack) = memory.split_at_mut(self.slots);
+        // Index of the next free place of the stack
+        let mut top = 0;
+        for op in &self.code {
+            match *op {
+                Op::Nb(nb) => {
+                    stack[top] = nb;
+                    top += 1;
+                }
+                Op::Load(slot) => {
+                    stack[top] = slots[slot];
+                    top += 1;
+                }
+                Op::Store(slot) => {
+                    top -= 1;
+                    slots[slot] = stack[top];
+                }
+                Op::Call(fun) => stack[top - 1] = fun(stack[top - 1]),
+                Op::Neg => stack[top - 1] = -stack[top - 1],
+                Op::Add => {
+                    top -= 1;
+                    stack[top - 1] += stack[top];
+                }
+                Op::AddSlot(slot) => stack[top - 1] += slots[slot],
+                Op::AddNb(nb) => stack[top - 1] += nb,
+                Op::Sub => {
+                    top -= 1;
+                    stack[top - 1] -= stack[top];
+                }
+                Op::SubSlot(slot) => stack[top - 1] -= slots[slot],
+                Op::SubNb(nb) => stack[top - 1] -= nb,
+                Op::Mul => {
+                    top -= 1;
+                    stack[top - 1] *= stack[top];
+                }
+                Op::MulSlot(slot) => stack[top - 1] *= slots[slot],
+                Op::MulNb(nb) => stack[top - 1] *= nb,
+                Op::Div => {
+                    top -= 1;
+                    stack[top - 1] /= stack[top];
+                }
+                Op::DivSlot(slot) => stack[top - 1] /= slots[slot],
+                Op::DivNb(nb) => stack[top - 1] /= nb,
+                Op::Mod => {
+                    top -= 1;
+                    stack[top - 1] %= stack[top];
+                }
+                Op::ModSlot(slot) => stack[top - 1] %= slots[slot],
+                Op::ModNb(nb) => stack[top - 1] %= nb,
+                Op::Pow => {
+                    top -= 1;
+                    stack[top - 1] = stack[top - 1].powf(stack[top]);
+                }
+                Op::PowSlot(slot) => stack[top - 1] = stack[top - 1].powf(slots[slot]),
+                Op::PowNb(nb) => stack[top - 1] = stack[top - 1].powf(nb),
+            }
+        }
+        Ok(stack[0])
+    }
+}
+
+struct Compiler<'c, 'a> {
+    ctx: &'c Context,
+    code: Vec<Op>,
+    // Names bound to slots, from the parameters to the innermost binding
+    scopes: Vec<(&'a str, usize)>,
+    slots: usize,
+    depth: usize,
+    max_depth: usize,
+}
+
+impl<'a> Compiler<'_, 'a> {
+    fn push(&mut self, op: Op) {
+        match op {
+            Op::Nb(_) | Op::Load(_) => self.depth += 1,
+            Op::Store(_) | Op::Add | Op::Sub | Op::Mul | Op::Div | Op::Mod | Op::Pow => {
+                self.depth -= 1
+            }
+            _ => {}
+        }
+        self.max_depth = self.max_depth.max(self.depth);
+        self.code.push(op);
+    }
+
+    /** Resolve a name as the interpreter does, constants first then the innermost variable */
+    fn var(&mut self, id: &str) -> Result<Arg, String> {
+        let value = match self.ctx.constant(id) {
+            Some(value) => value,
+            None => match self.scopes.iter().rev().find(|(name, _)| *name == id) {
+                Some((_, slot)) => return Ok(Arg::Slot(*slot)),
+                None => match self.ctx.get(id) {
+                    Some(value) => value.clone(),
+                    None => return Err(format!("Unknown variable '{}'", id)),
+                },
+            },
+        };
+        match value {
+            Value::Nb(nb) => Ok(Arg::Nb(nb)),
+            value => Err(format!("Cannot compile {}", value.to_code())),
+        }
+    }
+
+    /** Operand of a number or a name */
+    fn arg(&mut self, lit: &Literal) -> Result<Arg, String> {
+        match lit {
+            Literal::Nb(nb, _) => Ok(Arg::Nb(*nb)),
+            Literal::Var(id) => self.var(id),
+            _ => unreachable!(),
+        }
+    }
+
+    fn literal(&mut self, lit: &Literal<'a>) -> Result<(), String> {
+        match lit {
+            Literal::Nb(..) | Literal::Var(_) => {
+                let op = match self.arg(lit)? {
+                    Arg::Slot(slot) => Op::Load(slot),
+                    Arg::Nb(nb) => Op::Nb(nb),
+                    Arg::Pop => unreachable!(),
+                };
+                self.push(op);
+            }
+            Literal::UnaryOp(op, lit) => {
+                self.literal(lit)?;
+                if *op == UnOp::Sub {
+                    self.push(Op::Neg);
+                }
+            }
+            Literal::BinaryOp(op, lits) => {
+                self.literal(&lits.0)?;
+                let arg = match &lits.1 {
+                    lit @ (Literal::Nb(..) | Literal::Var(_)) => self.arg(lit)?,
+                    lit => {
+                        self.literal(lit)?;
+                        Arg::Pop
+                    }
+                };
+                self.push(binary(*op, arg));
+            }
+            Literal::Fun(name, args) => match (function(name), args.as_slice()) {
+                (Some(fun), [arg]) => {
+                    self.literal(arg)?;
+                    self.push(Op::Call(fun));
+                }
+                _ => return Err(format!("Cannot compile a call to '{}'", name)),
+            },
+            Literal::Let(bindings, body, _) => {
+                let depth = self.scopes.len();
+                for (id, lit) in bindings {
+                    if self.ctx.constant(id).is_some() {
+                        return Err(format!("Cannot bind the constant '{}'", id));
+                    }
+                    self.literal(lit)?;
+                    self.push(Op::Store(self.slots));
+                    self.scopes.push((id, self.slots));
+                    self.slots += 1;
+                }
+                self.literal(body)?;
+                self.scopes.truncate(depth);
+            }
+            Literal::Str(_) | Literal::Template(_) => return Err("Cannot compile a string".into()),
+            Literal::Equation(_) => return Err("Cannot compile an equation".into()),
+        }
+        Ok(())
+    }
+}
+
+/** Compile a literal computed from parameters */
+pub(crate) fn compile_literal<'a>(
+    ctx: &Context,
+    lit: &Literal<'a>,
+    params: &[&'a str],
+) -> Result<Program, String> {
+    if ctx.settings().decimal {
+        return Err("Cannot compile decimal numbers".into());
+    }
+    if let Some(id) = params.iter().find(|id| ctx.constant(id).is_some()) {
+        return Err(format!("Cannot use the constant '{}' as a variable", id));
+    }
+    let mut compiler = Compiler {
+        ctx,
+        code: Vec::new(),
+        scopes: params.iter().enumerate().map(|(i, id)| (*id, i)).collect(),
+        slots: params.len(),
+        depth: 0,
+        max_depth: 0,
+    };
+    compiler.literal(lit)?;
+    Ok(Program {
+        code: compiler.code,
+        params: params.len(),
+        slots: compiler.slots,
+        memory: RefCell::new(vec![0.; compiler.slots + compiler.max_depth]),
+    })
+}
+
+/** Compile an expression computed from parameters, the other names taking their current value
+in the context */
+pub fn compile(ctx: &Context, code: &str, params: &[&str]) -> Result<Program, String> {
+    match parse(Lexer::load(code))? {
+        Line::Exprs(exprs) => match exprs.as_slice() {
+            [Expression::Literal(lit)] => compile_literal(ctx, lit, params),
+            _ => Err("Expected a single expression".into()),
+        },
+        _ => Err("Expected an expression".into()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        interpreter::{compute, compute_literal},
+        testing::{bits, expression, literal},
+    };
+    use proptest::prelude::*;
+    use std::time::Instant;
+
+    /** Compute a literal with the interpreter, x bound to a value */
+    fn interpret(ctx: &mut Context, lit: &Literal, x: f64) -> Result<f64, String> {
+        ctx.with_var("x", x, |ctx| compute_literal(ctx, lit))
+            .map_err(String::from)
+    }
+
+    #[test]
+    fn test_run() {
+        let mut ctx = Context::empty();
+        compute(&mut ctx, "r = 2").unwrap();
+        compute(&mut ctx, "const k = 0.5").unwrap();
+        let sources = [
+            "x",
+            "-x^2 + 3*x - 1",
+            "sin(x)*exp(-x/r) % 0.3",
+            "PI*r^2*k + +x",
+            "let a = x*2, b = a + 1 in a*b + (let a = 1 in a) + a",
+            "y^2 + x where y = sqrt(x + 1)",
+            "let r = x in r*r",
+            "1/x + ln(x) + x^0.5",
+        ];
+        for source in sources {
+            let lit = literal(source);
+            let program = compile(&ctx, source, &["x"]).unwrap();
+            for x in [-2.5, -0., 0., 1e-300, 0.75, 3., f64::INFINITY, f64::NAN] {
+                let expected = interpret(&mut ctx, &lit, x).unwrap();
+                assert_eq!(
+                    bits(program.run(&[x]).unwrap()),
+                    bits(expected),
+                    "{} at {}",
+                    source,
+                    x
+                );
+            }
+        }
+        let program = compile(&ctx, "x*y - x/y", &["x", "y"]).unwrap();
+        assert_eq!(program.run(&[3., 2.]), Ok(4.5));
+        assert_eq!(
+            program.run(&[3.]),
+            Err("Program takes 2 argument(s) but 1 were given".into())
+        );
+        // Variables are read when compiling
+        let program = compile(&ctx, "r*x", &["x"]).unwrap();
+        compute(&mut ctx, "r = 10").unwrap();
+        assert_eq!(program.run(&[3.]), Ok(6.));
+    }
+
+    #[test]
+    fn test_refused() {
+        let mut ctx = Context::empty();
+        compute(&mut ctx, "s = \"a\"").unwrap();
+        let err = |source: &str, params: &[&str]| compile(&ctx, source, params).unwrap_err();
+        assert_eq!(err("x + y", &["x"]), "Unknown variable 'y'");
+        assert_eq!(err("x + s", &["x"]), "Cannot compile \"a\"");
+        assert_eq!(
+            err("len(\"ab\")*x", &["x"]),
+            "Cannot compile a call to 'len'"
+        );
+        assert_eq!(
+            err("integrate(t, t, 0, x)", &["x"]),
+            "Cannot compile a call to 'integrate'"
+        );
+        assert_eq!(err("sqrt(x, 2)", &["x"]), "Cannot compile a call to 'sqrt'");
+        assert_eq!(err("x + \"a\"", &["x"]), "Cannot compile a string");
+        assert_eq!(
+            err("let E = 1 in E*x", &["x"]),
+            "Cannot bind the constant 'E'"
+        );
+        assert_eq!(
+            err("PI*2", &["PI"]),
+            "Cannot use the constant 'PI' as a variable"
+        );
+        assert_eq!(err("a = x", &["x"]), "Expected a single expression");
+        let mut settings = ctx.settings();
+        settings.decimal = true;
+        ctx.set_settings(settings);
+        assert_eq!(
+            compile(&ctx, "x*0.1", &["x"]).unwrap_err(),
+            "Cannot compile decimal numbers"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn run_as_interpreter(code in expression(true), x in -10f64..10.) {
+            let mut ctx = Context::empty();
+            compute(&mut ctx, "y = 1.5").unwrap();
+            let lit = literal(&code);
+            let program = compile(&ctx, &code, &["x"]).unwrap();
+            let expected = interpret(&mut ctx, &lit, x).unwrap();
+            prop_assert_eq!(bits(program.run(&[x]).unwrap()), bits(expected));
+        }
+    }
+
+    /** Compare the time of repeated evaluations with the interpreter, run with
+    `cargo test --release --lib bench_vm -- --ignored --nocapture`. Builtins and powers take
+    the same time in both, which bounds the speedup of the expressions calling them. */
+    #[test]
+    #[ignore]
+    fn bench_vm() {
+        const RUNS: usize = 1_000_000;
+        let mut ctx = Context::empty();
+        compute(&mut ctx, "a = 1.5").unwrap();
+        let sources = [
+            "x*x + 3*x - 1",
+            "(x - a)*(x + a)/(x*x + 1) - 2*x",
+            "let u = x*a, v = u*u in (u + v)/(1 + v)",
+            "x^2 + 3*x - 1",
+            "sin(x)*exp(-x/a) + sqrt(x + 1)",
+        ];
+        for source in sources {
+            let lit = literal(source);
+            let program = compile(&ctx, source, &["x"]).unwrap();
+            let time = |f: &mut dyn FnMut(f64) -> f64| {
+                let start = Instant::now();
+                let mut sum = 0.;
+                for i in 0..RUNS {
+                    sum += f(i as f64 / RUNS as f64);
+                }
+                assert!(sum.is_finite());
+                start.elapsed().as_nanos() as f64 / RUNS as f64
+            };
+            let tree = time(&mut |x| interpret(&mut ctx, &lit, x).unwrap());
+            let vm = time(&mut |x| program.run(&[x]).unwrap());
+            println!(
+                "{:<42} tree {:>6.1}ns  vm {:>5.1}ns  x{:.1}",
+                source,
+                tree,
+                vm,
+                tree / vm
+            );
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use crate::{
+    cst::{parse_cst, Element, LeafKind, Node, NodeKind},
+    interpreter::{constant, function, is_builtin, BINDING_FUNCTIONS},
+    lexer::Lexer,
+    parser::parse,
+    resolve::{resolve_tree, Occurrence, Role, Symbol},
+};
+use std::{collections::HashSet, ops::Range};
+
+/* Static check of a whole document, reporting every problem found without computing anything.
+Statements are checked in order: names must be assigned by a statement above, be constants or be
+bound locally, and builtins must be called with a supported number of arguments. A statement
+that does not parse only reports its syntax error. */
+
+/** A problem on a span of the source */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    pub message: String,
+    pub span: Range<usize>,
+}
+
+/** Supported number of arguments of a builtin function, as a range and its description */
+fn arity(name: &str) -> Option<(Range<usize>, &'static str)> {
+    Some(match name {
+        "round" => (1..4, "1 to 3"),
+        "diff" | "substr" => (2..4, "2 or 3"),
+        "integrate" => (4..5, "4"),
+        "solve" => (2..5, "2 to 4"),
+        "len" | "upper" | "lower" | "str" | "num" => (1..2, "1"),
+        name if function(name).is_some() => (1..2, "1"),
+        _ => return None,
+    })
+}
+
+/** Names defined by the statements checked so far */
+#[derive(Default)]
+struct Scope<'a> {
+    variables: HashSet<&'a str>,
+    constants: HashSet<&'a str>,
+    // An expression was computed, its value is read as '$'
+    has_value: bool,
+}
+
+impl Scope<'_> {
+    fn is_constant(&self, id: &str) -> bool {
+        constant(id).is_some() || self.constants.contains(id)
+    }
+}
+
+struct Checker<'a> {
+    source: &'a str,
+    occurrences: Vec<Occurrence<'a>>,
+    scope: Scope<'a>,
+    // Span of the last equation waiting for a solve statement
+    equation: Option<Range<usize>>,
+    problems: Vec<Problem>,
+}
+
+impl<'a> Checker<'a> {
+    fn problem(&mut self, message: String, span: Range<usize>) {
+        self.problems.push(Problem { message, span });
+    }
+
+    /** Names directly under a node, as a keyword or an identifier */
+    fn leaves(&self, node: &Node, kind: LeafKind) -> Vec<(&'a str, Range<usize>)> {
+        node.children()
+            .iter()
+            .filter_map(|child| match child {
+                Element::Leaf(leaf) if leaf.kind() == kind => {
+                    Some((leaf.text(self.source), leaf.span()))
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /** Check the calls and the local bindings of an expression */
+    fn expression(&mut self, node: &Node) {
+        let children: Vec<_> = node.nodes().collect();
+        match node.kind() {
+            NodeKind::Call => {
+                let (name, span) = self.leaves(node, LeafKind::Id).remove(0);
+                match arity(name) {
+                    _ if !is_builtin(name) => {
+                        self.problem(format!("Unknown function '{}'", name), span)
+                    }
+                    Some((range, expected)) if !range.contains(&children.len()) => self.problem(
+                        format!(
+                            "Function '{}' takes {} argument(s) but {} were given",
+                            name,
+                            expected,
+                            children.len()
+                        ),
+                        node.span(),
+                    ),
+                    _ => {}
+                }
+                // diff(x^2, x), the variable is a name which is not a constant
+                if let (true, [_, var, ..]) =
+                    (BINDING_FUNCTIONS.contains(&name), children.as_slice())
+                {
+                    let text = var.text(self.source);
+                    if var.kind() != NodeKind::Var {
+                        self.problem(format!("Expected a variable, got '{}'", text), var.span());
+                    } else if self.scope.is_constant(text) {
+                        let message = format!("Cannot use the constant '{}' as a variable", text);
+                        self.problem(message, var.span());
+                    }
+                }
+            }
+            NodeKind::Binding => {
+                if let Some((id, span)) = self.leaves(node, LeafKind::Id).pop() {
+                    if self.scope.is_constant(id) {
+                        self.problem(format!("Cannot bind the constant '{}'", id), span);
+                    }
+                }
+            }
+            _ => {}
+        }
+        for child in children {
+            self.expression(child);
+        }
+    }
+
+    /** Report the equation waiting for a solve statement, if any */
+    fn unsolved(&mut self) {
+        if let Some(span) = self.equation.take() {
+            self.problem("Expected 'solve' after the equation".into(), span);
+        }
+    }
+
+    /** Check if a statement computes an expression, whose value is kept as '$' */
+    fn is_value(&self, node: &Node) -> bool {
+        match node.kind() {
+            NodeKind::Assign
+            | NodeKind::Const
+            | NodeKind::Solve
+            | NodeKind::Print
+            | NodeKind::Equation
+            | NodeKind::Error => false,
+            // A derivative displayed as an expression and the roots of an interval
+            NodeKind::Call => {
+                let name = self.leaves(node, LeafKind::Id).remove(0).0;
+                !matches!((name, node.nodes().count()), ("diff", 2) | ("solve", 4))
+            }
+            _ => true,
+        }
+    }
+
+    /** Check the names read by a statement, then define the names it writes */
+    fn statement(&mut self, node: &Node) {
+        let span = node.span();
+        let occurrences: Vec<_> = self
+            .occurrences
+            .iter()
+            .filter(|occurrence| span.contains(&occurrence.span.start))
+            .cloned()
+            .collect();
+
+        match node.kind() {
+            NodeKind::Equation => self.equation = Some(span.clone()),
+            NodeKind::Solve => self.equation = None,
+            _ => self.unsolved(),
+        }
+
+        // The names of an equation are its unknowns, solved later
+        if node.kind() == NodeKind::Equation {
+            let has_unknown = occurrences.iter().any(|occurrence| {
+                matches!(occurrence.symbol, Symbol::Global(id) if !self.scope.is_constant(id))
+            });
+            if !has_unknown {
+                let message = format!("Equation '{}' has no unknown", node.text(self.source));
+                self.problem(message, span.clone());
+            }
+        } else {
+            for read in occurrences.iter().filter(|o| o.role == Role::Read) {
+                let id = match read.symbol {
+                    Symbol::Global(id) => id,
+                    _ => continue,
+                };
+                let is_value = id == "$" && self.scope.has_value;
+                if is_value || self.scope.variables.contains(id) || self.scope.is_constant(id) {
+                    continue;
+                }
+                let is_later = self.occurrences.iter().any(|occurrence| {
+                    occurrence.symbol == read.symbol
+                        && occurrence.role == Role::Write
+                        && occurrence.span.start > span.end
+                });
+                let message = match is_later {
+                    true => format!("Variable '{}' is used before being assigned", id),
+                    false => format!("Unknown variable '{}'", id),
+                };
+                self.problem(message, read.span.clone());
+            }
+        }
+        if self.is_value(node) {
+            self.scope.has_value = true;
+        }
+        self.expression(node);
+
+        if !matches!(
+            node.kind(),
+            NodeKind::Assign | NodeKind::Const | NodeKind::Solve
+        ) {
+            return;
+        }
+        let keyword = self.leaves(node, LeafKind::Keyword).pop().map(|(k, _)| k);
+        for (id, span) in self.leaves(node, LeafKind::Id) {
+            let message = match (node.kind(), keyword) {
+                (NodeKind::Const, Some("override")) => {
+                    self.scope.constants.insert(id);
+                    continue;
+                }
+                _ if is_builtin(id) => format!("Cannot assign the builtin function '{}'", id),
+                (NodeKind::Const, _) if self.scope.is_constant(id) => format!(
+                    "Constant '{}' is already defined, use 'override {} = ...' to redefine it",
+                    id, id
+                ),
+                (NodeKind::Const, _) => {
+                    self.scope.constants.insert(id);
+                    continue;
+                }
+                _ if self.scope.is_constant(id) => format!(
+                    "Cannot assign the constant '{}', use 'override {} = ...' to redefine it",
+                    id, id
+                ),
+                _ => {
+                    self.scope.variables.insert(id);
+                    continue;
+                }
+            };
+            self.problem(message, span);
+        }
+    }
+}
+
+/** Check a document, returning its problems in source order */
+pub fn check(source: &str) -> Vec<Problem> {
+    let document = parse_cst(source);
+    let mut checker = Checker {
+        source,
+        occurrences: resolve_tree(source, &document),
+        scope: Scope::default(),
+        equation: None,
+        problems: Vec::new(),
+    };
+    for line in document.nodes() {
+        let text = line.text(source);
+        let start = line.span().start;
+        if let Err(err) = parse(Lexer::load(text)) {
+            let span = err.span.unwrap_or(0..text.trim_end().len());
+            checker.problem(err.message, start + span.start..start + span.end);
+            continue;
+        }
+        for statement in line.nodes() {
+            checker.statement(statement);
+        }
+    }
+    checker.unsolved();
+    let mut problems = checker.problems;
+    problems.sort_by_key(|problem| problem.span.start);
+    problems
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::{evaluate, statements, Context};
+
+    /** Check a source, listing the messages with the text they point at */
+    fn problems(source: &str) -> Vec<(String, &str)> {
+        check(source)
+            .into_iter()
+            .map(|problem| (problem.message, &source[problem.span]))
+            .collect()
+    }
+
+    #[test]
+    fn test_check_names() {
+        assert!(problems("a = 2\nb = a*PI + sqrt(a)\nb\n\"{b:.2}\" $").is_empty());
+        // '$' is the value of the last expression computed
+        assert_eq!(
+            problems("a = 2\n$ + 1"),
+            [("Unknown variable '$'".into(), "$")]
+        );
+        assert_eq!(
+            problems("a = b\nb = 1\nc + d"),
+            [
+                ("Variable 'b' is used before being assigned".into(), "b"),
+                ("Unknown variable 'c'".into(), "c"),
+                ("Unknown variable 'd'".into(), "d"),
+            ]
+        );
+        // Locals, unknowns of equations and variables of numerical methods
+        let source = "y = let a = 2 in a*x where x = 3\n\
+            a + b = 3\na - b = 1\nsolve a, b\nc = a + b\n\
+            d = integrate(t^2, t, 0, 1) + solve(u^2 = 4, u, 0) + diff(v^2, v, 1)";
+        assert!(problems(source).is_empty(), "{:?}", problems(source));
+        assert_eq!(
+            problems("x = 1; y = x + z"),
+            [("Unknown variable 'z'".into(), "z")]
+        );
+    }
+
+    #[test]
+    fn test_check_equations() {
+        assert_eq!(
+            problems(
+                "x + 1 = 3
+y = 2
+2*PI = 6; solve x
+x - 1 = 0"
+            ),
+            [
+                ("Expected 'solve' after the equation".into(), "x + 1 = 3"),
+                ("Equation '2*PI = 6' has no unknown".into(), "2*PI = 6"),
+                ("Expected 'solve' after the equation".into(), "x - 1 = 0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_calls() {
+        assert_eq!(
+            problems("sqrt(1, 2) + round(1.5, 0) + foo(1)\nsubstr(\"a\")"),
+            [
+                (
+                    "Function 'sqrt' takes 1 argument(s) but 2 were given".into(),
+                    "sqrt(1, 2)"
+                ),
+                ("Unknown function 'foo'".into(), "foo"),
+                (
+                    "Function 'substr' takes 2 or 3 argument(s) but 1 were given".into(),
+                    "substr(\"a\")"
+                ),
+            ]
+        );
+        assert_eq!(
+            problems("x = 1\ndiff(2*x, 3, 1) + integrate(x, PI, 0, 1)"),
+            [
+                ("Expected a variable, got '3'".into(), "3"),
+                ("Cannot use the constant 'PI' as a variable".into(), "PI"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_assignments() {
+        let source = "const g = 9.81\ng = 1\nconst g = 2\nsqrt = 1\nPI = 3\n\
+            override PI = 3\nlet E = 1 in E\n1 + * 2\nx = (1 +\n  )";
+        assert_eq!(
+            problems(source),
+            [
+                (
+                    "Cannot assign the constant 'g', use 'override g = ...' to redefine it".into(),
+                    "g"
+                ),
+                (
+                    "Constant 'g' is already defined, use 'override g = ...' to redefine it".into(),
+                    "g"
+                ),
+                ("Cannot assign the builtin function 'sqrt'".into(), "sqrt"),
+                (
+                    "Cannot assign the constant 'PI', use 'override PI = ...' to redefine it"
+                        .into(),
+                    "PI"
+                ),
+                ("Cannot bind the constant 'E'".into(), "E"),
+                ("Expected an unary operator such as + or -".into(), "*"),
+                ("Incomplete expression".into(), ")"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_runtime() {
+        // A document without problems computes without errors, and the first runtime error
+        // of a document is reported by the check
+        let sources = [
+            "r = 2\narea = PI*r^2\n\"{area:.1}\"",
+            "x = 1\ny = x + w\nw = 2",
+            "const c = 1\nc = 2",
+            "v = floor(1, 2)",
+            "a + b = 3\na - b = 1\nsolve a, b\nc = a*b",
+            "a + b = 3\nc = 1",
+            "2 = 3",
+            "$ + 1",
+            "x = 1\n$ + 1",
+            "diff(x^2, x)\n$",
+            "2*3\n$ + 1",
+            "1; $ + 1",
+        ];
+        for source in sources {
+            let mut ctx = Context::empty();
+            let error = statements(source)
+                .map(|(statement, _)| evaluate(&mut ctx, statement))
+                .find_map(Result::err);
+            let problems = check(source);
+            assert_eq!(error.is_some(), !problems.is_empty(), "{}", source);
+        }
+    }
+}
@@ -0,0 +1,747 @@
+use crate::{
+    interpreter::statements,
+    lexer::{Lexer, Sep, TokenKind},
+};
+use std::ops::Range;
+
+/* Lossless concrete syntax tree. Unlike the parser, which drops whitespace, comments and
+parentheses, every byte of the source belongs to a leaf of the tree, so untouched regions can be
+reproduced exactly when rewriting code. The tree follows the grammar of the parser but never
+fails: unexpected tokens are wrapped in error nodes and missing ones are empty error nodes.
+Whitespace is attached to the innermost node containing the tokens around it, so nodes other
+than the document and its lines start and end with a token. */
+
+/** Kind of a node */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NodeKind {
+    Document,
+    Line,     // A statement of the document, which may span multiple lines
+    Assign,   // a = 1
+    Const,    // const g = 9.81 or override PI = 3.14
+    Solve,    // solve a, b
+    Print,    // "a is {a}" a
+    Equation, // a + b = 1, also as a function argument
+    Nb,
+    Var,
+    Str,
+    Template,
+    Paren,
+    Unary,
+    Binary, // Also an implicit multiplication such as 2x
+    Call,
+    Let,   // let a = 1 in a
+    Where, // a where a = 1
+    Binding,
+    Error,
+}
+
+/** Kind of a leaf */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeafKind {
+    Whitespace,
+    Comment, // A whole comment line
+    Nb,
+    Id,
+    Keyword, // let, in, where, const, override and solve where they are used as such
+    Op,
+    Open,
+    Close,
+    Comma,
+    Semi,
+    Str,
+    Template, // String part around an interpolated expression
+    Spec,
+    Error, // Unsupported char
+}
+
+/** A token or trivia of the source */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leaf {
+    kind: LeafKind,
+    span: Range<usize>,
+}
+
+impl Leaf {
+    pub fn kind(&self) -> LeafKind {
+        self.kind
+    }
+
+    /** Byte range in the source */
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.span.clone()]
+    }
+
+    fn is_trivia(&self) -> bool {
+        self.kind == LeafKind::Whitespace
+    }
+}
+
+/** A child of a node */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Element {
+    Node(Node),
+    Leaf(Leaf),
+}
+
+impl Element {
+    pub fn span(&self) -> Range<usize> {
+        match self {
+            Element::Node(node) => node.span(),
+            Element::Leaf(leaf) => leaf.span(),
+        }
+    }
+}
+
+/** A node of the tree */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Node {
+    kind: NodeKind,
+    span: Range<usize>,
+    children: Vec<Element>,
+}
+
+impl Node {
+    pub fn kind(&self) -> NodeKind {
+        self.kind
+    }
+
+    /** Byte range in the source, empty for a missing element */
+    pub fn span(&self) -> Range<usize> {
+        self.span.clone()
+    }
+
+    pub fn children(&self) -> &[Element] {
+        &self.children
+    }
+
+    /** Child nodes */
+    pub fn nodes(&self) -> impl Iterator<Item = &Node> {
+        self.children.iter().filter_map(|child| match child {
+            Element::Node(node) => Some(node),
+            Element::Leaf(_) => None,
+        })
+    }
+
+    /** Every leaf under the node, in source order */
+    pub fn leaves(&self) -> Vec<&Leaf> {
+        let mut leaves = Vec::new();
+        self.collect_leaves(&mut leaves);
+        leaves
+    }
+
+    fn collect_leaves<'a>(&'a self, leaves: &mut Vec<&'a Leaf>) {
+        for child in &self.children {
+            match child {
+                Element::Node(node) => node.collect_leaves(leaves),
+                Element::Leaf(leaf) => leaves.push(leaf),
+            }
+        }
+    }
+
+    pub fn text<'a>(&self, source: &'a str) -> &'a str {
+        &source[self.span.clone()]
+    }
+
+    /** The deepest node containing an offset, an end offset included */
+    pub fn node_at(&self, offset: usize) -> Option<&Node> {
+        if offset < self.span.start || offset > self.span.end {
+            return None;
+        }
+        Some(
+            self.nodes()
+                .find_map(|node| node.node_at(offset))
+                .unwrap_or(self),
+        )
+    }
+}
+
+/** Builds nodes from the leaves of a statement */
+struct Builder<'a> {
+    source: &'a str,
+    leaves: Vec<Leaf>,
+    pos: usize,
+    // End of the last pushed element
+    offset: usize,
+    // Children of the nodes being built, innermost last
+    stack: Vec<Vec<Element>>,
+}
+
+impl<'a> Builder<'a> {
+    /** Index of the next token, skipping trivia */
+    fn next_index(&self, from: usize) -> usize {
+        (from..self.leaves.len())
+            .find(|&i| !self.leaves[i].is_trivia())
+            .unwrap_or(self.leaves.len())
+    }
+
+    /** The n-th next token */
+    fn nth(&self, n: usize) -> Option<&Leaf> {
+        let mut i = self.next_index(self.pos);
+        for _ in 0..n {
+            i = self.next_index(i + 1);
+        }
+        self.leaves.get(i)
+    }
+
+    fn peek(&self) -> Option<LeafKind> {
+        self.nth(0).map(Leaf::kind)
+    }
+
+    fn peek_text(&self) -> &'a str {
+        let source = self.source;
+        self.nth(0).map_or("", |leaf| leaf.text(source))
+    }
+
+    fn at(&self, kind: LeafKind, text: &str) -> bool {
+        self.peek() == Some(kind) && self.peek_text() == text
+    }
+
+    fn at_end(&self) -> bool {
+        matches!(self.peek(), None | Some(LeafKind::Semi))
+    }
+
+    fn push(&mut self, element: Element) {
+        self.offset = element.span().end;
+        self.stack.last_mut().unwrap().push(element);
+    }
+
+    /** Attach the pending trivia to the current node */
+    fn trivia(&mut self) {
+        while self.pos < self.leaves.len() && self.leaves[self.pos].is_trivia() {
+            let leaf = self.leaves[self.pos].clone();
+            self.push(Element::Leaf(leaf));
+            self.pos += 1;
+        }
+    }
+
+    /** Move the next token to the current node */
+    fn bump(&mut self) {
+        self.trivia();
+        if let Some(leaf) = self.leaves.get(self.pos).cloned() {
+            self.push(Element::Leaf(leaf));
+            self.pos += 1;
+        }
+    }
+
+    /** Move the next token to the current node as a keyword */
+    fn bump_keyword(&mut self) {
+        self.bump();
+        if let Some(Element::Leaf(leaf)) = self.stack.last_mut().unwrap().last_mut() {
+            leaf.kind = LeafKind::Keyword;
+        }
+    }
+
+    /** Position of the next node in the current one */
+    fn checkpoint(&mut self) -> usize {
+        self.trivia();
+        self.stack.last().unwrap().len()
+    }
+
+    fn start(&mut self) {
+        self.trivia();
+        self.stack.push(Vec::new());
+    }
+
+    /** Start a node wrapping the elements pushed since a checkpoint */
+    fn start_at(&mut self, checkpoint: usize) {
+        let children = self.stack.last_mut().unwrap().split_off(checkpoint);
+        self.stack.push(children);
+    }
+
+    fn finish(&mut self, kind: NodeKind) {
+        let children = self.stack.pop().unwrap();
+        let span = match (children.first(), children.last()) {
+            (Some(first), Some(last)) => first.span().start..last.span().end,
+            _ => self.offset..self.offset,
+        };
+        self.push(Element::Node(Node {
+            kind,
+            span,
+            children,
+        }));
+    }
+
+    /** Move the next token if it has the expected kind, else mark it as missing */
+    fn expect(&mut self, kind: LeafKind) {
+        if self.peek() == Some(kind) {
+            self.bump();
+        } else {
+            self.missing();
+        }
+    }
+
+    /** Mark a missing element with an empty error node */
+    fn missing(&mut self) {
+        self.start();
+        self.finish(NodeKind::Error);
+    }
+
+    /** Wrap the tokens up to the end of the statement in an error node */
+    fn error_to_end(&mut self) {
+        if !self.at_end() {
+            self.start();
+            while !self.at_end() {
+                self.bump();
+            }
+            self.finish(NodeKind::Error);
+        }
+    }
+
+    fn line(&mut self) {
+        loop {
+            match self.peek() {
+                None => break,
+                Some(LeafKind::Semi) => self.bump(),
+                Some(LeafKind::Comment) => self.bump(),
+                _ => {
+                    self.statement();
+                    self.error_to_end();
+                }
+            }
+        }
+        self.trivia();
+    }
+
+    fn statement(&mut self) {
+        let (first, second) = (self.peek(), self.nth(1).map(Leaf::kind));
+        let keyword = self.peek_text();
+        match (first, second) {
+            (Some(LeafKind::Str | LeafKind::Template), _) => {
+                self.start();
+                while !self.at_end() {
+                    let pos = self.pos;
+                    self.literal(0);
+                    if self.next_index(pos) >= self.pos {
+                        // Nothing could be parsed from there
+                        self.start();
+                        self.bump();
+                        self.finish(NodeKind::Error);
+                    }
+                }
+                self.finish(NodeKind::Print);
+            }
+            (Some(LeafKind::Id), Some(LeafKind::Op))
+                if self.nth(1).unwrap().text(self.source) == "=" =>
+            {
+                self.start();
+                self.bump();
+                self.bump();
+                self.literal(0);
+                self.finish(NodeKind::Assign);
+            }
+            (Some(LeafKind::Id), Some(LeafKind::Id)) if keyword == "solve" => {
+                self.start();
+                self.bump_keyword();
+                self.bump();
+                while self.peek() == Some(LeafKind::Comma) {
+                    self.bump();
+                    self.expect(LeafKind::Id);
+                }
+                self.finish(NodeKind::Solve);
+            }
+            (Some(LeafKind::Id), Some(LeafKind::Id))
+                if keyword == "const" || keyword == "override" =>
+            {
+                self.start();
+                self.bump_keyword();
+                self.bump();
+                self.expect_eq();
+                self.literal(0);
+                self.finish(NodeKind::Const);
+            }
+            _ => self.equation(NodeKind::Equation),
+        }
+    }
+
+    fn expect_eq(&mut self) {
+        if self.at(LeafKind::Op, "=") {
+            self.bump();
+        } else {
+            self.expect(LeafKind::Op);
+        }
+    }
+
+    /** A literal, or an equation if it is followed by '=' */
+    fn equation(&mut self, kind: NodeKind) {
+        let checkpoint = self.checkpoint();
+        self.literal(0);
+        if self.at(LeafKind::Op, "=") {
+            self.start_at(checkpoint);
+            self.bump();
+            self.literal(0);
+            self.finish(kind);
+        }
+    }
+
+    fn literal(&mut self, min_bp: u8) {
+        let checkpoint = self.checkpoint();
+        let text = self.peek_text();
+        match self.peek() {
+            Some(LeafKind::Nb) => {
+                self.start();
+                self.bump();
+                self.finish(NodeKind::Nb);
+                // A number directly followed by a variable or a block is multiplied with it: 2x
+                let implicit = matches!(self.peek(), Some(LeafKind::Id | LeafKind::Open));
+                if implicit && self.pos == self.next_index(self.pos) {
+                    self.start_at(checkpoint);
+                    self.literal(infix_binding_power("*"));
+                    self.finish(NodeKind::Binary);
+                }
+            }
+            Some(LeafKind::Str) => {
+                self.start();
+                self.bump();
+                self.finish(NodeKind::Str);
+            }
+            Some(LeafKind::Template) if text.starts_with('"') => {
+                self.start();
+                self.bump();
+                loop {
+                    self.literal(0);
+                    if self.peek() == Some(LeafKind::Spec) {
+                        self.bump();
+                    }
+                    if self.peek() != Some(LeafKind::Template) {
+                        // Missing interpolation end
+                        self.expect(LeafKind::Template);
+                        break;
+                    }
+                    let end = !self.peek_text().ends_with('{');
+                    self.bump();
+                    if end {
+                        break;
+                    }
+                }
+                self.finish(NodeKind::Template);
+            }
+            Some(LeafKind::Open) => {
+                self.start();
+                self.bump();
+                self.literal(0);
+                self.expect(LeafKind::Close);
+                self.finish(NodeKind::Paren);
+            }
+            Some(LeafKind::Id)
+                if text == "let" && self.nth(1).map(Leaf::kind) == Some(LeafKind::Id) =>
+            {
+                self.start();
+                self.bump_keyword();
+                self.bindings();
+                if self.at(LeafKind::Id, "in") {
+                    self.bump_keyword();
+                } else {
+                    self.missing();
+                }
+                self.literal(0);
+                self.finish(NodeKind::Let);
+            }
+            Some(LeafKind::Id) => {
+                self.start();
+                self.bump();
+                if self.peek() == Some(LeafKind::Open) {
+                    self.bump();
+                    self.equation(NodeKind::Equation);
+                    while self.peek() == Some(LeafKind::Comma) {
+                        self.bump();
+                        self.equation(NodeKind::Equation);
+                    }
+                    self.expect(LeafKind::Close);
+                    self.finish(NodeKind::Call);
+                } else {
+                    self.finish(NodeKind::Var);
+                }
+            }
+            Some(LeafKind::Op) if text == "+" || text == "-" => {
+                self.start();
+                self.bump();
+                self.literal(3);
+                self.finish(NodeKind::Unary);
+            }
+            // Unexpected tokens are skipped unless they may end the literal
+            Some(LeafKind::Op | LeafKind::Error) if text != "=" => {
+                self.start();
+                self.bump();
+                self.finish(NodeKind::Error);
+            }
+            _ => self.missing(),
+        }
+
+        while self.peek() == Some(LeafKind::Op) {
+            let bp = infix_binding_power(self.peek_text());
+            if bp <= min_bp {
+                break;
+            }
+            self.start_at(checkpoint);
+            self.bump();
+            self.literal(bp);
+            self.finish(NodeKind::Binary);
+        }
+
+        // a*b where a = 1, b = 2
+        if min_bp == 0 && self.at(LeafKind::Id, "where") {
+            self.start_at(checkpoint);
+            self.bump_keyword();
+            self.bindings();
+            self.finish(NodeKind::Where);
+        }
+    }
+
+    /** Local bindings, a comma continues them only if another binding follows */
+    fn bindings(&mut self) {
+        loop {
+            self.start();
+            self.expect(LeafKind::Id);
+            self.expect_eq();
+            self.literal(0);
+            self.finish(NodeKind::Binding);
+            let next = [self.nth(0), self.nth(1), self.nth(2)].map(|leaf| leaf.map(Leaf::kind));
+            let is_binding =
+                next == [
+                    Some(LeafKind::Comma),
+                    Some(LeafKind::Id),
+                    Some(LeafKind::Op),
+                ] && self.nth(2).unwrap().text(self.source) == "=";
+            if !is_binding {
+                return;
+            }
+            self.bump();
+        }
+    }
+}
+
+/** Binding power of an infix operator, 0 if it is not one */
+fn infix_binding_power(op: &str) -> u8 {
+    match op {
+        "+" | "-" => 1,
+        "*" | "/" | "%" => 2,
+        "^" => 3,
+        _ => 0,
+    }
+}
+
+/** Leaves of a statement starting at an offset of the source, whitespace included */
+fn statement_leaves(statement: &str, start: usize) -> Vec<Leaf> {
+    let mut leaves = Vec::new();
+    let mut offset = 0;
+    let whitespace = |leaves: &mut Vec<Leaf>, offset: usize, end: usize| {
+        if offset < end {
+            leaves.push(Leaf {
+                kind: LeafKind::Whitespace,
+                span: start + offset..start + end,
+            });
+        }
+    };
+    let mut lexer = Lexer::load(statement);
+    loop {
+        let token = lexer.next();
+        let span = token.span().clone();
+        whitespace(&mut leaves, offset, span.start);
+        let kind = match token.kind() {
+            TokenKind::Eof => break,
+            // A comment line is a single leaf
+            TokenKind::Sep(Sep::Comment) if leaves.iter().all(Leaf::is_trivia) => {
+                leaves.push(Leaf {
+                    kind: LeafKind::Comment,
+                    span: start + span.start..start + statement.len(),
+                });
+                return leaves;
+            }
+            TokenKind::Nb => LeafKind::Nb,
+            TokenKind::Id => LeafKind::Id,
+            TokenKind::Op(_) => LeafKind::Op,
+            TokenKind::Sep(Sep::Open) => LeafKind::Open,
+            TokenKind::Sep(Sep::Close) => LeafKind::Close,
+            TokenKind::Sep(Sep::Comma) => LeafKind::Comma,
+            TokenKind::Sep(Sep::Semi) => LeafKind::Semi,
+            TokenKind::Str => LeafKind::Str,
+            TokenKind::Template(_) => LeafKind::Template,
+            TokenKind::Spec => LeafKind::Spec,
+            TokenKind::Sep(Sep::Comment) | TokenKind::Err => LeafKind::Error,
+        };
+        leaves.push(Leaf {
+            kind,
+            span: start + span.start..start + span.end,
+        });
+        offset = span.end;
+    }
+    leaves
+}
+
+/** Parse a whole source into a lossless tree, whose root is a document of lines */
+pub fn parse_cst(source: &str) -> Node {
+    let mut builder = Builder {
+        source,
+        leaves: Vec::new(),
+        pos: 0,
+        offset: 0,
+        stack: vec![Vec::new()],
+    };
+    builder.start();
+    let mut start = 0;
+    for (statement, _) in statements(source) {
+        builder.leaves = statement_leaves(statement, start);
+        builder.pos = 0;
+        builder.start();
+        builder.line();
+        builder.finish(NodeKind::Line);
+
+        // The line break, with the carriage return trimmed from the statement
+        let end = start + statement.len();
+        start = source[end..]
+            .find('\n')
+            .map_or(source.len(), |i| end + i + 1);
+        if end < start {
+            builder.push(Element::Leaf(Leaf {
+                kind: LeafKind::Whitespace,
+                span: end..start,
+            }));
+        }
+    }
+    builder.finish(NodeKind::Document);
+    match builder.stack.pop().unwrap().pop() {
+        Some(Element::Node(mut document)) => {
+            document.span = 0..source.len();
+            document
+        }
+        _ => unreachable!("The document is the only root element"),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use proptest::prelude::*;
+
+    /** Kinds of the nodes in depth first order, with their text */
+    fn outline<'a>(node: &Node, source: &'a str, buf: &mut Vec<(NodeKind, &'a str)>) {
+        buf.push((node.kind(), node.text(source)));
+        for child in node.nodes() {
+            outline(child, source, buf);
+        }
+    }
+
+    /** Check that the leaves cover the whole source in order and nodes cover their children */
+    fn check_lossless(source: &str) -> Node {
+        let cst = parse_cst(source);
+        let mut offset = 0;
+        for leaf in cst.leaves() {
+            assert_eq!(leaf.span().start, offset, "{:?}", source);
+            offset = leaf.span().end;
+        }
+        assert_eq!(offset, source.len(), "{:?}", source);
+        let text: String = cst.leaves().iter().map(|leaf| leaf.text(source)).collect();
+        assert_eq!(text, source);
+        cst
+    }
+
+    #[test]
+    fn test_cst() {
+        let source = "# Area\nr = (2 )\n\narea = PI*r^2 where PI = 3  ; \"{area:.1}\"\r\n";
+        let cst = check_lossless(source);
+        let mut nodes = Vec::new();
+        outline(&cst, source, &mut nodes);
+        assert_eq!(
+            nodes,
+            [
+                (NodeKind::Document, source),
+                (NodeKind::Line, "# Area"),
+                (NodeKind::Line, "r = (2 )"),
+                (NodeKind::Assign, "r = (2 )"),
+                (NodeKind::Paren, "(2 )"),
+                (NodeKind::Nb, "2"),
+                (NodeKind::Line, ""),
+                (
+                    NodeKind::Line,
+                    "area = PI*r^2 where PI = 3  ; \"{area:.1}\""
+                ),
+                (NodeKind::Assign, "area = PI*r^2 where PI = 3"),
+                (NodeKind::Where, "PI*r^2 where PI = 3"),
+                (NodeKind::Binary, "PI*r^2"),
+                (NodeKind::Var, "PI"),
+                (NodeKind::Binary, "r^2"),
+                (NodeKind::Var, "r"),
+                (NodeKind::Nb, "2"),
+                (NodeKind::Binding, "PI = 3"),
+                (NodeKind::Nb, "3"),
+                (NodeKind::Print, "\"{area:.1}\""),
+                (NodeKind::Template, "\"{area:.1}\""),
+                (NodeKind::Var, "area"),
+            ]
+        );
+        let comment = cst.leaves()[0];
+        assert_eq!(comment.kind(), LeafKind::Comment);
+        assert_eq!(comment.text(source), "# Area");
+        let keywords: Vec<_> = cst
+            .leaves()
+            .into_iter()
+            .filter(|leaf| leaf.kind() == LeafKind::Keyword)
+            .map(|leaf| leaf.text(source))
+            .collect();
+        assert_eq!(keywords, ["where"]);
+        assert_eq!(cst.node_at(5).unwrap().kind(), NodeKind::Line);
+        assert_eq!(cst.node_at(12).unwrap().text(source), "2");
+    }
+
+    #[test]
+    fn test_cst_statements() {
+        let cases = [
+            ("const g = 9.81", NodeKind::Const),
+            ("override PI = 3", NodeKind::Const),
+            ("solve a, b", NodeKind::Solve),
+            ("a + b = 1", NodeKind::Equation),
+            ("\"a\" 1", NodeKind::Print),
+            ("let a = 1, b = 2 in a*b", NodeKind::Let),
+            ("2x", NodeKind::Binary),
+            ("-x", NodeKind::Unary),
+            ("solve(x^2 = 1, x, 0, 2)", NodeKind::Call),
+        ];
+        for (source, kind) in cases {
+            let cst = check_lossless(source);
+            let line = cst.nodes().next().unwrap();
+            let statement = line.nodes().next().unwrap();
+            assert_eq!(statement.kind(), kind, "{}", source);
+            assert_eq!(statement.text(source), source);
+        }
+        let cst = parse_cst("solve(x^2 = 1, x)");
+        let call = cst.node_at(8).unwrap();
+        assert_eq!(call.kind(), NodeKind::Nb);
+        assert!(parse_cst("f(a = 1)").node_at(2).unwrap().kind() == NodeKind::Var);
+    }
+
+    #[test]
+    fn test_cst_errors() {
+        let errors = |source: &str| {
+            let cst = check_lossless(source);
+            let mut nodes = Vec::new();
+            outline(&cst, source, &mut nodes);
+            nodes
+                .into_iter()
+                .filter(|(kind, _)| *kind == NodeKind::Error)
+                .map(|(_, text)| text.to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(errors("1 + * 2"), ["*", "2"]);
+        assert_eq!(errors("(1 + 2"), [""]);
+        assert_eq!(errors("a = 1 ) b"), [") b"]);
+        assert_eq!(errors("x # c"), ["# c"]);
+        assert_eq!(errors("\"{1\""), [""]);
+        assert!(errors("a = (1 +\n  2) * 3").is_empty());
+    }
+
+    proptest! {
+        #[test]
+        fn cst_anything(s: String) {
+            check_lossless(&s);
+        }
+
+        #[test]
+        fn cst_code(s in "[a-z0-9 ()+*^=,;\"{}:#\n-]{0,40}") {
+            check_lossless(&s);
+        }
+    }
+}
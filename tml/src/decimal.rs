@@ -0,0 +1,102 @@
+use rust_decimal::{
+    prelude::{FromPrimitive, ToPrimitive},
+    Decimal, RoundingStrategy,
+};
+use std::str::FromStr;
+
+/* Decimal arithmetic of the opt-in decimal mode. Numbers are 128-bit decimals with up to 28
+fractional digits, so decimal fractions like 0.1 are represented exactly. */
+
+/** Rule used to round a number to a given number of digits */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    #[default]
+    HalfEven, // Banker's rounding, 2.5 -> 2 and 3.5 -> 4
+    HalfUp,   // Half away from zero, 2.5 -> 3
+    HalfDown, // Half toward zero, 2.5 -> 2
+    Up,       // Away from zero
+    Down,     // Toward zero
+    Ceiling,  // Toward positive infinity
+    Floor,    // Toward negative infinity
+}
+
+impl Rounding {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        Ok(match name {
+            "half-even" => Rounding::HalfEven,
+            "half-up" => Rounding::HalfUp,
+            "half-down" => Rounding::HalfDown,
+            "up" => Rounding::Up,
+            "down" => Rounding::Down,
+            "ceiling" => Rounding::Ceiling,
+            "floor" => Rounding::Floor,
+            _ => {
+                return Err(format!(
+                    "Unknown rounding '{}', expected half-even, half-up, half-down, up, down, ceiling or floor",
+                    name
+                ))
+            }
+        })
+    }
+
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::HalfDown => RoundingStrategy::MidpointTowardZero,
+            Rounding::Up => RoundingStrategy::AwayFromZero,
+            Rounding::Down => RoundingStrategy::ToZero,
+            Rounding::Ceiling => RoundingStrategy::ToPositiveInfinity,
+            Rounding::Floor => RoundingStrategy::ToNegativeInfinity,
+        }
+    }
+}
+
+/** Convert a float to a decimal from its shortest representation, so 0.1 stays 0.1 */
+pub fn from_f64(nb: f64) -> Result<Decimal, String> {
+    if !nb.is_finite() {
+        return Err(format!("{} cannot be represented as a decimal", nb));
+    }
+    Decimal::from_str(&nb.to_string())
+        .or_else(|_| Decimal::from_f64(nb).ok_or(()))
+        .map_err(|_| format!("{} is out of the decimal range", nb))
+}
+
+/** Parse a number literal exactly, as written in the source */
+pub fn parse(text: &str) -> Result<Decimal, String> {
+    Decimal::from_str(text).map_err(|_| format!("{} is out of the decimal range", text))
+}
+
+pub fn to_f64(dec: Decimal) -> f64 {
+    dec.to_f64().unwrap_or(f64::NAN)
+}
+
+/** Round to a number of fractional digits, negative digits round to tens, hundreds... */
+pub fn round(dec: Decimal, digits: i64, rounding: Rounding) -> Result<Decimal, String> {
+    if digits >= 0 {
+        return Ok(dec.round_dp_with_strategy(digits.min(28) as u32, rounding.strategy()));
+    }
+    let scale = pow(Decimal::TEN, -digits).ok_or("Decimal overflow in round")?;
+    let rounded = (dec / scale).round_dp_with_strategy(0, rounding.strategy());
+    rounded
+        .checked_mul(scale)
+        .ok_or_else(|| "Decimal overflow in round".into())
+}
+
+/** Raise to an integer power by squaring, None on overflow */
+pub fn pow(base: Decimal, exp: i64) -> Option<Decimal> {
+    if exp < 0 {
+        return Decimal::ONE.checked_div(pow(base, exp.checked_neg()?)?);
+    }
+    let (mut result, mut base, mut exp) = (Decimal::ONE, base, exp);
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = result.checked_mul(base)?;
+        }
+        exp >>= 1;
+        if exp > 0 {
+            base = base.checked_mul(base)?;
+        }
+    }
+    Some(result)
+}
@@ -0,0 +1,303 @@
+use crate::{
+    interpreter::{self, statements, Context, Entry, Settings, EQUATIONS},
+    report::Error,
+};
+use std::collections::HashSet;
+
+/* Incremental evaluation of a whole document for the editor. Each statement caches its result
+with the worksheet names it read and the state of the names it wrote. On an edit, unchanged
+statements replay their writes and only the statements reading a changed name are computed
+again. */
+
+/** A statement of a document with its cached evaluation */
+#[derive(Debug)]
+pub struct Statement {
+    source: String,
+    lines: usize,
+    result: Result<String, Error>,
+    reads: Vec<String>,
+    writes: Vec<(String, Entry)>,
+}
+
+impl Statement {
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /** Number of lines spanned by the statement */
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    /** Result of the statement, the span of an error is relative to its source */
+    pub fn result(&self) -> Result<&str, &Error> {
+        self.result.as_deref()
+    }
+
+    /** Worksheet names read by the statement */
+    pub fn reads(&self) -> impl Iterator<Item = &str> {
+        self.reads
+            .iter()
+            .map(String::as_str)
+            .filter(|id| *id != EQUATIONS)
+    }
+
+    /** Worksheet names written by the statement */
+    pub fn writes(&self) -> impl Iterator<Item = &str> {
+        self.writes.iter().map(|(id, _)| id.as_str())
+    }
+
+    fn replay(&self, ctx: &mut Context) {
+        for (id, entry) in &self.writes {
+            ctx.restore(id, entry.clone());
+        }
+    }
+
+    fn is_affected(&self, dirty: &HashSet<String>) -> bool {
+        self.reads.iter().any(|id| dirty.contains(id))
+    }
+}
+
+/** Evaluate a statement, logging the names it accesses */
+fn evaluate(ctx: &mut Context, source: &str, lines: usize) -> Statement {
+    ctx.start_log();
+    let result = interpreter::evaluate(ctx, source).map(|outcome| outcome.text);
+    let log = ctx.take_log();
+    Statement {
+        source: source.into(),
+        lines,
+        result,
+        reads: log.reads,
+        writes: log
+            .writes
+            .into_iter()
+            .map(|id| {
+                let entry = ctx.entry(&id);
+                (id, entry)
+            })
+            .collect(),
+    }
+}
+
+/** State of a name after some statements, None if they do not write it */
+fn last_entry<'a>(statements: &'a [Statement], id: &str) -> Option<&'a Entry> {
+    statements
+        .iter()
+        .rev()
+        .find_map(|statement| statement.writes.iter().find(|(write, _)| write == id))
+        .map(|(_, entry)| entry)
+}
+
+/** A document evaluated incrementally, see `update` */
+#[derive(Debug, Default)]
+pub struct Document {
+    settings: Settings,
+    statements: Vec<Statement>,
+}
+
+impl Document {
+    pub fn new(settings: Settings) -> Self {
+        let mut document = Self::default();
+        document.set_settings(settings);
+        document
+    }
+
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    /** Change the settings, every statement is computed again on the next update. The
+    reactive mode is always off as the document is evaluated in order. */
+    pub fn set_settings(&mut self, settings: Settings) {
+        let settings = Settings {
+            reactive: false,
+            ..settings
+        };
+        if settings != self.settings {
+            self.settings = settings;
+            self.statements.clear();
+        }
+    }
+
+    pub fn statements(&self) -> &[Statement] {
+        &self.statements
+    }
+
+    /** Context holding the state before a statement, replaying the statements above it */
+    pub fn context_before(&self, index: usize) -> Context {
+        let mut ctx = Context::empty();
+        ctx.set_settings(self.settings);
+        for statement in &self.statements[..index.min(self.statements.len())] {
+            statement.replay(&mut ctx);
+        }
+        ctx
+    }
+
+    /** Update the document to a new source, returning the number of statements computed */
+    pub fn update(&mut self, source: &str) -> usize {
+        let new: Vec<_> = statements(source).collect();
+        let mut old = std::mem::take(&mut self.statements);
+
+        // Unchanged statements at the start and the end of the document
+        let prefix = old
+            .iter()
+            .zip(&new)
+            .take_while(|(old, (source, _))| old.source == *source)
+            .count();
+        let suffix = old[prefix..]
+            .iter()
+            .rev()
+            .zip(new[prefix..].iter().rev())
+            .take_while(|(old, (source, _))| old.source == *source)
+            .count();
+        let suffix = old.split_off(old.len() - suffix);
+        let start = new.len() - suffix.len();
+        let removed = old.split_off(prefix);
+
+        let mut ctx = Context::empty();
+        ctx.set_settings(self.settings);
+        for statement in &old {
+            statement.replay(&mut ctx);
+        }
+
+        let mut computed = 0;
+        let mut changed = Vec::new();
+        for (source, lines) in &new[prefix..start] {
+            changed.push(evaluate(&mut ctx, source, *lines));
+            computed += 1;
+        }
+
+        // Names whose state differs from the previous evaluation
+        let mut dirty = HashSet::new();
+        for id in removed.iter().chain(&changed).flat_map(Statement::writes) {
+            let previous = last_entry(&removed, id)
+                .or_else(|| last_entry(&old, id))
+                .unwrap_or(&Entry::Unset);
+            if *previous != ctx.entry(id) {
+                dirty.insert(id.to_string());
+            }
+        }
+        old.append(&mut changed);
+        for (previous, (_, lines)) in suffix.into_iter().zip(&new[start..]) {
+            if !previous.is_affected(&dirty) {
+                // The names written get back their previous state
+                previous.replay(&mut ctx);
+                for id in previous.writes() {
+                    dirty.remove(id);
+                }
+                old.push(Statement {
+                    lines: *lines,
+                    ..previous
+                });
+                continue;
+            }
+            let statement = evaluate(&mut ctx, &previous.source, *lines);
+            computed += 1;
+            // A name stays dirty only if its new state differs from the previous one
+            for id in previous.writes().chain(statement.writes()) {
+                let unchanged = previous
+                    .writes
+                    .iter()
+                    .any(|(write, entry)| write == id && *entry == ctx.entry(id));
+                if unchanged {
+                    dirty.remove(id);
+                } else {
+                    dirty.insert(id.into());
+                }
+            }
+            old.push(statement);
+        }
+        self.statements = old;
+        computed
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /** Results of a document evaluated from scratch */
+    fn full(source: &str) -> Vec<Result<String, String>> {
+        let mut document = Document::default();
+        document.update(source);
+        results(&document)
+    }
+
+    fn results(document: &Document) -> Vec<Result<String, String>> {
+        document
+            .statements()
+            .iter()
+            .map(|statement| {
+                statement
+                    .result()
+                    .map(String::from)
+                    .map_err(|err| err.message.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_update() {
+        let mut document = Document::default();
+        let source = "a = 1\nb = 2\nc = a + 1\nd = b * 2\nc + d";
+        assert_eq!(document.update(source), 5);
+        assert_eq!(document.update(source), 0);
+
+        // Only the statements reading a changed variable are computed
+        let edits = [
+            ("a = 5\nb = 2\nc = a + 1\nd = b * 2\nc + d", 3),
+            ("a = 5\nb = 3\nc = a + 1\nd = b * 2\nc + d", 3),
+            ("a = 5\nb = 3\nc = a + 1\nd = b * 2\nc + d\n1 + 1", 1),
+            ("a = 5\nb = 3\nc = 6\nd = b * 2\nc + d\n1 + 1", 1),
+            ("a = 5\nb = 3\nd = b * 2\nc + d\n1 + 1", 1),
+            ("a = 5\nb = 3\nd = b * 2\nd\nc + d\n1 + 1", 1),
+            ("b = 3\nd = b * 2\nd\nc + d\n1 + 1", 0),
+            ("# Comment\n\nb = 3\nd = (b +\n 1) * 2\nd\nc + d\n1 + 1", 5),
+        ];
+        for (source, computed) in edits {
+            assert_eq!(document.update(source), computed, "{}", source);
+            assert_eq!(results(&document), full(source), "{}", source);
+        }
+        let statement = &document.statements()[3];
+        assert_eq!(statement.lines(), 2);
+        assert_eq!(statement.reads().collect::<Vec<_>>(), ["d", "b"]);
+        assert_eq!(statement.writes().collect::<Vec<_>>(), ["d"]);
+    }
+
+    #[test]
+    fn test_update_declarations() {
+        let mut document = Document::default();
+        let edits = [
+            "x + y = 3\nx - y = 1\nsolve x, y\nx * 10",
+            "x + y = 5\nx - y = 1\nsolve x, y\nx * 10",
+            "x + y = 5\nsolve x, y\nx * 10",
+            "const k = 2\nk * 3\nf = k + 1",
+            "const k = 3\nk * 3\nf = k + 1",
+            "k = 3\nk * 3\nf = k + 1",
+            "k = 3\nk = 4\nk * 3\nf = k + 1",
+            "x = 1\nlet x = 2 in x\nx",
+            "x = 3\nlet x = 2 in x\nx",
+        ];
+        for source in edits {
+            document.update(source);
+            assert_eq!(results(&document), full(source), "{}", source);
+        }
+        assert_eq!(document.update("x = 3\nlet x = 2 in x\ny = x"), 1);
+    }
+
+    #[test]
+    fn test_settings() {
+        let mut settings = Settings {
+            reactive: true,
+            ..Settings::default()
+        };
+        let mut document = Document::new(settings);
+        assert!(!document.settings().reactive);
+        document.update("a = 1/3");
+        assert_eq!(document.update("a = 1/3"), 0);
+        settings.set("digits", "3").unwrap();
+        document.set_settings(settings);
+        assert_eq!(document.update("a = 1/3"), 1);
+        assert_eq!(document.statements()[0].result(), Ok("a = 0.333"));
+    }
+}
@@ -0,0 +1,406 @@
+use crate::{
+    decimal,
+    interpreter::{Decimal, Value},
+};
+
+/* Format specs of interpolated expressions, inspired by the Python format mini-language:
+`[[fill]align][0][width][,][.precision][type]` with align in `<`, `>`, `^` and type in `f`, `e`, `%` */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Align {
+    Left,   // <
+    Right,  // >
+    Center, // ^
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Notation {
+    Default,
+    Fixed,      // f
+    Scientific, // e
+    Percent,    // %
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Spec {
+    fill: char,
+    align: Option<Align>,
+    zero: bool,
+    width: usize,
+    thousands: bool,
+    precision: Option<usize>,
+    notation: Notation,
+}
+
+fn align(c: char) -> Option<Align> {
+    match c {
+        '<' => Some(Align::Left),
+        '>' => Some(Align::Right),
+        '^' => Some(Align::Center),
+        _ => None,
+    }
+}
+
+/** Parse an unsigned integer at the start of a str */
+fn digits(str: &str) -> (Option<usize>, &str) {
+    let end = str.find(|c: char| !c.is_ascii_digit()).unwrap_or(str.len());
+    (str[..end].parse().ok(), &str[end..])
+}
+
+impl Spec {
+    /** Parse a spec, without its leading ':' */
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let err = || format!("Invalid format spec ':{}'", spec);
+        let mut fmt = Spec {
+            fill: ' ',
+            align: None,
+            zero: false,
+            width: 0,
+            thousands: false,
+            precision: None,
+            notation: Notation::Default,
+        };
+        let mut chars = spec.chars();
+        let mut rest = spec;
+        match (chars.next(), chars.next()) {
+            (Some(fill), Some(c)) if align(c).is_some() => {
+                fmt.fill = fill;
+                fmt.align = align(c);
+                rest = chars.as_str();
+            }
+            (Some(c), _) if align(c).is_some() => {
+                fmt.align = align(c);
+                rest = &spec[1..];
+            }
+            _ => {}
+        }
+        // A leading zero pads numbers with zeros after their sign
+        if let (None, Some(tail)) = (fmt.align, rest.strip_prefix('0')) {
+            fmt.zero = true;
+            rest = tail;
+        }
+        let (width, tail) = digits(rest);
+        fmt.width = width.unwrap_or(0);
+        rest = tail;
+        if let Some(tail) = rest.strip_prefix(',') {
+            fmt.thousands = true;
+            rest = tail;
+        }
+        if let Some(tail) = rest.strip_prefix('.') {
+            let (precision, tail) = digits(tail);
+            fmt.precision = Some(precision.ok_or_else(err)?);
+            rest = tail;
+        }
+        fmt.notation = match rest {
+            "" => Notation::Default,
+            "f" => Notation::Fixed,
+            "e" => Notation::Scientific,
+            "%" => Notation::Percent,
+            _ => return Err(err()),
+        };
+        Ok(fmt)
+    }
+
+    /** Format a value following the spec */
+    pub fn format(&self, value: &Value) -> String {
+        match value {
+            Value::Nb(_) | Value::Dec(_) if self.zero => {
+                let nb = self.format_value(value);
+                let (sign, abs) = nb.split_at(if nb.starts_with('-') { 1 } else { 0 });
+                let missing = self.width.saturating_sub(nb.chars().count());
+                format!("{}{}{}", sign, "0".repeat(missing), abs)
+            }
+            Value::Nb(_) | Value::Dec(_) => self.pad(&self.format_value(value), Align::Right),
+            Value::Str(str) => self.pad(str, Align::Left),
+        }
+    }
+
+    /** Format a number, decimals keep their exact digits unless a notation needs floats */
+    fn format_value(&self, value: &Value) -> String {
+        match (value, self.notation) {
+            (Value::Dec(dec), Notation::Default | Notation::Fixed) => {
+                let formatted = match self.precision {
+                    Some(precision) => format!("{:.*}", precision, dec.round_dp(precision as u32)),
+                    None => dec.normalize().to_string(),
+                };
+                if self.thousands {
+                    group_thousands(&formatted, ',')
+                } else {
+                    formatted
+                }
+            }
+            (Value::Dec(dec), _) => self.format_nb(decimal::to_f64(*dec)),
+            (Value::Nb(nb), _) => self.format_nb(*nb),
+            (Value::Str(str), _) => str.clone(),
+        }
+    }
+
+    fn format_nb(&self, nb: f64) -> String {
+        if !nb.is_finite() {
+            return nb.to_string();
+        }
+        let formatted = match (self.notation, self.precision) {
+            (Notation::Scientific, Some(precision)) => format!("{:.*e}", precision, nb),
+            (Notation::Scientific, None) => format!("{:e}", nb),
+            (Notation::Percent, Some(precision)) => format!("{:.*}%", precision, nb * 100.),
+            (Notation::Percent, None) => format!("{}%", nb * 100.),
+            (_, Some(precision)) => format!("{:.*}", precision, nb),
+            (_, None) => nb.to_string(),
+        };
+        if self.thousands && self.notation != Notation::Scientific {
+            group_thousands(&formatted, ',')
+        } else {
+            formatted
+        }
+    }
+
+    fn pad(&self, str: &str, default: Align) -> String {
+        let len = str.chars().count();
+        if len >= self.width {
+            return str.to_string();
+        }
+        let missing = self.width - len;
+        let (before, after) = match self.align.unwrap_or(default) {
+            Align::Left => (0, missing),
+            Align::Right => (missing, 0),
+            Align::Center => (missing / 2, missing - missing / 2),
+        };
+        let fill = |n| std::iter::repeat_n(self.fill, n);
+        fill(before).chain(str.chars()).chain(fill(after)).collect()
+    }
+}
+
+/** Insert a separator between each group of three digits of the integer part */
+pub fn group_thousands(nb: &str, separator: char) -> String {
+    let start = nb.find(|c: char| c.is_ascii_digit()).unwrap_or(nb.len());
+    let end = nb[start..]
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(nb.len(), |i| i + start);
+    let mut buf = String::from(&nb[..start]);
+    for (i, c) in nb[start..end].chars().enumerate() {
+        if i > 0 && (end - start - i) % 3 == 0 {
+            buf.push(separator);
+        }
+        buf.push(c);
+    }
+    buf.push_str(&nb[end..]);
+    buf
+}
+
+/** How numbers are rounded */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Precision {
+    Shortest,        // Shortest representation that round-trips
+    Significant(u8), // Maximum number of significant digits
+    Decimals(u8),    // Fixed number of decimals
+}
+
+/** How numbers are written */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NumberNotation {
+    Plain,       // 1234.5
+    Scientific,  // 1.2345e3
+    Engineering, // 1.2345e3 with an exponent multiple of 3
+    Si,          // 1.2345k
+}
+
+/** Formatting of the numbers of results */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NumberFormat {
+    pub precision: Precision,
+    pub notation: NumberNotation,
+    pub grouping: Option<char>,
+    // Round to 15 significant digits to hide float noise: 0.1+0.2 = 0.3
+    pub trim: bool,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self {
+            precision: Precision::Shortest,
+            notation: NumberNotation::Plain,
+            grouping: None,
+            trim: false,
+        }
+    }
+}
+
+const SI_PREFIXES: [(i32, &str); 12] = [
+    (-18, "a"),
+    (-15, "f"),
+    (-12, "p"),
+    (-9, "n"),
+    (-6, "µ"),
+    (-3, "m"),
+    (3, "k"),
+    (6, "M"),
+    (9, "G"),
+    (12, "T"),
+    (15, "P"),
+    (18, "E"),
+];
+
+/** Place a decimal point after the n first digits, padding with zeros */
+fn place_point(digits: &str, point: usize) -> String {
+    if digits.len() <= point {
+        format!("{}{}", digits, "0".repeat(point - digits.len()))
+    } else {
+        format!("{}.{}", &digits[..point], &digits[point..])
+    }
+}
+
+impl NumberFormat {
+    /** Set an option from its name and value, as given by the REPL or the CLI */
+    pub fn set(&mut self, option: &str, value: &str) -> Result<(), String> {
+        let count = || match value.parse::<u8>() {
+            Ok(nb) if nb <= 17 => Ok(nb),
+            _ => Err(format!("Expected a count from 0 to 17, got '{}'", value)),
+        };
+        let switch = || match value {
+            "on" => Ok(true),
+            "off" => Ok(false),
+            _ => Err(format!("Expected 'on' or 'off', got '{}'", value)),
+        };
+        match option {
+            "digits" => {
+                self.precision = match value {
+                    "auto" => Precision::Shortest,
+                    _ => match count()? {
+                        0 => return Err("Expected at least 1 significant digit".into()),
+                        nb => Precision::Significant(nb),
+                    },
+                }
+            }
+            "decimals" => self.precision = Precision::Decimals(count()?),
+            "notation" => {
+                self.notation = match value {
+                    "plain" => NumberNotation::Plain,
+                    "sci" => NumberNotation::Scientific,
+                    "eng" => NumberNotation::Engineering,
+                    "si" => NumberNotation::Si,
+                    _ => {
+                        return Err(format!(
+                            "Expected 'plain', 'sci', 'eng' or 'si', got '{}'",
+                            value
+                        ))
+                    }
+                }
+            }
+            "group" => {
+                let mut chars = value.chars();
+                self.grouping = match (value, chars.next(), chars.next()) {
+                    ("off", _, _) => None,
+                    ("on", _, _) => Some(','),
+                    (_, Some(c), None) => Some(c),
+                    _ => {
+                        return Err(format!(
+                            "Expected 'on', 'off' or a separator, got '{}'",
+                            value
+                        ))
+                    }
+                }
+            }
+            "trim" => self.trim = switch()?,
+            _ => {
+                return Err(format!(
+                    "Unknown number format option '{}', expected digits, decimals, notation, \
+                    group or trim",
+                    option
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /** Format a number */
+    pub fn format(&self, nb: f64) -> String {
+        if !nb.is_finite() {
+            return nb.to_string();
+        }
+        let nb = if self.trim {
+            format!("{:.14e}", nb).parse().unwrap_or(nb)
+        } else {
+            nb
+        };
+        let formatted = match self.notation {
+            NumberNotation::Plain => match self.precision {
+                Precision::Shortest => nb.to_string(),
+                Precision::Significant(digits) => format!("{:.*e}", digits as usize - 1, nb)
+                    .parse::<f64>()
+                    .unwrap_or(nb)
+                    .to_string(),
+                Precision::Decimals(decimals) => format!("{:.*}", decimals as usize, nb),
+            },
+            NumberNotation::Scientific => match self.precision {
+                Precision::Shortest => format!("{:e}", nb),
+                Precision::Significant(digits) => {
+                    let (mantissa, exp) = self.mantissa(nb, digits);
+                    format!("{}e{}", mantissa, exp)
+                }
+                Precision::Decimals(decimals) => format!("{:.*e}", decimals as usize, nb),
+            },
+            NumberNotation::Engineering | NumberNotation::Si => {
+                let (mantissa, exp) = self.engineering(nb);
+                match SI_PREFIXES.iter().find(|(e, _)| *e == exp) {
+                    Some((_, prefix)) if self.notation == NumberNotation::Si => {
+                        format!("{}{}", mantissa, prefix)
+                    }
+                    _ if exp == 0 => mantissa,
+                    _ => format!("{}e{}", mantissa, exp),
+                }
+            }
+        };
+        match self.grouping {
+            Some(separator) if self.notation == NumberNotation::Plain => {
+                group_thousands(&formatted, separator)
+            }
+            _ => formatted,
+        }
+    }
+
+    /** Format a decimal, keeping its exact digits in plain notation */
+    pub fn format_decimal(&self, dec: Decimal) -> String {
+        let formatted = match (self.notation, self.precision) {
+            (NumberNotation::Plain, Precision::Shortest) => dec.normalize().to_string(),
+            (NumberNotation::Plain, Precision::Decimals(decimals)) => {
+                format!("{:.*}", decimals as usize, dec.round_dp(decimals as u32))
+            }
+            _ => return self.format(decimal::to_f64(dec)),
+        };
+        match self.grouping {
+            Some(separator) => group_thousands(&formatted, separator),
+            None => formatted,
+        }
+    }
+
+    /** Round to significant digits, returning the shortest mantissa and the exponent */
+    fn mantissa(&self, nb: f64, digits: u8) -> (f64, i32) {
+        let sci = format!("{:.*e}", digits as usize - 1, nb);
+        let (mantissa, exp) = sci.split_once('e').unwrap();
+        (mantissa.parse().unwrap(), exp.parse().unwrap())
+    }
+
+    /** Write a number with an exponent multiple of three, returning the mantissa and the exponent */
+    fn engineering(&self, nb: f64) -> (String, i32) {
+        let sci = match self.precision {
+            Precision::Significant(digits) => format!("{:.*e}", digits as usize - 1, nb),
+            _ => format!("{:e}", nb),
+        };
+        let (mantissa, exp) = sci.split_once('e').unwrap();
+        let exp: i32 = exp.parse().unwrap();
+        let eng = if nb == 0. { 0 } else { exp.div_euclid(3) * 3 };
+        let mantissa = match self.precision {
+            Precision::Decimals(decimals) => {
+                format!("{:.*}", decimals as usize, nb / 10f64.powi(eng))
+            }
+            _ => {
+                let (sign, mantissa) = mantissa.split_at(if nb < 0. { 1 } else { 0 });
+                let digits = mantissa.replace('.', "");
+                let digits = digits.trim_end_matches('0');
+                let digits = if digits.is_empty() { "0" } else { digits };
+                format!("{}{}", sign, place_point(digits, (exp - eng) as usize + 1))
+            }
+        };
+        (mantissa, eng)
+    }
+}
@@ -0,0 +1,280 @@
+use crate::{
+    cst::{parse_cst, Element, LeafKind, Node, NodeKind},
+    interpreter::statements,
+    lexer::Lexer,
+    parser::{parse, Expression, Line},
+};
+
+/* Source formatter. Statements are printed back from their syntax tree, which spaces the
+operators by precedence and only keeps the required parentheses. The '=' of consecutive
+assignments are aligned. Comments, blank lines and the lines that cannot be parsed are kept as
+is. Statements spanning multiple lines keep their layout, which is deliberate: their tokens are
+respaced from the concrete syntax tree but their line breaks and indentation are kept. */
+
+/** A formatted line */
+enum Formatted {
+    Line(String),
+    // A single assignment, aligned with its neighbors
+    Assign(String, String),
+}
+
+/** Check a formatted statement parses to the same expressions, never changing its meaning */
+fn checked(formatted: Formatted, exprs: &[Expression]) -> Option<Formatted> {
+    let code = match &formatted {
+        Formatted::Line(line) => line.clone(),
+        Formatted::Assign(target, value) => format!("{} = {}", target, value),
+    };
+    let same = match parse(Lexer::load(&code)) {
+        Ok(Line::Exprs(reparsed)) => reparsed == exprs,
+        _ => false,
+    };
+    same.then_some(formatted)
+}
+
+/** Parse the expressions of a statement, None for a comment, an empty line or invalid code */
+fn expressions(statement: &str) -> Option<Vec<Expression<'_>>> {
+    match parse(Lexer::load(statement)).ok()? {
+        Line::Exprs(exprs) => Some(exprs),
+        Line::Comment(_) | Line::Empty => None,
+    }
+}
+
+/** Format a statement, None if it must be kept as is */
+fn format_statement(statement: &str) -> Option<Formatted> {
+    let exprs = expressions(statement)?;
+    let formatted = match exprs.as_slice() {
+        [Expression::Assign(id, lit)] => Formatted::Assign(id.to_string(), lit.to_string()),
+        [Expression::Const(id, lit)] => Formatted::Assign(format!("const {}", id), lit.to_string()),
+        [Expression::Override(id, lit)] => {
+            Formatted::Assign(format!("override {}", id), lit.to_string())
+        }
+        exprs => Formatted::Line(
+            exprs
+                .iter()
+                .map(Expression::to_string)
+                .collect::<Vec<_>>()
+                .join("; "),
+        ),
+    };
+    checked(formatted, &exprs)
+}
+
+/** A token of a statement with the kind of the node it belongs to */
+struct Token<'a> {
+    kind: LeafKind,
+    text: &'a str,
+    parent: NodeKind,
+    // Whitespace before the token
+    gap: &'a str,
+}
+
+/** List the tokens of a node, whitespace excluded */
+fn tokens<'a>(node: &Node, source: &'a str, end: &mut usize, tokens: &mut Vec<Token<'a>>) {
+    for child in node.children() {
+        match child {
+            Element::Node(child) => self::tokens(child, source, end, tokens),
+            Element::Leaf(leaf) if leaf.kind() == LeafKind::Whitespace => {}
+            Element::Leaf(leaf) => {
+                tokens.push(Token {
+                    kind: leaf.kind(),
+                    text: leaf.text(source),
+                    parent: node.kind(),
+                    gap: &source[*end..leaf.span().start],
+                });
+                *end = leaf.span().end;
+            }
+        }
+    }
+}
+
+/** Space between two tokens on a line, as a statement is printed from its syntax tree */
+fn space(prev: &Token, next: &Token) -> &'static str {
+    let is_infix = |token: &Token| token.kind == LeafKind::Op && token.parent != NodeKind::Unary;
+    let spaced = |token: &Token| {
+        if matches!(token.text, "+" | "-" | "=") {
+            " "
+        } else {
+            ""
+        }
+    };
+    match (prev.kind, next.kind) {
+        _ if is_infix(next) => spaced(next),
+        _ if is_infix(prev) => spaced(prev),
+        (LeafKind::Op, _) => "",
+        (LeafKind::Open, _) => "",
+        (_, LeafKind::Close | LeafKind::Comma | LeafKind::Semi | LeafKind::Spec) => "",
+        (LeafKind::Keyword, _) | (_, LeafKind::Keyword) => " ",
+        (LeafKind::Id, LeafKind::Open) if next.parent == NodeKind::Call => "",
+        // 2x is an implicit multiplication
+        (LeafKind::Nb, LeafKind::Id | LeafKind::Open) if next.gap.is_empty() => "",
+        (LeafKind::Template, _) if prev.text.ends_with('{') => "",
+        (_, LeafKind::Template) if next.text.starts_with('}') => "",
+        _ => " ",
+    }
+}
+
+/** Print tokens back, keeping the line breaks and the indentation of the lines they start */
+fn join(tokens: &[Token]) -> String {
+    let mut buf = String::new();
+    for (i, token) in tokens.iter().enumerate() {
+        match token.gap.rfind('\n') {
+            Some(last) if i > 0 => {
+                buf.extend(token.gap.matches('\n'));
+                buf.push_str(&token.gap[last + 1..]);
+            }
+            _ if i > 0 => buf.push_str(space(&tokens[i - 1], token)),
+            _ => {}
+        }
+        buf.push_str(token.text);
+    }
+    buf
+}
+
+/** Format a statement spanning multiple lines, None if it must be kept as is */
+fn format_lines(statement: &str) -> Option<Formatted> {
+    let exprs = expressions(statement)?;
+    let mut list = Vec::new();
+    tokens(&parse_cst(statement), statement, &mut 0, &mut list);
+    let eq = list
+        .iter()
+        .position(|token| token.text == "=" && token.parent != NodeKind::Equation);
+    let formatted = match (exprs.as_slice(), eq) {
+        // The '=' of a single assignment is aligned, unless the value starts on the next line
+        ([Expression::Assign(..) | Expression::Const(..) | Expression::Override(..)], Some(eq))
+            if !list[eq + 1].gap.contains('\n') =>
+        {
+            Formatted::Assign(join(&list[..eq]), join(&list[eq + 1..]))
+        }
+        _ => Formatted::Line(join(&list)),
+    };
+    checked(formatted, &exprs)
+}
+
+/** Format a source */
+pub fn format_source(source: &str) -> String {
+    let mut lines = Vec::new();
+    for (statement, nb_lines) in statements(source) {
+        let formatted = match nb_lines {
+            1 => format_statement(statement.trim()),
+            _ => format_lines(statement),
+        };
+        lines.push(formatted.unwrap_or_else(|| {
+            let kept: Vec<_> = statement.lines().map(str::trim_end).collect();
+            Formatted::Line(kept.join("\n"))
+        }));
+    }
+
+    let mut buf = String::new();
+    let mut i = 0;
+    while i < lines.len() {
+        // Consecutive assignments share the column of their '='
+        let group = lines[i..]
+            .iter()
+            .take_while(|line| matches!(line, Formatted::Assign(..)))
+            .count()
+            .max(1);
+        let width = lines[i..i + group]
+            .iter()
+            .map(|line| match line {
+                Formatted::Assign(target, _) => target.chars().count(),
+                Formatted::Line(_) => 0,
+            })
+            .max()
+            .unwrap_or(0);
+        for line in &lines[i..i + group] {
+            match line {
+                Formatted::Line(line) => buf.push_str(line),
+                Formatted::Assign(target, value) => {
+                    buf.push_str(&format!("{:<width$} = {}", target, value, width = width))
+                }
+            }
+            buf.push('\n');
+        }
+        i += group;
+    }
+    if !source.ends_with('\n') {
+        buf.pop();
+    }
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::{compute, Context};
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_format() {
+        let cases = [
+            ("1+2*3", "1 + 2*3"),
+            ("((1+2))*3", "(1 + 2)*3"),
+            ("a-(b-c)", "a - (b - c)"),
+            ("(a-b)-c", "a - b - c"),
+            ("2^(3^2)", "2^(3^2)"),
+            ("-(x^2)", "-(x^2)"),
+            ("(-x)^2", "(-x)^2"),
+            ("sqrt( (x) )", "sqrt(x)"),
+            ("y=(x)*2", "y = x*2"),
+            ("const  g=9.81", "const g = 9.81"),
+            ("a+b=3;a-b=1 ;solve a,b", "a + b = 3; a - b = 1; solve a, b"),
+            ("\"total: {a+b:.2}\"  (1)", "\"total: {a + b:.2}\" 1"),
+            (
+                "area = w*h where w=3,h=(4)",
+                "area = w*h where w = 3, h = 4",
+            ),
+            ("let r=2 in PI*r^2", "let r = 2 in PI*r^2"),
+            // Numbers are written as in the source
+            ("x = 1.50 + 0.0", "x = 1.50 + 0.0"),
+            ("1234567890123456.78+0.010", "1234567890123456.78 + 0.010"),
+            // Statements spanning multiple lines keep their line breaks
+            ("f = sqrt( 2 +  \n    x )", "f = sqrt(2 +\n    x)"),
+            ("total=(a+\n  b) *2x", "total = (a +\n  b)*2x"),
+            ("a+b=(3 -\n1)", "a + b = (3 -\n1)"),
+            ("y =\n  2+3", "y =\n  2 + 3"),
+            (
+                "s = (let a=1, b=2 in\n\n a+b)",
+                "s = (let a = 1, b = 2 in\n\n a + b)",
+            ),
+            ("#  comment  ", "#  comment"),
+            ("1 + * 2  ", "1 + * 2"),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(format_source(source), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_format_document() {
+        let source = "# Box\nwidth=3\nheight = 4 \nconst density=(2)\n\n\
+            area=width*height\nvolume =area*(height+\n  1)\nmass = volume*density\n";
+        let expected = "# Box\nwidth         = 3\nheight        = 4\nconst density = 2\n\n\
+            area   = width*height\nvolume = area*(height +\n  1)\nmass   = volume*density\n";
+        let formatted = format_source(source);
+        assert_eq!(formatted, expected);
+        assert_eq!(format_source(&formatted), formatted);
+
+        // The formatted document computes the same results
+        let results = |source: &str| {
+            let mut ctx = Context::empty();
+            statements(source)
+                .map(|(statement, _)| compute(&mut ctx, statement))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(results(&formatted), results(source));
+    }
+
+    proptest! {
+        #[test]
+        fn format_anything(s: String) {
+            let formatted = format_source(&s);
+            prop_assert_eq!(format_source(&formatted), formatted);
+        }
+
+        #[test]
+        fn format_code(s in "[a-z0-9 ()+*^=,;\"{}:#\n-]{0,40}") {
+            let formatted = format_source(&s);
+            prop_assert_eq!(format_source(&formatted), formatted);
+        }
+    }
+}
@@ -1,6 +1,10 @@
 use crate::lexer::{Lexer, Sep, TokenKind};
-use std::fmt::{self, Write};
+use std::{
+    fmt::{self, Write},
+    ops::Range,
+};
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SpanKind {
     Nb,
     Op,
@@ -17,26 +21,24 @@ pub trait Highlighter {
 
 pub struct HtmlHighlighter;
 
-fn span_kind_to_name(kind: SpanKind) -> &'static str {
-    match kind {
-        SpanKind::Nb => "number",
-        SpanKind::Op => "operator",
-        SpanKind::Fun => "function",
-        SpanKind::Var => "variable",
-        SpanKind::Str => "string",
-        SpanKind::Comment => "comment",
-        SpanKind::Error => "error",
+impl SpanKind {
+    /** Name of the kind, used as HTML class */
+    pub fn name(self) -> &'static str {
+        match self {
+            SpanKind::Nb => "number",
+            SpanKind::Op => "operator",
+            SpanKind::Fun => "function",
+            SpanKind::Var => "variable",
+            SpanKind::Str => "string",
+            SpanKind::Comment => "comment",
+            SpanKind::Error => "error",
+        }
     }
 }
 
 impl Highlighter for HtmlHighlighter {
     fn span(&mut self, mut writer: impl Write, kind: SpanKind, span: &str) -> fmt::Result {
-        write!(
-            writer,
-            "<span class=\"{}\">{}</span>",
-            span_kind_to_name(kind),
-            span
-        )
+        write!(writer, "<span class=\"{}\">{}</span>", kind.name(), span)
     }
 }
 pub struct AnsiHighlighter;
@@ -59,58 +61,59 @@ impl Highlighter for AnsiHighlighter {
     }
 }
 
+/** Classify the tokens of a line, other chars like separators are left unstyled */
+pub fn spans(code: &str) -> Vec<(SpanKind, Range<usize>)> {
+    let mut lexer = Lexer::load(code);
+    if lexer.peek().kind() == TokenKind::Sep(Sep::Comment) {
+        return vec![(SpanKind::Comment, 0..code.len())];
+    }
+    let mut spans = Vec::new();
+    loop {
+        let token = lexer.next();
+        let kind = match token.kind() {
+            TokenKind::Nb => SpanKind::Nb,
+            TokenKind::Op(_) => SpanKind::Op,
+            TokenKind::Id if lexer.peek().kind() == TokenKind::Sep(Sep::Open) => SpanKind::Fun,
+            TokenKind::Id => SpanKind::Var,
+            TokenKind::Str | TokenKind::Template(_) | TokenKind::Spec => SpanKind::Str,
+            TokenKind::Sep(_) | TokenKind::Err => continue,
+            TokenKind::Eof => return spans,
+        };
+        spans.push((kind, token.span().clone()));
+    }
+}
+
 /** Generate styled HTML */
 pub fn highlight(
     mut writer: impl Write,
     code: &str,
     mut highlighter: impl Highlighter,
 ) -> fmt::Result {
-    let mut lexer = Lexer::load(code);
-    let peek = lexer.peek();
-    if peek.kind() == TokenKind::Sep(Sep::Comment) {
-        highlighter.span(&mut writer, SpanKind::Comment, code)
-    } else {
-        let mut c = 0;
-        loop {
-            let token = lexer.next();
-            let span = token.span();
-            if c < span.start {
-                writer.write_str(&code[c..span.start])?;
-            }
-            c = span.end;
-            match token.kind() {
-                TokenKind::Nb => highlighter
-                    .span(&mut writer, SpanKind::Nb, token.splice())
-                    .unwrap(),
-                TokenKind::Op(_) => highlighter
-                    .span(&mut writer, SpanKind::Op, token.splice())
-                    .unwrap(),
-                TokenKind::Id => {
-                    if lexer.peek().kind() == TokenKind::Sep(Sep::Open) {
-                        highlighter
-                            .span(&mut writer, SpanKind::Fun, token.splice())
-                            .unwrap()
-                    } else {
-                        highlighter
-                            .span(&mut writer, SpanKind::Var, token.splice())
-                            .unwrap()
-                    }
-                }
-                TokenKind::Str => highlighter
-                    .span(&mut writer, SpanKind::Str, token.splice())
-                    .unwrap(),
-                TokenKind::Sep(_) => writer.write_str(token.splice())?,
-                TokenKind::Err => writer.write_str(token.splice())?,
-                TokenKind::Eof => return Ok(()),
-            }
-        }
+    let mut c = 0;
+    for (kind, span) in spans(code) {
+        writer.write_str(&code[c..span.start])?;
+        highlighter.span(&mut writer, kind, &code[span.clone()])?;
+        c = span.end;
     }
+    writer.write_str(&code[c..])
 }
+
 #[cfg(test)]
 mod test {
     use crate::highlighter::{highlight, AnsiHighlighter, HtmlHighlighter};
     use proptest::prelude::*;
 
+    #[test]
+    fn highlight_interpolation() {
+        let mut buf = String::new();
+        highlight(&mut buf, "\"cost {total:.2} EUR\"", HtmlHighlighter).unwrap();
+        assert_eq!(
+            buf,
+            "<span class=\"string\">\"cost {</span><span class=\"variable\">total</span>\
+            <span class=\"string\">:.2</span><span class=\"string\">} EUR\"</span>"
+        );
+    }
+
     proptest! {
         #[test]
         fn highlight_anything(s: String) {
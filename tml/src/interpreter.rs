@@ -1,126 +1,1131 @@
 use crate::{
-    lexer::Lexer,
-    parser::{parse, BinOp, Expression, Line, Literal, Print, UnOp},
+    decimal::{self, Rounding},
+    format::{NumberFormat, Spec},
+    lexer::{Lexer, Sep, TokenKind},
+    linear, numeric, optimizer,
+    parser::{BinOp, Expression, Fragment, Line, Literal, ParsedLine, Statement, UnOp},
+    reactive::Graph,
+    report::Error,
+    symbolic,
 };
-use std::{collections::HashMap, fmt::Write};
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{self, Write},
+    ops::Range,
+    rc::Rc,
+};
+
+pub use rust_decimal::Decimal;
+
+/** A computed value */
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Nb(f64),
+    Dec(Decimal),
+    Str(String),
+}
+
+impl Value {
+    /** Numeric value as a float, None for strings */
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Nb(nb) => Some(*nb),
+            Value::Dec(dec) => Some(decimal::to_f64(*dec)),
+            Value::Str(_) => None,
+        }
+    }
+
+    /** Format the value as code, quoting strings and escaping their braces */
+    pub fn to_code(&self) -> String {
+        match self {
+            Value::Nb(nb) => nb.to_string(),
+            Value::Dec(dec) => dec.normalize().to_string(),
+            Value::Str(str) => format!("\"{}\"", str.replace('{', "{{").replace('}', "}}")),
+        }
+    }
+
+    /** Format the value as code, numbers following a number format */
+    pub fn to_code_with(&self, format: &NumberFormat) -> String {
+        match self {
+            Value::Nb(nb) => format.format(*nb),
+            Value::Dec(dec) => format.format_decimal(*dec),
+            value => value.to_code(),
+        }
+    }
+
+    /** Format the value as text, numbers following a number format */
+    pub fn to_text_with(&self, format: &NumberFormat) -> String {
+        match self {
+            Value::Nb(nb) => format.format(*nb),
+            Value::Dec(dec) => format.format_decimal(*dec),
+            Value::Str(str) => str.clone(),
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Nb(nb) => write!(f, "{}", nb),
+            Value::Dec(dec) => write!(f, "{}", dec.normalize()),
+            Value::Str(str) => f.write_str(str),
+        }
+    }
+}
+
+/** Evaluation settings, set by name from the REPL, the CLI or the wasm binding */
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Settings {
+    pub format: NumberFormat,
+    // Compute with exact decimals instead of floats
+    pub decimal: bool,
+    // Default rule of round(x, digits)
+    pub rounding: Rounding,
+    // Recompute the variables depending on an assigned variable
+    pub reactive: bool,
+}
+
+impl Settings {
+    /** Set an option from its name and value */
+    pub fn set(&mut self, option: &str, value: &str) -> Result<(), String> {
+        match option {
+            "decimal" => {
+                self.decimal = match value {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("Expected 'on' or 'off', got '{}'", value)),
+                }
+            }
+            "rounding" => self.rounding = Rounding::parse(value)?,
+            "reactive" => {
+                self.reactive = match value {
+                    "on" => true,
+                    "off" => false,
+                    _ => return Err(format!("Expected 'on' or 'off', got '{}'", value)),
+                }
+            }
+            "digits" | "decimals" | "notation" | "group" | "trim" => {
+                self.format.set(option, value)?
+            }
+            _ => {
+                return Err(format!(
+                    "Unknown option '{}', expected digits, decimals, notation, group, trim, \
+                    decimal, rounding or reactive",
+                    option
+                ))
+            }
+        }
+        Ok(())
+    }
+}
+
+/** Pseudo name of the pending equations in logs */
+pub(crate) const EQUATIONS: &str = "=";
+
+/** Names read and written by the statements computed while logging */
+#[derive(Debug, Default)]
+pub(crate) struct Log {
+    pub reads: Vec<String>,
+    pub writes: Vec<String>,
+}
+
+/** Worksheet state of a name, replayed by documents instead of computing a statement again */
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Entry {
+    Unset,
+    Var(Value),
+    Const(Value),
+    Equations(Vec<Statement>),
+}
 
 /** Execution context */
 pub struct Context {
-    variables: HashMap<String, f64>,
+    // Chain of scopes, from the worksheet variables to the innermost local bindings
+    scopes: Vec<HashMap<String, Value>>,
+    // User constants and overridden builtin constants
+    constants: HashMap<String, Value>,
+    // Formulas of the variables assigned in reactive mode
+    graph: Graph,
+    // Worksheet names accessed, when logging
+    log: RefCell<Option<Log>>,
+    // Equations waiting for a solve statement
+    equations: Vec<Statement>,
+    // Line computed, to locate the names of its errors
+    line: Option<Rc<ParsedLine>>,
+    settings: Settings,
 }
 
 impl Context {
     pub fn empty() -> Self {
         Self {
-            variables: HashMap::new(),
+            scopes: vec![HashMap::new()],
+            constants: HashMap::new(),
+            graph: Graph::default(),
+            log: RefCell::new(None),
+            equations: Vec::new(),
+            line: None,
+            settings: Settings::default(),
+        }
+    }
+
+    /** Span of a name in the line computed, None for a name of another line */
+    fn span(&self, name: &str) -> Option<Range<usize>> {
+        self.line.as_ref()?.span(name)
+    }
+
+    /** Error located on a name of the line computed */
+    fn error_at(&self, message: impl Into<String>, name: &str) -> Error {
+        Error::from(message.into()).or_at(self.span(name))
+    }
+
+    pub fn number_format(&self) -> NumberFormat {
+        self.settings.format
+    }
+
+    pub fn set_number_format(&mut self, format: NumberFormat) {
+        self.settings.format = format;
+    }
+
+    /** Number format and evaluation options */
+    pub fn settings(&self) -> Settings {
+        self.settings
+    }
+
+    pub fn set_settings(&mut self, settings: Settings) {
+        self.settings = settings;
+    }
+
+    /** Wrap a computed number, as a decimal in decimal mode */
+    pub fn number(&self, nb: f64) -> Result<Value, String> {
+        if self.settings.decimal {
+            decimal::from_f64(nb).map(Value::Dec)
+        } else {
+            Ok(Value::Nb(nb))
+        }
+    }
+
+    /** Start logging the worksheet names read and written */
+    pub(crate) fn start_log(&self) {
+        *self.log.borrow_mut() = Some(Log::default());
+    }
+
+    pub(crate) fn take_log(&self) -> Log {
+        self.log.borrow_mut().take().unwrap_or_default()
+    }
+
+    fn log_read(&self, id: &str) {
+        if let Some(log) = self.log.borrow_mut().as_mut() {
+            if !log.reads.iter().any(|read| read == id) {
+                log.reads.push(id.into());
+            }
+        }
+    }
+
+    fn log_write(&self, id: &str) {
+        if let Some(log) = self.log.borrow_mut().as_mut() {
+            if !log.writes.iter().any(|write| write == id) {
+                log.writes.push(id.into());
+            }
+        }
+    }
+
+    /** Current worksheet state of a name */
+    pub(crate) fn entry(&self, id: &str) -> Entry {
+        if id == EQUATIONS {
+            Entry::Equations(self.equations.clone())
+        } else if let Some(value) = self.constants.get(id) {
+            Entry::Const(value.clone())
+        } else if let Some(value) = self.scopes[0].get(id) {
+            Entry::Var(value.clone())
+        } else {
+            Entry::Unset
+        }
+    }
+
+    /** Restore the worksheet state of a name */
+    pub(crate) fn restore(&mut self, id: &str, entry: Entry) {
+        self.scopes[0].remove(id);
+        self.constants.remove(id);
+        match entry {
+            Entry::Unset => {}
+            Entry::Var(value) => self.assign(id.into(), value),
+            Entry::Const(value) => self.set_constant(id, value),
+            Entry::Equations(equations) => self.equations = equations,
+        }
+    }
+
+    /** Define or redefine a constant, replacing the variable of the same name */
+    fn set_constant(&mut self, id: &str, value: Value) {
+        self.log_write(id);
+        self.scopes[0].remove(id);
+        self.graph.remove(id);
+        self.constants.insert(id.into(), value);
+    }
+
+    /** Take the pending equations */
+    fn take_equations(&mut self) -> Vec<Statement> {
+        self.log_read(EQUATIONS);
+        if !self.equations.is_empty() {
+            self.log_write(EQUATIONS);
+        }
+        std::mem::take(&mut self.equations)
+    }
+
+    fn push_equation(&mut self, equation: Statement) {
+        self.log_read(EQUATIONS);
+        self.log_write(EQUATIONS);
+        self.equations.push(equation);
+    }
+
+    /** Value of a constant, user defined or builtin */
+    pub(crate) fn constant(&self, id: &str) -> Option<Value> {
+        self.log_read(id);
+        match self.constants.get(id) {
+            Some(value) => Some(value.clone()),
+            None => constant(id).map(Value::Nb),
+        }
+    }
+
+    fn is_constant(&self, id: &str) -> bool {
+        self.log_read(id);
+        self.constants.contains_key(id) || constant(id).is_some()
+    }
+
+    /** List the builtin constants then the user constants, with their current value */
+    pub fn constants(&self) -> Vec<(String, Value)> {
+        let mut user: Vec<_> = self
+            .constants
+            .iter()
+            .filter(|(id, _)| constant(id).is_none())
+            .map(|(id, value)| (id.clone(), value.clone()))
+            .collect();
+        user.sort_by(|a, b| a.0.cmp(&b.0));
+        CONSTANTS
+            .iter()
+            .map(|(id, _)| (id.to_string(), self.constant(id).unwrap()))
+            .chain(user)
+            .collect()
+    }
+
+    /** Value of a worksheet variable */
+    pub fn variable(&self, id: &str) -> Option<&Value> {
+        self.scopes[0].get(id)
+    }
+
+    /** List the worksheet variables sorted by name */
+    pub fn variables(&self) -> Vec<(String, Value)> {
+        let mut variables: Vec<_> = self.scopes[0]
+            .iter()
+            .map(|(id, value)| (id.clone(), value.clone()))
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
+    }
+
+    /** Set a worksheet variable like an assignment, in reactive mode its dependents are
+    computed again */
+    pub fn set_variable(&mut self, id: &str, value: Value) -> Result<(), String> {
+        let mut lexer = Lexer::load(id);
+        if lexer.next().kind() != TokenKind::Id || lexer.next().kind() != TokenKind::Eof {
+            return Err(format!("'{}' is not a valid variable name", id));
+        }
+        self.check_assign(id)?;
+        self.graph.remove(id);
+        self.assign(id.into(), value);
+        if self.settings.reactive {
+            compute_downstream(self, &[id])?;
+        }
+        Ok(())
+    }
+
+    /** Unknown of a builtin like solve or integrate, which unlike `variable` also rejects the
+    worksheet constants and locates the error on the argument */
+    fn unknown<'a>(&self, lit: &Literal<'a>) -> Result<&'a str, Error> {
+        let span = match lit {
+            Literal::Var(id) => self.span(id),
+            _ => None,
+        };
+        let id = variable(lit).map_err(|err| Error::from(err).or_at(span.clone()))?;
+        if self.is_constant(id) {
+            let err = format!("Cannot use the constant '{}' as a variable", id);
+            return Err(Error::from(err).or_at(span));
+        }
+        Ok(id)
+    }
+
+    /** Check that a name can be assigned, constants and builtins being protected */
+    fn check_assign(&self, id: &str) -> Result<(), String> {
+        if self.is_constant(id) {
+            Err(format!(
+                "Cannot assign the constant '{}', use 'override {} = ...' to redefine it",
+                id, id
+            ))
+        } else if is_builtin(id) {
+            Err(format!("Cannot assign the builtin function '{}'", id))
+        } else {
+            Ok(())
+        }
+    }
+
+    /** Assign a worksheet variable */
+    fn assign(&mut self, id: String, value: Value) {
+        self.log_write(&id);
+        self.scopes[0].insert(id, value);
+    }
+
+    /** Bind a variable in the innermost scope */
+    fn bind(&mut self, id: &str, value: Value) {
+        self.scopes.last_mut().unwrap().insert(id.into(), value);
+    }
+
+    /** Search a variable from the innermost scope */
+    pub(crate) fn get(&self, id: &str) -> Option<&Value> {
+        let local = self.scopes[1..]
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(id));
+        if local.is_none() {
+            self.log_read(id);
+        }
+        local.or_else(|| self.scopes[0].get(id))
+    }
+
+    /** Run a computation in a new scope, dropped at the end */
+    fn with_scope<T>(&mut self, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.scopes.push(HashMap::new());
+        let result = f(self);
+        self.scopes.pop();
+        result
+    }
+
+    /** Run a computation with a variable temporarily bound to a value */
+    pub(crate) fn with_var<T>(&mut self, id: &str, nb: f64, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.with_scope(|ctx| {
+            ctx.bind(id, Value::Nb(nb));
+            f(ctx)
+        })
+    }
+}
+
+type Function = fn(f64) -> f64;
+
+/** Builtin functions taking a single argument */
+pub(crate) const FUNCTIONS: [(&str, Function); 16] = [
+    ("floor", f64::floor),
+    ("ceil", f64::ceil),
+    ("round", f64::round),
+    ("trunc", f64::trunc),
+    ("fract", f64::fract),
+    ("sqrt", f64::sqrt),
+    ("exp", f64::exp),
+    ("ln", f64::ln),
+    ("log2", f64::log2),
+    ("log10", f64::log10),
+    ("cos", f64::cos),
+    ("sin", f64::sin),
+    ("tan", f64::tan),
+    ("acos", f64::acos),
+    ("asin", f64::asin),
+    ("atan", f64::atan),
+];
+
+/** Builtin constants */
+pub(crate) const CONSTANTS: [(&str, f64); 2] =
+    [("PI", std::f64::consts::PI), ("E", std::f64::consts::E)];
+
+pub(crate) fn function(name: &str) -> Option<Function> {
+    FUNCTIONS.iter().find(|(n, _)| *n == name).map(|(_, f)| *f)
+}
+
+pub(crate) fn constant(name: &str) -> Option<f64> {
+    CONSTANTS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, nb)| *nb)
+}
+
+/** Functions with special evaluation rules, not in the builtin table */
+pub(crate) const SPECIAL_FUNCTIONS: [&str; 9] = [
+    "diff",
+    "integrate",
+    "solve",
+    "len",
+    "upper",
+    "lower",
+    "str",
+    "num",
+    "substr",
+];
+
+/** Builtins taking the name of their variable as second argument */
+pub(crate) const BINDING_FUNCTIONS: [&str; 3] = ["diff", "integrate", "solve"];
+
+/** Check if a name is a builtin function */
+pub(crate) fn is_builtin(name: &str) -> bool {
+    function(name).is_some() || SPECIAL_FUNCTIONS.contains(&name)
+}
+
+/** Syntactically extract the variable a builtin like diff operates on, only rejecting the
+builtin constants since no context is available when differentiating symbolically */
+pub(crate) fn variable<'a>(lit: &Literal<'a>) -> Result<&'a str, String> {
+    match lit {
+        Literal::Var(id) if constant(id).is_some() => {
+            Err(format!("Cannot use the constant '{}' as a variable", id))
+        }
+        Literal::Var(id) => Ok(id),
+        lit => Err(format!("Expected a variable, got '{}'", lit)),
+    }
+}
+
+/** Check if a statement continues on the next line, because its parentheses are unbalanced
+or it ends with an operator */
+pub fn is_incomplete(statement: &str) -> bool {
+    let mut lexer = Lexer::load(statement);
+    if lexer.peek().kind() == TokenKind::Sep(Sep::Comment) {
+        return false;
+    }
+    let (mut depth, mut last) = (0usize, TokenKind::Eof);
+    loop {
+        let kind = lexer.next().kind();
+        match kind {
+            TokenKind::Eof => break,
+            TokenKind::Sep(Sep::Open) => depth += 1,
+            TokenKind::Sep(Sep::Close) => depth = depth.saturating_sub(1),
+            _ => {}
         }
+        last = kind;
     }
+    depth > 0 || matches!(last, TokenKind::Op(_))
+}
+
+/** Iterator over the statements of a source, see `statements` */
+pub struct Statements<'a> {
+    source: &'a str,
+    offset: usize,
+}
+
+impl<'a> Iterator for Statements<'a> {
+    type Item = (&'a str, usize);
 
-    fn assign(&mut self, id: String, nb: f64) {
-        self.variables.insert(id, nb);
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.source.len() {
+            return None;
+        }
+        let start = self.offset;
+        let mut lines = 0;
+        loop {
+            let end = self.source[self.offset..]
+                .find('\n')
+                .map_or(self.source.len(), |i| self.offset + i);
+            lines += 1;
+            self.offset = end + 1;
+            let statement = &self.source[start..end];
+            if self.offset >= self.source.len() || !is_incomplete(statement) {
+                return Some((statement.trim_end_matches('\r'), lines));
+            }
+        }
     }
+}
+
+/** Split a source into statements with their number of lines, a statement spanning multiple
+lines while it is incomplete */
+pub fn statements(source: &str) -> Statements<'_> {
+    Statements { source, offset: 0 }
+}
 
-    fn get(&self, id: &str) -> Option<&f64> {
-        self.variables.get(id)
+/** Kind of the result of a line, given by its last statement */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Empty,
+    Comment,
+    Value,
+    Expression, // Derivative displayed as an expression
+    Roots,      // Every root found in an interval
+    Assignment,
+    Print,
+    Equation,
+    Solution,
+}
+
+impl Kind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Kind::Empty => "empty",
+            Kind::Comment => "comment",
+            Kind::Value => "value",
+            Kind::Expression => "expression",
+            Kind::Roots => "roots",
+            Kind::Assignment => "assignment",
+            Kind::Print => "print",
+            Kind::Equation => "equation",
+            Kind::Solution => "solution",
+        }
     }
 }
 
-/** Compute a line, returning a formatted result */
+/** Typed result of a line */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Outcome {
+    pub kind: Kind,
+    // Value computed or assigned, if any
+    pub value: Option<Value>,
+    // Formatted result, as returned by compute
+    pub text: String,
+}
+
+/** Compute a line, returning the formatted result of its last statement */
 pub fn compute(ctx: &mut Context, input: &str) -> Result<String, String> {
-    let lexer = Lexer::load(input);
-    let result = match parse(lexer)? {
-        Line::Expr(expr) => match expr {
-            Expression::Assign(id, lit) => {
-                let nb = compute_literal(ctx, &lit)?;
-                ctx.assign(id.into(), nb);
-                format!("{} = {}", id, nb)
+    evaluate(ctx, input)
+        .map(|outcome| outcome.text)
+        .map_err(|err| err.to_text(input))
+}
+
+/** Compute a line, returning the typed result of its last statement */
+pub fn evaluate(ctx: &mut Context, input: &str) -> Result<Outcome, Error> {
+    let line = Rc::new(ParsedLine::parse(input)?);
+    ctx.line = Some(line.clone());
+    let kind = match line.borrow_dependent() {
+        Line::Exprs(_) => Kind::Value,
+        Line::Empty => Kind::Empty,
+        Line::Comment(_) => Kind::Comment,
+    };
+    let mut outcome = Outcome {
+        kind,
+        value: None,
+        text: String::new(),
+    };
+    for statement in Statement::all(line) {
+        // Where the value ends up
+        let (kind, id) = match statement.expression() {
+            Expression::Assign(id, _) | Expression::Const(id, _) | Expression::Override(id, _) => {
+                (Kind::Assignment, Some(*id))
             }
-            Expression::Literal(lit) => {
-                let nb = compute_literal(ctx, &lit)?;
-                ctx.assign("$".to_string(), nb);
-                nb.to_string()
+            Expression::Literal(Literal::Fun("diff", args)) if args.len() == 2 => {
+                (Kind::Expression, None)
             }
-            Expression::Print(print) => compute_print(ctx, &print)?,
-        },
-        Line::Empty | Line::Comment(_) => "".into(),
+            Expression::Literal(Literal::Fun("solve", args)) if args.len() == 4 => {
+                (Kind::Roots, None)
+            }
+            Expression::Literal(_) => (Kind::Value, Some("$")),
+            Expression::Print(_) => (Kind::Print, None),
+            Expression::Equation(..) => (Kind::Equation, None),
+            Expression::Solve(_) => (Kind::Solution, None),
+        };
+        let text = compute_expr(ctx, &statement)?;
+        let value = match kind {
+            Kind::Print => Some(Value::Str(text[1..text.len() - 1].into())),
+            _ => id.and_then(|id| {
+                (ctx.scopes[0].get(id).cloned()).or_else(|| ctx.constants.get(id).cloned())
+            }),
+        };
+        outcome = Outcome { kind, value, text };
+    }
+    Ok(outcome)
+}
+
+/** Compute a statement, returning a formatted result */
+fn compute_expr(ctx: &mut Context, statement: &Statement) -> Result<String, Error> {
+    let expr = statement.expression();
+    if !matches!(expr, Expression::Equation(..) | Expression::Solve(_)) {
+        if let Some(equation) = ctx.take_equations().pop() {
+            return Err(format!(
+                "Expected 'solve' after the equation '{}'",
+                equation.expression()
+            )
+            .into());
+        }
+    }
+    let result = match expr {
+        Expression::Assign(id, lit) if ctx.settings.reactive => {
+            ctx.check_assign(id).map_err(|err| ctx.error_at(err, id))?;
+            compute_reactive(ctx, statement, id, lit)?
+        }
+        Expression::Assign(id, lit) => {
+            ctx.check_assign(id).map_err(|err| ctx.error_at(err, id))?;
+            let value = compute_value(ctx, lit)?;
+            let result = format!("{} = {}", id, value.to_code_with(&ctx.settings.format));
+            ctx.assign(id.to_string(), value);
+            ctx.graph.remove(id);
+            result
+        }
+        Expression::Const(id, lit) => {
+            if ctx.is_constant(id) {
+                let err = format!(
+                    "Constant '{}' is already defined, use 'override {} = ...' to redefine it",
+                    id, id
+                );
+                return Err(ctx.error_at(err, id));
+            }
+            if is_builtin(id) {
+                let err = format!("Cannot assign the builtin function '{}'", id);
+                return Err(ctx.error_at(err, id));
+            }
+            let value = compute_value(ctx, lit)?;
+            let result = format!("{} = {}", id, value.to_code_with(&ctx.settings.format));
+            ctx.set_constant(id, value);
+            result
+        }
+        Expression::Override(id, lit) => {
+            if !ctx.is_constant(id) {
+                let err = format!(
+                    "'{}' is not a constant, declare it with 'const {} = ...'",
+                    id, id
+                );
+                return Err(ctx.error_at(err, id));
+            }
+            let value = compute_value(ctx, lit)?;
+            let mut result = format!("{} = {}", id, value.to_code_with(&ctx.settings.format));
+            ctx.set_constant(id, value);
+            if ctx.settings.reactive {
+                result.push_str(&compute_downstream(ctx, &[id])?);
+            }
+            result
+        }
+        // A derivative without evaluation point is displayed as an expression
+        Expression::Literal(Literal::Fun("diff", args)) if args.len() == 2 => {
+            symbolic::diff(&args[0], ctx.unknown(&args[1])?)?.to_string()
+        }
+        // Every root found in an interval is displayed
+        Expression::Literal(Literal::Fun("solve", args)) if args.len() == 4 => {
+            let roots = compute_solve(ctx, &args[0], &args[1], &args[2..])?;
+            if roots.is_empty() {
+                return Err("No root found in the interval".into());
+            }
+            let roots: Vec<_> = roots
+                .iter()
+                .map(|nb| ctx.settings.format.format(*nb))
+                .collect();
+            format!("{} = {}", args[1], roots.join(", "))
+        }
+        // The error estimate of an integral is displayed with its value
+        Expression::Literal(Literal::Fun("integrate", args)) if args.len() == 4 => {
+            let integral = compute_integral(ctx, &args[0], &args[1], &args[2], &args[3])?;
+            let value = ctx.number(integral.value)?;
+            let error = match integral.error {
+                0. => "0".into(),
+                error => format!("{:.1e}", error),
+            };
+            let result = format!("{} ± {}", value.to_code_with(&ctx.settings.format), error);
+            ctx.assign("$".to_string(), value);
+            result
+        }
+        Expression::Literal(lit) => {
+            let value = compute_value(ctx, lit)?;
+            let result = value.to_code_with(&ctx.settings.format);
+            ctx.assign("$".to_string(), value);
+            result
+        }
+        Expression::Print(print) => compute_print(ctx, print)?,
+        // The equation waits for the solve statement following it
+        Expression::Equation(lhs, rhs) => {
+            let mut names = symbolic::free_variables(lhs);
+            names.extend(symbolic::free_variables(rhs));
+            if names.iter().all(|id| ctx.is_constant(id)) {
+                return Err(format!("Equation '{}' has no unknown", expr).into());
+            }
+            ctx.push_equation(statement.clone());
+            expr.to_string()
+        }
+        Expression::Solve(unknowns) => compute_system(ctx, unknowns)?,
     };
     Ok(result)
 }
 
-/** Compute a print expression, concatenate raw string with literal expression result */
-fn compute_print(ctx: &mut Context, print: &[Print]) -> Result<String, String> {
-    let mut buf = String::from("\"");
-    for item in print {
-        match item {
-            Print::Literal(lit) => write!(buf, "{}", compute_literal(ctx, &lit)?).unwrap(),
-            Print::Str(str) => buf.push_str(&str),
+/** Solve the pending equations for the unknowns and assign the solutions */
+fn compute_system(ctx: &mut Context, unknowns: &[&str]) -> Result<String, Error> {
+    let statements = ctx.take_equations();
+    if statements.is_empty() {
+        return Err("No equation to solve, write one equation per line above".into());
+    }
+    for (i, x) in unknowns.iter().enumerate() {
+        if ctx.is_constant(x) {
+            return Err(format!("Cannot use the constant '{}' as an unknown", x).into());
+        }
+        if unknowns[..i].contains(x) {
+            return Err(format!("Unknown '{}' is repeated", x).into());
+        }
+    }
+    let equations: Vec<_> = statements
+        .iter()
+        .filter_map(|statement| match statement.expression() {
+            Expression::Equation(lhs, rhs) => Some((lhs.clone(), rhs.clone())),
+            _ => None,
+        })
+        .collect();
+    let solution = linear::solve_system(ctx, &equations, unknowns)?;
+    let mut buf = String::new();
+    for (x, nb) in unknowns.iter().zip(solution) {
+        let value = ctx.number(nb)?;
+        ctx.assign(x.to_string(), value);
+        ctx.graph.remove(x);
+        if !buf.is_empty() {
+            buf.push_str(", ");
+        }
+        write!(buf, "{} = {}", x, ctx.settings.format.format(nb)).unwrap();
+    }
+    if ctx.settings.reactive {
+        buf.push_str(&compute_downstream(ctx, unknowns)?);
+    }
+    Ok(buf)
+}
+
+/** Assign a variable from a formula and recompute the variables depending on it */
+fn compute_reactive(
+    ctx: &mut Context,
+    assignment: &Statement,
+    id: &str,
+    lit: &Literal,
+) -> Result<String, Error> {
+    let deps: Vec<String> = symbolic::free_variables(lit)
+        .into_iter()
+        .map(String::from)
+        .collect();
+    // A formula reading its own variable updates its previous value once: x = x + 1
+    let is_update = deps.iter().any(|dep| dep == id);
+    if !is_update {
+        ctx.graph.check_cycle(id, &deps)?;
+    }
+    let value = compute_value(ctx, lit)?;
+    let mut result = format!("{} = {}", id, value.to_code_with(&ctx.settings.format));
+    ctx.assign(id.into(), value);
+    if deps.is_empty() || is_update {
+        ctx.graph.remove(id);
+    } else {
+        ctx.graph.set(id, assignment.clone(), deps);
+    }
+    result.push_str(&compute_downstream(ctx, &[id])?);
+    Ok(result)
+}
+
+/** Recompute the variables depending on some variables, returning their new values */
+fn compute_downstream(ctx: &mut Context, ids: &[&str]) -> Result<String, Error> {
+    let mut buf = String::new();
+    for dependent in ctx.graph.downstream(ids) {
+        let assignment = match ctx.graph.assignment(&dependent) {
+            Some(assignment) => assignment.clone(),
+            None => continue,
+        };
+        if let Expression::Assign(_, lit) = assignment.expression() {
+            let value = compute_value(ctx, lit)
+                .map_err(|err| format!("Cannot update '{}': {}", dependent, err.message))?;
+            write!(
+                buf,
+                ", {} = {}",
+                dependent,
+                value.to_code_with(&ctx.settings.format)
+            )
+            .unwrap();
+            ctx.assign(dependent, value);
         }
     }
+    Ok(buf)
+}
+
+/** Compute a print expression, concatenate the values of its parts */
+fn compute_print(ctx: &mut Context, print: &[Literal]) -> Result<String, Error> {
+    let mut buf = String::from("\"");
+    for lit in print {
+        let value = compute_value(ctx, lit)?;
+        buf.push_str(&value.to_text_with(&ctx.settings.format));
+    }
     buf.push('\"');
     Ok(buf)
 }
 
+/** Compute a literal expression that must be a number */
+pub(crate) fn compute_literal(ctx: &mut Context, lit: &Literal) -> Result<f64, Error> {
+    match compute_value(ctx, lit)? {
+        Value::Nb(nb) => Ok(nb),
+        Value::Dec(dec) => Ok(decimal::to_f64(dec)),
+        value => Err(format!("Expected a number, got {}", value.to_code()).into()),
+    }
+}
+
+/** Compute a literal expression that must be a string */
+fn compute_str(ctx: &mut Context, lit: &Literal) -> Result<String, Error> {
+    match compute_value(ctx, lit)? {
+        Value::Str(str) => Ok(str),
+        value => Err(format!("Expected a string, got {}", value.to_code()).into()),
+    }
+}
+
+/** Compute a literal expression that must be a positive integer */
+fn compute_index(ctx: &mut Context, lit: &Literal) -> Result<usize, Error> {
+    let nb = compute_literal(ctx, lit)?;
+    if nb < 0. || nb.fract() != 0. || !nb.is_finite() {
+        return Err(format!("Expected a positive integer, got {}", nb).into());
+    }
+    Ok(nb as usize)
+}
+
 /** Compute a literal expression, perform calculation */
-fn compute_literal(ctx: &mut Context, lit: &Literal) -> Result<f64, String> {
+pub(crate) fn compute_value(ctx: &mut Context, lit: &Literal) -> Result<Value, Error> {
     Ok(match lit {
-        Literal::Nb(nb) => *nb,
-        Literal::UnaryOp(op, lit) => {
-            let nb = compute_literal(ctx, lit)?;
-            match op {
-                UnOp::Add => nb,
-                UnOp::Sub => -nb,
+        // A literal is exact in decimal mode, without rounding to the nearest float
+        Literal::Nb(_, Some(text)) if ctx.settings.decimal => Value::Dec(decimal::parse(text)?),
+        Literal::Nb(nb, _) => ctx.number(*nb)?,
+        Literal::Str(str) => Value::Str(unescape(str)),
+        Literal::Template(fragments) => {
+            let mut buf = String::new();
+            for fragment in fragments {
+                match fragment {
+                    Fragment::Str(str) => buf.push_str(&unescape(str)),
+                    Fragment::Expr(lit, spec) => {
+                        let value = compute_value(ctx, lit)?;
+                        match spec {
+                            Some(spec) => buf.push_str(&Spec::parse(spec)?.format(&value)),
+                            None => buf.push_str(&value.to_text_with(&ctx.settings.format)),
+                        }
+                    }
+                }
             }
+            Value::Str(buf)
         }
+        Literal::UnaryOp(op, lit) => match (op, compute_value(ctx, lit)?) {
+            (UnOp::Add, Value::Nb(nb)) => Value::Nb(nb),
+            (UnOp::Sub, Value::Nb(nb)) => Value::Nb(-nb),
+            (UnOp::Add, Value::Dec(dec)) => Value::Dec(dec),
+            (UnOp::Sub, Value::Dec(dec)) => Value::Dec(-dec),
+            (_, value) => return Err(format!("Expected a number, got {}", value.to_code()).into()),
+        },
         Literal::BinaryOp(op, lits) => {
-            let (l, r) = (
-                compute_literal(ctx, &lits.0)?,
-                compute_literal(ctx, &lits.1)?,
-            );
-            match op {
-                BinOp::Add => l + r,
-                BinOp::Sub => l - r,
-                BinOp::Mul => l * r,
-                BinOp::Div => l / r,
-                BinOp::Mod => l % r,
-                BinOp::Pow => l.powf(r),
+            let (l, r) = (compute_value(ctx, &lits.0)?, compute_value(ctx, &lits.1)?);
+            match (op, l, r) {
+                (op, Value::Nb(l), Value::Nb(r)) => Value::Nb(match op {
+                    BinOp::Add => l + r,
+                    BinOp::Sub => l - r,
+                    BinOp::Mul => l * r,
+                    BinOp::Div => l / r,
+                    BinOp::Mod => l % r,
+                    BinOp::Pow => l.powf(r),
+                }),
+                // A decimal operand makes the operation decimal
+                (op, Value::Dec(l), Value::Nb(r)) => {
+                    compute_decimal(*op, l, decimal::from_f64(r)?)?
+                }
+                (op, Value::Nb(l), Value::Dec(r)) => {
+                    compute_decimal(*op, decimal::from_f64(l)?, r)?
+                }
+                (op, Value::Dec(l), Value::Dec(r)) => compute_decimal(*op, l, r)?,
+                (BinOp::Add, Value::Str(l), Value::Str(r)) => Value::Str(l + &r),
+                (BinOp::Add, l, r) => {
+                    return Err(format!(
+                        "Cannot add {} and {}, convert with str() or num()",
+                        l.to_code(),
+                        r.to_code()
+                    )
+                    .into())
+                }
+                (_, l, r) => {
+                    return Err(format!(
+                        "Expected numbers, got {} and {}",
+                        l.to_code(),
+                        r.to_code()
+                    )
+                    .into())
+                }
+            }
+        }
+        // An error is located on the function name unless an operand located it
+        Literal::Fun(name, args) => {
+            compute_fun(ctx, name, args).map_err(|err| err.or_at(ctx.span(name)))?
+        }
+        Literal::Equation(_) => return Err("An equation can only be used in 'solve'".into()),
+        Literal::Let(bindings, body, _) => ctx.with_scope(|ctx| {
+            for (id, lit) in bindings {
+                if ctx.is_constant(id) {
+                    let err = format!("Cannot bind the constant '{}'", id);
+                    return Err(ctx.error_at(err, id));
+                }
+                let value = compute_value(ctx, lit)?;
+                ctx.bind(id, value);
+            }
+            compute_value(ctx, body)
+        })?,
+        Literal::Var(id) => match ctx.constant(id) {
+            Some(Value::Nb(nb)) => ctx.number(nb)?,
+            Some(value) => value,
+            None => match ctx.get(id) {
+                Some(value) => value.clone(),
+                None => return Err(ctx.error_at(format!("Unknown variable '{}'", id), id)),
+            },
+        },
+    })
+}
+
+/** Compute a function invocation, either a builtin or a numerical method */
+fn compute_fun(ctx: &mut Context, name: &str, args: &[Literal]) -> Result<Value, Error> {
+    let nb = match (name, args) {
+        ("round", [lit, digits, rest @ ..]) if rest.len() <= 1 => {
+            let value = compute_value(ctx, lit)?;
+            let digits = compute_literal(ctx, digits)?;
+            if digits.fract() != 0. || digits.abs() > 28. {
+                return Err(
+                    format!("Expected a number of digits from -28 to 28, got {}", digits).into(),
+                );
             }
+            let rounding = match rest {
+                [rule] => Rounding::parse(&compute_str(ctx, rule)?)?,
+                _ => ctx.settings.rounding,
+            };
+            // Floats are rounded from their shortest representation, 2.675 is 2.675
+            return Ok(match value {
+                Value::Dec(dec) => Value::Dec(decimal::round(dec, digits as i64, rounding)?),
+                Value::Nb(nb) if !nb.is_finite() => Value::Nb(nb),
+                Value::Nb(nb) => {
+                    let rounded = decimal::round(decimal::from_f64(nb)?, digits as i64, rounding)?;
+                    Value::Nb(decimal::to_f64(rounded))
+                }
+                value => return Err(format!("Expected a number, got {}", value.to_code()).into()),
+            });
         }
-        Literal::Fun(name, lit) => {
-            let nb = compute_literal(ctx, lit)?;
-            match name.as_ref() {
-                "floor" => nb.floor(),
-                "ceil" => nb.ceil(),
-                "round" => nb.round(),
-                "trunc" => nb.trunc(),
-                "fract" => nb.fract(),
-                "sqrt" => nb.sqrt(),
-                "exp" => nb.exp(),
-                "ln" => nb.ln(),
-                "log2" => nb.log2(),
-                "log10" => nb.log10(),
-                "cos" => nb.cos(),
-                "sin" => nb.sin(),
-                "tan" => nb.tan(),
-                "acos" => nb.acos(),
-                "asin" => nb.asin(),
-                "atan" => nb.atan(),
-                _ => return Err(format!("Unknown function '{}'", name)),
+        ("diff", [lit, x, at]) => {
+            let x = ctx.unknown(x)?;
+            let derivative = symbolic::diff(lit, x)?;
+            let at = compute_literal(ctx, at)?;
+            ctx.with_var(x, at, |ctx| compute_literal(ctx, &derivative))?
+        }
+        ("diff", [_, _]) => {
+            return Err(
+                "'diff(expr, x)' is an expression, use 'diff(expr, x, at)' to compute a number"
+                    .into(),
+            )
+        }
+        ("diff", _) => return Err(arity_err(name, "2 or 3", args.len()).into()),
+        ("integrate", [lit, x, a, b]) => compute_integral(ctx, lit, x, a, b)?.value,
+        ("integrate", _) => return Err(arity_err(name, "4", args.len()).into()),
+        ("solve", [eq, x, rest @ ..]) if rest.len() <= 2 => {
+            let roots = compute_solve(ctx, eq, x, rest)?;
+            match roots.as_slice() {
+                [root] => *root,
+                [] => return Err("No root found in the interval".into()),
+                roots => {
+                    return Err(format!(
+                        "Found {} roots in the interval, narrow it to select one",
+                        roots.len()
+                    )
+                    .into())
+                }
+            }
+        }
+        ("solve", _) => return Err(arity_err(name, "2 to 4", args.len()).into()),
+        ("len", [lit]) => compute_str(ctx, lit)?.chars().count() as f64,
+        ("upper", [lit]) => return Ok(Value::Str(compute_str(ctx, lit)?.to_uppercase())),
+        ("lower", [lit]) => return Ok(Value::Str(compute_str(ctx, lit)?.to_lowercase())),
+        ("str", [lit]) => return Ok(Value::Str(compute_value(ctx, lit)?.to_string())),
+        ("num", [lit]) => {
+            let str = compute_str(ctx, lit)?;
+            match str.trim().parse::<f64>() {
+                Ok(nb) => nb,
+                Err(_) => return Err(format!("Cannot convert \"{}\" to a number", str).into()),
             }
         }
-        Literal::Var(id) => match id.as_ref() {
-            "PI" => std::f64::consts::PI,
-            "E" => std::f64::consts::E,
-            _ => match ctx.get(id) {
-                Some(nb) => *nb,
-                None => return Err(format!("Unknown variable '{}'", id)),
+        ("len" | "upper" | "lower" | "str" | "num", _) => {
+            return Err(arity_err(name, "1", args.len()).into())
+        }
+        ("substr", [lit, start, rest @ ..]) if rest.len() <= 1 => {
+            let str = compute_str(ctx, lit)?;
+            let chars = str.chars().skip(compute_index(ctx, start)?);
+            return Ok(Value::Str(match rest {
+                [len] => chars.take(compute_index(ctx, len)?).collect(),
+                _ => chars.collect(),
+            }));
+        }
+        ("substr", _) => return Err(arity_err(name, "2 or 3", args.len()).into()),
+        (name, args) => match function(name) {
+            Some(fun) => match args {
+                [lit] => fun(compute_literal(ctx, lit)?),
+                _ => return Err(arity_err(name, "1", args.len()).into()),
             },
+            None => return Err(format!("Unknown function '{}'", name).into()),
+        },
+    };
+    Ok(ctx.number(nb)?)
+}
+
+/** Compute a binary operation on decimals, failing instead of losing precision */
+fn compute_decimal(op: BinOp, l: Decimal, r: Decimal) -> Result<Value, Error> {
+    if matches!(op, BinOp::Div | BinOp::Mod) && r.is_zero() {
+        return Err("Division by zero".into());
+    }
+    let result = match op {
+        BinOp::Add => l.checked_add(r),
+        BinOp::Sub => l.checked_sub(r),
+        BinOp::Mul => l.checked_mul(r),
+        BinOp::Div => l.checked_div(r),
+        BinOp::Mod => l.checked_rem(r),
+        // Integer powers are exact, others are computed with floats
+        BinOp::Pow if r.fract().is_zero() => match r.to_string().parse() {
+            Ok(exp) => decimal::pow(l, exp),
+            Err(_) => None,
         },
+        BinOp::Pow => {
+            let nb = decimal::to_f64(l).powf(decimal::to_f64(r));
+            return Ok(Value::Dec(decimal::from_f64(nb)?));
+        }
+    };
+    match result {
+        Some(dec) => Ok(Value::Dec(dec)),
+        None => Err(format!("Decimal overflow with {} and {}", l, r).into()),
+    }
+}
+
+/** Compute a definite integral with its error estimate */
+fn compute_integral(
+    ctx: &mut Context,
+    lit: &Literal,
+    x: &Literal,
+    a: &Literal,
+    b: &Literal,
+) -> Result<numeric::Integral, Error> {
+    let x = ctx.unknown(x)?;
+    let (a, b) = (compute_literal(ctx, a)?, compute_literal(ctx, b)?);
+    let lit = optimizer::optimize(ctx, lit, x);
+    numeric::integrate(ctx, &lit, x, a, b)
+}
+
+/** Solve an equation from a guess or in an interval */
+fn compute_solve(
+    ctx: &mut Context,
+    eq: &Literal,
+    x: &Literal,
+    rest: &[Literal],
+) -> Result<Vec<f64>, Error> {
+    let x = ctx.unknown(x)?;
+    let residual = optimizer::optimize(ctx, &numeric::residual(eq), x);
+    Ok(match rest {
+        [] => vec![numeric::find_root(ctx, &residual, x, 0.)?],
+        [guess] => {
+            let guess = compute_literal(ctx, guess)?;
+            vec![numeric::find_root(ctx, &residual, x, guess)?]
+        }
+        [a, b] => {
+            let (a, b) = (compute_literal(ctx, a)?, compute_literal(ctx, b)?);
+            numeric::find_roots(ctx, &residual, x, a, b)?
+        }
+        _ => unreachable!(),
     })
 }
 
+/** Replace escaped braces of a string literal */
+fn unescape(str: &str) -> String {
+    str.replace("{{", "{").replace("}}", "}")
+}
+
+fn arity_err(name: &str, expected: &str, given: usize) -> String {
+    format!(
+        "Function '{}' takes {} argument(s) but {} were given",
+        name, expected, given
+    )
+}
+
 #[cfg(test)]
 mod test {
-    use crate::interpreter::{compute, compute_literal, compute_print, Context};
+    use crate::format::NumberFormat;
+    use crate::interpreter::{
+        compute, compute_literal, compute_print, is_incomplete, statements, Context, Value,
+    };
     use crate::lexer::Lexer;
     use crate::parser::parse;
     use crate::parser::Expression;
@@ -131,7 +1136,7 @@ mod test {
         let parsed = parse(Lexer::load(str));
         assert!(parsed.is_ok(), "{:?}", parsed);
         let expr = match parsed.unwrap() {
-            Line::Expr(it) => it,
+            Line::Exprs(mut exprs) => exprs.remove(0),
             _ => unreachable!(),
         };
         let lit = match expr {
@@ -147,7 +1152,7 @@ mod test {
         let parsed = parse(Lexer::load(str));
         assert!(parsed.is_ok(), "{:?}", parsed);
         let expr = match parsed.unwrap() {
-            Line::Expr(it) => it,
+            Line::Exprs(mut exprs) => exprs.remove(0),
             _ => unreachable!(),
         };
         let print = match expr {
@@ -165,6 +1170,11 @@ mod test {
         assert!(result.is_err(), "{:?}", result)
     }
 
+    fn assert_fail_ctx(ctx: &mut Context, str: &str) {
+        let result = compute(ctx, str);
+        assert!(result.is_err(), "{:?}", result)
+    }
+
     fn compute_no_context(str: &str) -> Result<String, String> {
         let mut context = Context::empty();
         compute(&mut context, str)
@@ -176,6 +1186,11 @@ mod test {
         assert_compute("12345", 12345.);
         assert_compute("12345.", 12345.);
         assert_compute("123.45", 123.45);
+        // A number directly followed by a name multiplies it, there is no exponent
+        assert_compute("2E", 2. * std::f64::consts::E);
+        assert_compute("2E-3", 2. * std::f64::consts::E - 3.);
+        let mut ctx = Context::empty();
+        assert_eq!(compute(&mut ctx, "e3 = 7; 2e3").unwrap(), "14");
     }
 
     #[test]
@@ -248,6 +1263,584 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_str() {
+        let mut ctx = Context::empty();
+        assert_eq!(
+            compute(&mut ctx, "name = \"pump A\"").unwrap(),
+            "name = \"pump A\""
+        );
+        assert_eq!(
+            compute(&mut ctx, "upper(name) + \" \" + lower(name)").unwrap(),
+            "\"PUMP A pump a\""
+        );
+        assert_eq!(compute(&mut ctx, "len(name)").unwrap(), "6");
+        assert_eq!(compute(&mut ctx, "len(\"été\")").unwrap(), "3");
+        assert_eq!(compute(&mut ctx, "substr(name, 5)").unwrap(), "\"A\"");
+        assert_eq!(compute(&mut ctx, "substr(name, 0, 4)").unwrap(), "\"pump\"");
+        assert_eq!(compute(&mut ctx, "substr(name, 10, 4)").unwrap(), "\"\"");
+        assert_eq!(compute(&mut ctx, "str(1/4) + \"!\"").unwrap(), "\"0.25!\"");
+        assert_eq!(compute(&mut ctx, "num(\" 3.5 \") * 2").unwrap(), "7");
+        assert_eq!(compute(&mut ctx, "$").unwrap(), "7");
+        assert_eq!(
+            compute(&mut ctx, "\"Hello \" + name \"!\"").unwrap(),
+            "\"Hello pump A!\""
+        );
+        assert_fail("\"a\" + 1");
+        assert_fail("\"a\" * 2");
+        assert_fail("-\"a\"");
+        assert_fail("sqrt(\"a\")");
+        assert_fail("len(2)");
+        assert_fail("num(\"abc\")");
+        assert_fail("substr(\"abc\", -1)");
+        assert_fail("substr(\"abc\", 0.5)");
+        assert_fail("upper(\"a\", \"b\")");
+    }
+
+    #[test]
+    fn test_interpolation() {
+        let mut ctx = Context::empty();
+        compute(&mut ctx, "total = 1/3").unwrap();
+        compute(&mut ctx, "name = \"pump\"").unwrap();
+        let assert_str = |ctx: &mut Context, str: &str, expected: &str| {
+            assert_eq!(compute(ctx, str).unwrap(), format!("\"{}\"", expected))
+        };
+        assert_str(&mut ctx, "\"cost {total:.2} EUR\"", "cost 0.33 EUR");
+        assert_str(&mut ctx, "\"{total}\"", "0.3333333333333333");
+        assert_str(&mut ctx, "\"{name} {upper(name)}!\"", "pump PUMP!");
+        assert_str(&mut ctx, "\"[{total:8.3}]\"", "[   0.333]");
+        assert_str(&mut ctx, "\"[{total:<8.3}]\"", "[0.333   ]");
+        assert_str(&mut ctx, "\"[{name:*^8}]\"", "[**pump**]");
+        assert_str(&mut ctx, "\"[{name:>6}]\"", "[  pump]");
+        assert_str(&mut ctx, "\"{total:.1%}\"", "33.3%");
+        assert_str(&mut ctx, "\"{1234.5:.2e}\"", "1.23e3");
+        assert_str(&mut ctx, "\"{1234567.891:,.2}\"", "1,234,567.89");
+        assert_str(&mut ctx, "\"{-1234567:,}\"", "-1,234,567");
+        assert_str(&mut ctx, "\"{2:03}\"", "002");
+        assert_str(&mut ctx, "\"{-2.5:06.2}\"", "-02.50");
+        assert_str(&mut ctx, "\"{{not}} {\"{name}\"}\"", "{not} pump");
+        assert_str(&mut ctx, "\"a\" + \"{1+1}\"", "a2");
+        assert_eq!(
+            compute(&mut ctx, "brace = \"{{\"").unwrap(),
+            "brace = \"{{\""
+        );
+        assert_fail("\"{1:.x}\"");
+        assert_fail("\"{1:?}\"");
+        assert_fail("\"{1\"");
+        assert_fail("\"{1}");
+        assert_fail("\"{}\"");
+        assert_fail("\"{unknown}\"");
+    }
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(
+            compute_no_context("diff(x^2*sin(x), x)").unwrap(),
+            "2*x*sin(x) + x^2*cos(x)"
+        );
+        assert_eq!(compute_no_context("diff(x^2, x, 3)").unwrap(), "6");
+        assert_eq!(
+            compute_no_context("diff(diff(x^3, x), x, 2)").unwrap(),
+            "12"
+        );
+        assert_fail("diff(x^2, x) + 1");
+        assert_fail("diff(x^2, PI)");
+        assert_fail("diff(x^2, 2*x)");
+        assert_fail("diff(x^2)");
+        assert_fail("sqrt(1, 2)");
+    }
+
+    #[test]
+    fn test_integrate() {
+        let assert_close = |str: &str, nb: f64| {
+            let result = compute_no_context(str).unwrap();
+            let result = result.split(" ± ").next().unwrap().parse::<f64>().unwrap();
+            assert!((result - nb).abs() < 1e-9, "{} = {}", str, result)
+        };
+        assert_close("integrate(x^2, x, 0, 3)", 9.);
+        assert_close("integrate(x^2, x, 3, 0)", -9.);
+        assert_close("integrate(sin(x), x, 0, PI)", 2.);
+        assert_close("integrate(sin(x), x, 0, 2*PI)", 0.);
+        assert_close("integrate(sqrt(x), x, 0, 1)", 2. / 3.);
+        assert_close("integrate(x^2, x, 0, 1000) / 10^9", 1. / 3.);
+        assert_close("2*integrate(integrate(x*y, x, 0, 1), y, 0, 2)", 2.);
+        assert_fail("integrate(1/x, x, 0, 1)");
+        assert_fail("integrate(1/x, x, -1, 1)");
+        assert_fail("integrate(x, x, 0, 1/0)");
+        assert_fail("integrate(sin(1/x), x, 0.0000001, 1)");
+        assert_fail("integrate(x, PI, 0, 1)");
+        assert_fail("integrate(x, x, 0)");
+        // Large values are printed in scientific notation
+        let err = compute_no_context("integrate(1/(x - 10^300), x, 0, 10^300)").unwrap_err();
+        assert!(err.contains("is inf at x = 1e300"), "{}", err);
+        // The error estimate is reported with the value of a single integral
+        assert_eq!(
+            compute_no_context("integrate(sin(x), x, 0, PI)").unwrap(),
+            "1.9999999999999993 ± 2.3e-11"
+        );
+        assert_eq!(
+            compute_no_context("integrate(x^2, x, 0, 1)").unwrap(),
+            "0.3333333333333333 ± 0"
+        );
+        assert_eq!(
+            compute_no_context("integrate(x^2, x, 0, 1) + 1").unwrap(),
+            "1.3333333333333333"
+        );
+    }
+
+    #[test]
+    fn test_solve() {
+        let assert_close = |str: &str, nb: f64| {
+            let result = compute_no_context(str).unwrap().parse::<f64>().unwrap();
+            assert!((result - nb).abs() < 1e-9, "{} = {}", str, result)
+        };
+        assert_close("solve(x^2 - 2 = 0, x)", 2f64.sqrt());
+        assert_close("solve(x^2 - 2, x, -1)", -(2f64.sqrt()));
+        assert_close("solve(exp(x) = 3, x, 10)", 3f64.ln());
+        assert_close("solve(cos(x) = x, x)", 0.7390851332151607);
+        assert_close("2*solve(sin(x), x, 3, 4)", 2. * std::f64::consts::PI);
+        let assert_roots = |str: &str, roots: &[f64]| {
+            let result = compute_no_context(str).unwrap();
+            let found: Vec<f64> = result
+                .trim_start_matches("x = ")
+                .split(", ")
+                .map(|nb| nb.parse().unwrap())
+                .collect();
+            assert_eq!(found.len(), roots.len(), "{}", result);
+            for (found, root) in found.iter().zip(roots) {
+                assert!((found - root).abs() < 1e-9, "{}", result)
+            }
+        };
+        assert_roots("solve(x^2 = 2, x, -2, 2)", &[-(2f64.sqrt()), 2f64.sqrt()]);
+        assert_roots(
+            "solve(sin(x) = 0, x, -1, 7)",
+            &[0., std::f64::consts::PI, 2. * std::f64::consts::PI],
+        );
+        assert_fail("solve(x^2 = 2, x, -2, 2) + 1");
+        assert_fail("solve(x^2 = -2, x)");
+        assert_fail("solve(1/x = 0, x, -1, 1)");
+        assert_fail("solve(sin(x) = 0, x, 0, 1000)");
+        assert_fail("solve(x = 2, PI)");
+        assert_fail("solve(x = 2)");
+        assert_fail("x = 2 = 3");
+        assert_fail("sqrt(x = 2)");
+    }
+
+    #[test]
+    fn test_system() {
+        let mut ctx = Context::empty();
+        assert_eq!(compute(&mut ctx, "2x + 3y = 5").unwrap(), "2*x + 3*y = 5");
+        assert_eq!(compute(&mut ctx, "x - y = 1").unwrap(), "x - y = 1");
+        assert_eq!(compute(&mut ctx, "solve x, y").unwrap(), "x = 1.6, y = 0.6");
+        assert_eq!(compute(&mut ctx, "x + y").unwrap(), "2.2");
+
+        // Known variables and functions are evaluated
+        compute(&mut ctx, "k = 2").unwrap();
+        compute(&mut ctx, "k*(a + b) = sqrt(16)").unwrap();
+        compute(&mut ctx, "a/2 + 1 = b/2").unwrap();
+        assert_eq!(compute(&mut ctx, "solve a, b").unwrap(), "a = 0, b = 2");
+
+        let assert_system_fail = |lines: &[&str]| {
+            let mut ctx = Context::empty();
+            for line in lines {
+                compute(&mut ctx, line).unwrap();
+            }
+            assert_fail_ctx(&mut ctx, "solve x, y");
+            // Equations are consumed even on failure
+            assert_fail_ctx(&mut ctx, "solve x, y");
+        };
+        assert_system_fail(&["x + y = 1"]);
+        assert_system_fail(&["x + y = 1", "2x + 2y = 2"]);
+        assert_system_fail(&["x + y = 1", "x + y = 2"]);
+        assert_system_fail(&["x + y = 1", "x - y = 1", "2x = 3 - y"]);
+        assert_system_fail(&["x*y = 1", "x - y = 1"]);
+        assert_system_fail(&["x + z = 1", "x - y = 1"]);
+        assert_fail("solve x, PI");
+        assert_fail("solve x, x");
+        assert_fail("solve x,");
+        assert_fail("2x + 3y = 5 = 3");
+        assert_fail("2 = 3");
+        assert_fail("2*PI = 6");
+
+        // Equations must be followed by a solve statement
+        let mut ctx = Context::empty();
+        compute(&mut ctx, "x + 1 = 3").unwrap();
+        assert_eq!(
+            compute(&mut ctx, "y = 2"),
+            Err("Expected 'solve' after the equation 'x + 1 = 3'".into())
+        );
+        assert_fail_ctx(&mut ctx, "solve x");
+        assert_fail("x + 1 = 3; x");
+        assert_eq!(compute(&mut ctx, "x + 1 = 3; solve x").unwrap(), "x = 2");
+    }
+
+    #[test]
+    fn test_number_format() {
+        let assert_format = |options: &[(&str, &str)], str: &str, expected: &str| {
+            let mut ctx = Context::empty();
+            let mut settings = ctx.settings();
+            for (option, value) in options {
+                settings.set(option, value).unwrap();
+            }
+            ctx.set_settings(settings);
+            assert_eq!(compute(&mut ctx, str).unwrap(), expected);
+        };
+        assert_format(&[], "0.1 + 0.2", "0.30000000000000004");
+        assert_format(&[("trim", "on")], "0.1 + 0.2", "0.3");
+        assert_format(&[("digits", "3")], "1/3", "0.333");
+        assert_format(&[("digits", "3")], "123456", "123000");
+        assert_format(&[("decimals", "2")], "x = 1/3", "x = 0.33");
+        assert_format(&[("decimals", "2")], "2", "2.00");
+        assert_format(&[("notation", "sci")], "1234.5", "1.2345e3");
+        assert_format(&[("notation", "sci"), ("digits", "2")], "1234.5", "1.2e3");
+        assert_format(&[("notation", "sci"), ("decimals", "2")], "1000", "1.00e3");
+        assert_format(&[("notation", "eng")], "12345", "12.345e3");
+        assert_format(&[("notation", "eng")], "-0.00012", "-120e-6");
+        assert_format(&[("notation", "eng")], "100000", "100e3");
+        assert_format(&[("notation", "eng")], "12", "12");
+        assert_format(&[("notation", "si")], "0.0047", "4.7m");
+        assert_format(&[("notation", "si")], "2200000", "2.2M");
+        assert_format(&[("notation", "si")], "10^21", "1e21");
+        assert_format(&[("notation", "si"), ("digits", "2")], "1/3", "330m");
+        assert_format(&[("notation", "si"), ("decimals", "1")], "1234", "1.2k");
+        assert_format(&[("group", "on")], "-1234567.5", "-1,234,567.5");
+        assert_format(
+            &[("group", "'"), ("decimals", "1")],
+            "1234567",
+            "1'234'567.0",
+        );
+        assert_format(&[("digits", "3")], "\"{1/3} {1/3:.1}\"", "\"0.333 0.3\"");
+        assert_format(&[("digits", "3")], "\"a\" 1/3", "\"a0.333\"");
+        assert_format(&[("digits", "3")], "1/0", "inf");
+        assert_format(&[("notation", "si")], "0", "0");
+
+        let mut format = NumberFormat::default();
+        assert!(format.set("digits", "0").is_err());
+        assert!(format.set("digits", "x").is_err());
+        assert!(format.set("decimals", "18").is_err());
+        assert!(format.set("notation", "roman").is_err());
+        assert!(format.set("group", "ab").is_err());
+        assert!(format.set("trim", "yes").is_err());
+        assert!(format.set("color", "on").is_err());
+        assert_eq!(format, NumberFormat::default());
+    }
+
+    #[test]
+    fn test_decimal() {
+        let mut ctx = Context::empty();
+        assert_eq!(
+            compute(&mut ctx, "0.1 + 0.2").unwrap(),
+            "0.30000000000000004"
+        );
+        assert_eq!(compute(&mut ctx, "round(2.675, 2)").unwrap(), "2.68");
+        let mut settings = ctx.settings();
+        settings.set("decimal", "on").unwrap();
+        ctx.set_settings(settings);
+        let mut assert_dec = |str: &str, expected: &str| {
+            assert_eq!(compute(&mut ctx, str).unwrap(), expected, "{}", str)
+        };
+        assert_dec("0.1 + 0.2", "0.3");
+        assert_dec("0.1 + 0.2 - 0.3", "0");
+        assert_dec("1/3", "0.3333333333333333333333333333");
+        assert_dec("price = 19.99", "price = 19.99");
+        assert_dec("price*3", "59.97");
+        assert_dec("-price", "-19.99");
+        assert_dec("1.1^2", "1.21");
+        assert_dec("2^-2", "0.25");
+        assert_dec("10 % 3", "1");
+        assert_dec("sqrt(2)", "1.4142135623730951");
+        // Literals are parsed exactly, not rounded to a float first
+        assert_dec("1234567890123456.78 + 0.01", "1234567890123456.79");
+        assert_dec("1500 + 0.025", "1500.025");
+        assert_dec("0.1234567890123456789", "0.1234567890123456789");
+        assert_dec("\"{price*3:.1}\"", "\"60.0\"");
+
+        // Banker's rounding by default
+        assert_dec("round(2.5, 0)", "2");
+        assert_dec("round(3.5, 0)", "4");
+        assert_dec("round(0.125, 2)", "0.12");
+        assert_dec("round(1234.5, -2)", "1200");
+        assert_dec("round(2.5, 0, \"half-up\")", "3");
+        assert_dec("round(-2.5, 0, \"half-down\")", "-2");
+        assert_dec("round(2.01, 1, \"up\")", "2.1");
+        assert_dec("round(-2.01, 1, \"floor\")", "-2.1");
+        assert_dec("round(2.99, 1, \"down\")", "2.9");
+        assert_dec("round(2.91, 1, \"ceiling\")", "3");
+        let mut settings = ctx.settings();
+        settings.set("rounding", "half-up").unwrap();
+        ctx.set_settings(settings);
+        assert_eq!(compute(&mut ctx, "round(0.125, 2)").unwrap(), "0.13");
+
+        assert_fail_ctx(&mut ctx, "1/0");
+        assert_fail_ctx(&mut ctx, "1 % 0");
+        assert_fail_ctx(&mut ctx, "sqrt(-1)");
+        assert_fail_ctx(&mut ctx, "10^30");
+        assert_fail_ctx(&mut ctx, "round(1, 0.5)");
+        assert_fail_ctx(&mut ctx, "round(1, 2, \"nearest\")");
+        assert_fail_ctx(&mut ctx, "round(\"1\", 2)");
+        assert!(ctx.settings().set("decimal", "yes").is_err());
+        assert!(ctx.settings().set("rounding", "nearest").is_err());
+        assert_eq!(
+            ctx.settings().set("color", "on"),
+            Err(
+                "Unknown option 'color', expected digits, decimals, notation, group, trim, \
+                decimal, rounding or reactive"
+                    .into()
+            )
+        );
+    }
+
+    #[test]
+    fn test_statements() {
+        let source = "a = (1 +\n  2)\n\n# comment (\nb = a *\n  2 +\n  1\nc = (\n";
+        let split: Vec<_> = statements(source).collect();
+        assert_eq!(
+            split,
+            [
+                ("a = (1 +\n  2)", 2),
+                ("", 1),
+                ("# comment (", 1),
+                ("b = a *\n  2 +\n  1", 3),
+                ("c = (", 1),
+            ]
+        );
+        assert_eq!(
+            statements("1\r\n2").collect::<Vec<_>>(),
+            [("1", 1), ("2", 1)]
+        );
+        assert!(is_incomplete("f(1,"));
+        assert!(is_incomplete("x ="));
+        assert!(!is_incomplete("x = 1)"));
+        assert!(!is_incomplete("\"a +\""));
+
+        let mut ctx = Context::empty();
+        let results: Vec<_> = statements(source)
+            .map(|(statement, _)| compute(&mut ctx, statement))
+            .collect();
+        assert_eq!(results[0], Ok("a = 3".into()));
+        assert_eq!(results[3], Ok("b = 7".into()));
+        // Errors point at the line of the statement
+        assert_eq!(
+            compute(&mut ctx, "1 +\n  2 2").unwrap_err(),
+            "Incomplete expression\n  2 2\n    ^"
+        );
+    }
+
+    #[test]
+    fn test_separator() {
+        let mut ctx = Context::empty();
+        assert_eq!(compute(&mut ctx, "a = 2; b = 3; a*b").unwrap(), "6");
+        assert_eq!(compute(&mut ctx, "c = a + b;").unwrap(), "c = 5");
+        assert_eq!(compute(&mut ctx, ";; c; ;").unwrap(), "5");
+        assert_eq!(compute(&mut ctx, " ; ").unwrap(), "");
+        assert_eq!(compute(&mut ctx, "\"a\" \"b\"; \"c\"").unwrap(), "\"c\"");
+        assert_eq!(
+            compute(&mut ctx, "2x + 3y = 5; x - y = 1; solve x, y").unwrap(),
+            "x = 1.6, y = 0.6"
+        );
+        assert_eq!(
+            compute(&mut ctx, "x = 1; x*3 = 6; solve x").unwrap(),
+            "x = 2"
+        );
+        // Statements before a computation error are kept
+        assert_fail_ctx(&mut ctx, "d = 1; e = d + g; f = 2");
+        assert_eq!(compute(&mut ctx, "d").unwrap(), "1");
+        assert_fail_ctx(&mut ctx, "f");
+        assert_fail_ctx(&mut ctx, "g = 1; 1 +; 3");
+        assert_fail_ctx(&mut ctx, "g");
+    }
+
+    #[test]
+    fn test_let() {
+        let mut ctx = Context::empty();
+        let mut assert_let = |str: &str, expected: &str| {
+            assert_eq!(compute(&mut ctx, str).unwrap(), expected, "{}", str)
+        };
+        assert_let(
+            "let r = 2 in PI*r^2",
+            &(std::f64::consts::PI * 4.).to_string(),
+        );
+        assert_let("area = w*h where w = 3, h = 4", "area = 12");
+        assert_let("let a = 1, b = a + 1 in a + b", "3");
+        assert_let("w = 10", "w = 10");
+        assert_let("(w where w = 2) + w", "12");
+        assert_let("1 + let w = 2 in w*3", "7");
+        assert_let("max = let a = 1 in a*b where b = 5", "max = 5");
+        assert_let("\"{x where x = 4}\"", "\"4\"");
+        assert_let("integrate(k*x, x, 0, 1) where k = 2", "1");
+        assert_let("solve(x^2 = a, x, 1) where a = 4", "2");
+        assert_let(
+            "2*(let a = 1 in a) = -(b where b = 1) + y",
+            "2*(let a = 1 in a) = -(b where b = 1) + y",
+        );
+        assert_let("solve y", "y = 3");
+        assert_fail_ctx(&mut ctx, "2*(let a = 1 in a) = b where b = 1");
+        // Local names do not leak in the worksheet
+        assert_fail_ctx(&mut ctx, "r");
+        assert_fail_ctx(&mut ctx, "a");
+        assert_fail_ctx(&mut ctx, "h");
+        assert_fail_ctx(&mut ctx, "(1/q where q = 0) + q");
+        assert_fail_ctx(&mut ctx, "let PI = 3 in PI");
+        assert_fail_ctx(&mut ctx, "let a = 1 a");
+        assert_fail_ctx(&mut ctx, "let a 1 in a");
+        assert_fail_ctx(&mut ctx, "a where");
+        assert_fail_ctx(&mut ctx, "diff(let a = 2 in a*x, x)");
+    }
+
+    #[test]
+    fn test_const() {
+        let mut ctx = Context::empty();
+        assert_fail_ctx(&mut ctx, "PI = 3");
+        assert_fail_ctx(&mut ctx, "sin = 3");
+        assert_fail_ctx(&mut ctx, "diff = 3");
+        assert_fail_ctx(&mut ctx, "const E = 3");
+        assert_fail_ctx(&mut ctx, "override x = 3");
+        assert_eq!(compute(&mut ctx, "g = 9").unwrap(), "g = 9");
+        assert_eq!(
+            compute(&mut ctx, "const g = 9.80665").unwrap(),
+            "g = 9.80665"
+        );
+        assert_eq!(compute(&mut ctx, "2*g").unwrap(), "19.6133");
+        assert_fail_ctx(&mut ctx, "g = 10");
+        assert_fail_ctx(&mut ctx, "const g = 10");
+        assert_fail_ctx(&mut ctx, "let g = 1 in g");
+        assert_fail_ctx(&mut ctx, "integrate(g, g, 0, 1)");
+        assert_fail_ctx(&mut ctx, "diff(g^2, g)");
+        assert_fail_ctx(&mut ctx, "g + y = 1; g - y = 2; solve g, y");
+        assert_eq!(compute(&mut ctx, "override g = 9.81").unwrap(), "g = 9.81");
+        assert_eq!(
+            compute(&mut ctx, "override PI = 3.14").unwrap(),
+            "PI = 3.14"
+        );
+        assert_eq!(compute(&mut ctx, "2*PI").unwrap(), "6.28");
+        assert_fail_ctx(&mut ctx, "PI = 3");
+        assert_eq!(
+            compute(&mut ctx, "const c = \"m/s\"").unwrap(),
+            "c = \"m/s\""
+        );
+        assert_eq!(compute(&mut ctx, "const a = 1; a + 1").unwrap(), "2");
+        assert_fail_ctx(&mut ctx, "const b 1");
+        let constants: Vec<_> = ctx
+            .constants()
+            .into_iter()
+            .map(|(id, value)| format!("{} = {}", id, value.to_code()))
+            .collect();
+        assert_eq!(
+            constants,
+            [
+                "PI = 3.14",
+                &format!("E = {}", std::f64::consts::E),
+                "a = 1",
+                "c = \"m/s\"",
+                "g = 9.81"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reactive() {
+        let mut ctx = Context::empty();
+        let mut settings = ctx.settings();
+        settings.set("reactive", "on").unwrap();
+        ctx.set_settings(settings);
+        let assert_line = |ctx: &mut Context, str: &str, expected: &str| {
+            assert_eq!(compute(ctx, str).unwrap(), expected, "{}", str)
+        };
+        assert_line(&mut ctx, "rate = 0.05", "rate = 0.05");
+        assert_line(&mut ctx, "years = 2", "years = 2");
+        assert_line(&mut ctx, "interest = 1000*rate", "interest = 50");
+        assert_line(&mut ctx, "total = 1000 + interest*years", "total = 1100");
+        assert_line(
+            &mut ctx,
+            "label = \"{total} EUR\" + str(integrate(x*rate, x, 0, 1))",
+            "label = \"1100 EUR0.025\"",
+        );
+        assert_line(
+            &mut ctx,
+            "rate = 0.1",
+            "rate = 0.1, interest = 100, total = 1200, label = \"1200 EUR0.05\"",
+        );
+        assert_line(
+            &mut ctx,
+            "years = 3",
+            "years = 3, total = 1300, label = \"1300 EUR0.05\"",
+        );
+        assert_line(&mut ctx, "scaled = let k = 2 in k*years", "scaled = 6");
+        assert_line(&mut ctx, "k = 10", "k = 10");
+        // A plain value breaks the dependency
+        assert_line(
+            &mut ctx,
+            "interest = 7",
+            "interest = 7, total = 1021, label = \"1021 EUR0.05\"",
+        );
+        assert_line(
+            &mut ctx,
+            "rate = 0.2",
+            "rate = 0.2, label = \"1021 EUR0.1\"",
+        );
+        assert_line(&mut ctx, "const fee = 5", "fee = 5");
+        assert_line(&mut ctx, "net = total - fee", "net = 1016");
+        assert_line(&mut ctx, "override fee = 6", "fee = 6, net = 1015");
+        assert_line(&mut ctx, "a + b = 3; a - b = 1; solve a, b", "a = 2, b = 1");
+        assert_line(&mut ctx, "c = a*b", "c = 2");
+        assert_line(
+            &mut ctx,
+            "a + b = 4; a - b = 0; solve a, b",
+            "a = 2, b = 2, c = 4",
+        );
+
+        // Cycles are rejected and leave the variable unchanged
+        assert_eq!(
+            compute(&mut ctx, "years = net/100").unwrap_err(),
+            "Circular dependency: years -> net -> total -> years"
+        );
+        assert_line(&mut ctx, "years", "3");
+        // A formula reading its own variable updates it once from its previous value
+        assert_line(
+            &mut ctx,
+            "years = years + 1",
+            "years = 4, total = 1028, net = 1022, label = \"1028 EUR0.1\", scaled = 8",
+        );
+        assert_line(&mut ctx, "k = 2", "k = 2");
+        // Downstream errors are reported
+        assert_line(&mut ctx, "d = 1", "d = 1");
+        assert_line(&mut ctx, "inv = 1/(d - 1)", "inv = inf");
+        assert_line(&mut ctx, "s = \"a\"", "s = \"a\"");
+        assert_line(&mut ctx, "u = s + \"b\"", "u = \"ab\"");
+        assert_fail_ctx(&mut ctx, "s = 1");
+    }
+
+    #[test]
+    fn test_variables() {
+        let mut ctx = Context::empty();
+        ctx.set_variable("width", Value::Nb(3.)).unwrap();
+        ctx.set_variable("name", Value::Str("box".into())).unwrap();
+        assert_eq!(compute(&mut ctx, "area = width*4").unwrap(), "area = 12");
+        assert_eq!(ctx.variable("area"), Some(&Value::Nb(12.)));
+        assert_eq!(ctx.variable("height"), None);
+        assert_eq!(
+            ctx.variables(),
+            [
+                ("area".to_string(), Value::Nb(12.)),
+                ("name".to_string(), Value::Str("box".into())),
+                ("width".to_string(), Value::Nb(3.)),
+            ]
+        );
+        assert!(ctx.set_variable("PI", Value::Nb(3.)).is_err());
+        assert!(ctx.set_variable("sqrt", Value::Nb(3.)).is_err());
+        assert!(ctx.set_variable("a b", Value::Nb(3.)).is_err());
+        assert!(ctx.set_variable("1a", Value::Nb(3.)).is_err());
+        assert!(ctx.set_variable("", Value::Nb(3.)).is_err());
+
+        // Dependents are updated in reactive mode
+        let mut settings = ctx.settings();
+        settings.reactive = true;
+        ctx.set_settings(settings);
+        assert_eq!(compute(&mut ctx, "area = width*4").unwrap(), "area = 12");
+        ctx.set_variable("width", Value::Nb(5.)).unwrap();
+        assert_eq!(ctx.variable("area"), Some(&Value::Nb(20.)));
+    }
+
     prop_compose! {
         fn arb_nb()(nb in any::<u8>(), op in "[+-]?") -> String {
             format!("{}{}", op, nb)
@@ -283,7 +1876,7 @@ mod test {
         let parsed = parse(Lexer::load(&nb));
         assert!(parsed.is_ok(), "{:?}", parsed);
         let expr = match parsed.unwrap() {
-            Line::Expr(it) => it,
+            Line::Exprs(mut exprs) => exprs.remove(0),
             _ => unreachable!(),
         };
         let lit = match expr {
@@ -1,3 +1,4 @@
+use crate::report::Error;
 use std::ops::Range;
 
 /** This is a pull lexer responsible for finding tokens in a code line.
@@ -18,18 +19,29 @@ pub enum Op {
 pub enum Sep {
     Open,    // (
     Close,   // )
+    Comma,   // ,
     Comment, // #
+    Semi,    // ;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Template {
+    Start,  // "...{
+    Middle, // }...{
+    End,    // }..."
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TokenKind {
-    Nb,       // f64 num
-    Op(Op),   // Any operator
-    Id,       // Sequence of supported char
-    Str,      // Sequence of any char between "
-    Sep(Sep), // Any separator
-    Err,      // Unsupported char
-    Eof,      // End of file
+    Nb,                 // f64 num
+    Op(Op),             // Any operator
+    Id,                 // Sequence of supported char
+    Str,                // Sequence of any char between "
+    Template(Template), // String part around an interpolated expression
+    Spec,               // Format spec of an interpolated expression, from : to }
+    Sep(Sep),           // Any separator
+    Err,                // Unsupported char
+    Eof,                // End of file
 }
 
 /// A code token
@@ -58,20 +70,14 @@ impl<'a> Token<'a> {
     }
 
     // Currently used for end string missing " error, this is a design smell and should be removed
-    pub fn after(&self) -> Token {
+    pub fn after(&self) -> Token<'a> {
         Token::new(self.source, self.kind, self.span.end..self.span.end + 1)
     }
 
-    pub fn err_there(&self, err: &str) -> String {
-        format!(
-            "{}\n{}\n{:>4$}{:^>5$}",
-            err,
-            self.source,
-            "",
-            "^",
-            self.span.start,
-            self.span.len()
-        )
+    /** An error pointing at the token */
+    pub fn err_there(&self, err: &str) -> Error {
+        let len = self.source.len();
+        Error::at(err, self.span.start.min(len)..self.span.end.min(len))
     }
 }
 
@@ -80,16 +86,35 @@ pub struct Lexer<'a> {
     source: &'a str,
     offset: usize,
     peeked: Option<Token<'a>>,
+    // Number of interpolated expressions we are in
+    depth: usize,
 }
 
 impl<'a> Lexer<'a> {
     /** Init the lexer at the beginning of a source */
-    pub fn load(source: &'a str) -> Lexer {
+    pub fn load(source: &'a str) -> Lexer<'a> {
         Lexer {
             source,
             offset: 0,
             peeked: None,
+            depth: 0,
+        }
+    }
+
+    /** Search the end of a string part, returning its end and if it opens an interpolation.
+    A doubled brace is an escaped one. */
+    fn scan_str(&self, from: usize) -> (usize, bool) {
+        let bytes = self.source.as_bytes();
+        let mut i = from;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'"' => return (i + 1, false),
+                b'{' if bytes.get(i + 1) == Some(&b'{') => i += 2,
+                b'{' => return (i + 1, true),
+                _ => i += 1,
+            }
         }
+        (bytes.len(), false)
     }
 
     /** Lex the next token */
@@ -104,23 +129,38 @@ impl<'a> Lexer<'a> {
             let start = self.offset + i;
             let uni_range = start..start + 1;
             match c {
-                '+' => ((TokenKind::Op(Op::Add), uni_range)),
-                '-' => ((TokenKind::Op(Op::Sub), uni_range)),
-                '*' => ((TokenKind::Op(Op::Mul), uni_range)),
-                '/' => ((TokenKind::Op(Op::Div), uni_range)),
-                '%' => ((TokenKind::Op(Op::Mod), uni_range)),
-                '=' => ((TokenKind::Op(Op::Eq), uni_range)),
-                '^' => ((TokenKind::Op(Op::Pow), uni_range)),
-                '(' => ((TokenKind::Sep(Sep::Open), uni_range)),
-                ')' => ((TokenKind::Sep(Sep::Close), uni_range)),
-                '#' => ((TokenKind::Sep(Sep::Comment), uni_range)),
-                '"' => {
-                    // Search next "
+                '+' => (TokenKind::Op(Op::Add), uni_range),
+                '-' => (TokenKind::Op(Op::Sub), uni_range),
+                '*' => (TokenKind::Op(Op::Mul), uni_range),
+                '/' => (TokenKind::Op(Op::Div), uni_range),
+                '%' => (TokenKind::Op(Op::Mod), uni_range),
+                '=' => (TokenKind::Op(Op::Eq), uni_range),
+                '^' => (TokenKind::Op(Op::Pow), uni_range),
+                '(' => (TokenKind::Sep(Sep::Open), uni_range),
+                ')' => (TokenKind::Sep(Sep::Close), uni_range),
+                ',' => (TokenKind::Sep(Sep::Comma), uni_range),
+                '#' => (TokenKind::Sep(Sep::Comment), uni_range),
+                ';' => (TokenKind::Sep(Sep::Semi), uni_range),
+                '"' => match self.scan_str(start + 1) {
+                    (end, true) => {
+                        self.depth += 1;
+                        (TokenKind::Template(Template::Start), start..end)
+                    }
+                    (end, false) => (TokenKind::Str, start..end),
+                },
+                '}' if self.depth > 0 => match self.scan_str(start + 1) {
+                    (end, true) => (TokenKind::Template(Template::Middle), start..end),
+                    (end, false) => {
+                        self.depth -= 1;
+                        (TokenKind::Template(Template::End), start..end)
+                    }
+                },
+                ':' if self.depth > 0 => {
                     let end = chars
-                        .find(|(_, c)| *c == '"')
-                        .map(|(i, _)| i + self.offset + 1)
+                        .find(|(_, c)| *c == '}')
+                        .map(|(i, _)| i + self.offset)
                         .unwrap_or(self.source.len());
-                    (TokenKind::Str, start..end)
+                    (TokenKind::Spec, start..end)
                 }
                 c if c.is_ascii_digit() => {
                     let mut chars = chars.skip_while(|(_, c)| c.is_ascii_digit());
@@ -149,7 +189,7 @@ impl<'a> Lexer<'a> {
                         .unwrap_or(self.source.len());
                     (TokenKind::Id, start..end)
                 }
-                _ => ((TokenKind::Err, start..self.source.len())),
+                _ => (TokenKind::Err, start..self.source.len()),
             }
         } else {
             // No more token
@@ -157,7 +197,7 @@ impl<'a> Lexer<'a> {
             (TokenKind::Eof, len..len)
         };
         self.offset = range.end; // Move forward
-        return Token::new(self.source, kind, range);
+        Token::new(self.source, kind, range)
     }
 
     /** Return the next token moving forward */
@@ -167,15 +207,9 @@ impl<'a> Lexer<'a> {
 
     /** Return the next token without moving */
     pub fn peek(&mut self) -> &Token<'a> {
-        if let None = self.peeked {
+        if self.peeked.is_none() {
             self.peeked = Some(self.lex_next());
         }
         self.peeked.as_ref().unwrap()
     }
-
-    /** Return at the beginning of the source */
-    pub fn reset(&mut self) {
-        self.offset = 0;
-        self.peeked = None;
-    }
 }
@@ -1,4 +1,23 @@
+pub mod bytecode;
+pub mod check;
+pub mod cst;
+mod decimal;
+pub mod document;
+pub mod format;
+pub mod formatter;
 pub mod highlighter;
 pub mod interpreter;
 mod lexer;
+mod linear;
+pub mod lint;
+mod numeric;
+mod optimizer;
 mod parser;
+pub mod query;
+mod reactive;
+pub mod rename;
+pub mod report;
+mod resolve;
+mod symbolic;
+#[cfg(test)]
+mod testing;
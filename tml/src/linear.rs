@@ -0,0 +1,158 @@
+use crate::{
+    interpreter::{compute_literal, Context},
+    parser::{BinOp, Literal, UnOp},
+    symbolic::depends,
+};
+
+/* Systems of linear equations. Each equation is reduced to a linear form, the coefficients
+of the unknowns and a constant, then the system is solved by Gaussian elimination. */
+
+/** A linear combination of the unknowns plus a constant */
+struct Linear {
+    coefs: Vec<f64>,
+    constant: f64,
+}
+
+impl Linear {
+    fn constant(len: usize, constant: f64) -> Self {
+        Self {
+            coefs: vec![0.; len],
+            constant,
+        }
+    }
+
+    fn unknown(len: usize, idx: usize) -> Self {
+        let mut linear = Self::constant(len, 0.);
+        linear.coefs[idx] = 1.;
+        linear
+    }
+
+    fn is_constant(&self) -> bool {
+        self.coefs.iter().all(|coef| *coef == 0.)
+    }
+
+    fn scale(mut self, factor: f64) -> Self {
+        self.coefs.iter_mut().for_each(|coef| *coef *= factor);
+        self.constant *= factor;
+        self
+    }
+
+    fn add(mut self, other: Self, factor: f64) -> Self {
+        for (coef, other) in self.coefs.iter_mut().zip(other.coefs) {
+            *coef += factor * other;
+        }
+        self.constant += factor * other.constant;
+        self
+    }
+}
+
+/** Reduce a literal to a linear form of the unknowns, other variables being evaluated */
+fn linear(ctx: &mut Context, lit: &Literal, unknowns: &[&str]) -> Result<Linear, String> {
+    let len = unknowns.len();
+    if !unknowns.iter().any(|x| depends(lit, x)) {
+        return Ok(Linear::constant(len, compute_literal(ctx, lit)?));
+    }
+    let not_linear = || {
+        Err(format!(
+            "'{}' is not linear in {}",
+            lit,
+            unknowns.join(", ")
+        ))
+    };
+    Ok(match lit {
+        // The variable is an unknown as the literal depends on it
+        Literal::Var(id) => Linear::unknown(len, unknowns.iter().position(|x| x == id).unwrap()),
+        Literal::UnaryOp(op, hs) => match op {
+            UnOp::Add => linear(ctx, hs, unknowns)?,
+            UnOp::Sub => linear(ctx, hs, unknowns)?.scale(-1.),
+        },
+        Literal::BinaryOp(op, lits) => {
+            let l = linear(ctx, &lits.0, unknowns)?;
+            let r = linear(ctx, &lits.1, unknowns)?;
+            match op {
+                BinOp::Add => l.add(r, 1.),
+                BinOp::Sub => l.add(r, -1.),
+                BinOp::Mul if l.is_constant() => r.scale(l.constant),
+                BinOp::Mul if r.is_constant() => l.scale(r.constant),
+                BinOp::Div if r.is_constant() => l.scale(1. / r.constant),
+                _ => return not_linear(),
+            }
+        }
+        _ => return not_linear(),
+    })
+}
+
+/** Solve a system of linear equations, returning the value of each unknown */
+pub fn solve_system(
+    ctx: &mut Context,
+    equations: &[(Literal, Literal)],
+    unknowns: &[&str],
+) -> Result<Vec<f64>, String> {
+    let (m, n) = (equations.len(), unknowns.len());
+    if m < n {
+        return Err(format!(
+            "Underdetermined system, {} equation(s) for {} unknowns",
+            m, n
+        ));
+    }
+
+    // Build the augmented matrix, moving constants to the right side
+    let mut rows = Vec::with_capacity(m);
+    for (i, (lhs, rhs)) in equations.iter().enumerate() {
+        let form = linear(ctx, lhs, unknowns)
+            .and_then(|lhs| Ok(lhs.add(linear(ctx, rhs, unknowns)?, -1.)))
+            .map_err(|err| format!("Equation {}: {}", i + 1, err))?;
+        let mut row = form.coefs;
+        row.push(-form.constant);
+        if row.iter().any(|nb| !nb.is_finite()) {
+            return Err(format!("Equation {}: coefficients must be finite", i + 1));
+        }
+        rows.push(row);
+    }
+    let max = rows
+        .iter()
+        .flatten()
+        .fold(0f64, |max, nb| max.max(nb.abs()));
+    let tolerance = 1e-12 * max.max(1.);
+
+    // Gaussian elimination with partial pivoting
+    let mut rank = 0;
+    let mut pivots = Vec::with_capacity(n);
+    for col in 0..n {
+        let pivot = (rank..m)
+            .max_by(|a, b| rows[*a][col].abs().total_cmp(&rows[*b][col].abs()))
+            .filter(|row| rows[*row][col].abs() > tolerance);
+        let pivot = match pivot {
+            Some(pivot) => pivot,
+            None => continue,
+        };
+        rows.swap(rank, pivot);
+        let (top, bottom) = rows.split_at_mut(rank + 1);
+        let pivot_row = &top[rank];
+        for row in bottom {
+            let factor = row[col] / pivot_row[col];
+            for (nb, pivot_nb) in row.iter_mut().zip(pivot_row).skip(col) {
+                *nb -= factor * pivot_nb;
+            }
+        }
+        pivots.push(col);
+        rank += 1;
+    }
+    if rows[rank..].iter().any(|row| row[n].abs() > tolerance) {
+        return Err("Inconsistent system, the equations have no common solution".into());
+    }
+    if rank < n {
+        return Err(format!(
+            "Singular system, only {} independent equation(s) for {} unknowns",
+            rank, n
+        ));
+    }
+
+    // Back substitution, every column has a pivot
+    let mut solution = vec![0.; n];
+    for (row, col) in pivots.iter().enumerate().rev() {
+        let sum: f64 = (col + 1..n).map(|k| rows[row][k] * solution[k]).sum();
+        solution[*col] = (rows[row][n] - sum) / rows[row][*col];
+    }
+    Ok(solution)
+}
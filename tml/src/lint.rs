@@ -0,0 +1,315 @@
+use crate::{
+    cst::{parse_cst, Element, LeafKind, Node, NodeKind},
+    interpreter::{evaluate, function, is_builtin, Context},
+    resolve::{resolve_tree, Occurrence, Role, Symbol},
+};
+use std::ops::Range;
+
+/* Lint pass over a document, warning about code that is valid but probably wrong. Nothing is
+computed except the constant arguments of builtin calls. A lint is suppressed for the next
+statement by a comment line such as `# allow(unused-variable, division-by-zero)`, or for the
+whole document by the caller. */
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Lint {
+    UnusedVariable,   // Assigned or bound but never read
+    UnusedAssignment, // Assigned again before being read
+    DivisionByZero,   // Divided by a literal zero
+    ShadowedBuiltin,  // Local binding named as a builtin function
+    NanCall,          // Builtin returning NaN or an infinity for a constant argument
+    UnusedValue,      // Value computed before another statement of its line, which ignores it
+}
+
+impl Lint {
+    pub const ALL: [Lint; 6] = [
+        Lint::UnusedVariable,
+        Lint::UnusedAssignment,
+        Lint::DivisionByZero,
+        Lint::ShadowedBuiltin,
+        Lint::NanCall,
+        Lint::UnusedValue,
+    ];
+
+    /** Identifier used to suppress the lint */
+    pub fn id(self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedAssignment => "unused-assignment",
+            Lint::DivisionByZero => "division-by-zero",
+            Lint::ShadowedBuiltin => "shadowed-builtin",
+            Lint::NanCall => "nan-call",
+            Lint::UnusedValue => "unused-value",
+        }
+    }
+
+    pub fn from_id(id: &str) -> Option<Lint> {
+        Lint::ALL.into_iter().find(|lint| lint.id() == id)
+    }
+}
+
+/** A lint warning on a span of the source */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub lint: Lint,
+    pub message: String,
+    pub span: Range<usize>,
+}
+
+/** Lints allowed by a comment line, None if it is not an allow comment */
+fn allowed(comment: &str) -> Option<Vec<Lint>> {
+    let ids = comment
+        .trim_start_matches('#')
+        .trim()
+        .strip_prefix("allow(")?
+        .strip_suffix(')')?;
+    Some(
+        ids.split(',')
+            .filter_map(|id| Lint::from_id(id.trim()))
+            .collect(),
+    )
+}
+
+/** Warn about the variables never read after being written */
+fn lint_variables(source: &str, occurrences: &[Occurrence], warnings: &mut Vec<Warning>) {
+    for (idx, write) in occurrences.iter().enumerate() {
+        let id = &source[write.span.clone()];
+        let is_read = |occurrence: &&Occurrence| {
+            occurrence.symbol == write.symbol && occurrence.role == Role::Read
+        };
+        let unused = match (write.role, write.symbol) {
+            // A where binding is read before it in the source
+            (Role::Bind, _) if !occurrences.iter().any(|o| is_read(&o)) => Some((
+                Lint::UnusedVariable,
+                format!("'{}' is bound but never used", id),
+            )),
+            (Role::Bind, _) => None,
+            // The value of an assignment is read by the next statements, an assignment reading
+            // the variable reads its previous value
+            (Role::Write, Symbol::Global(_)) => {
+                let next = occurrences[idx..].iter().find(|occurrence| {
+                    occurrence.symbol == write.symbol
+                        && occurrence.role == Role::Write
+                        && occurrence.statement > write.statement
+                });
+                let is_used = occurrences.iter().filter(is_read).any(|read| {
+                    read.statement > write.statement
+                        && next.is_none_or(|next| read.statement <= next.statement)
+                });
+                match next {
+                    _ if is_used => None,
+                    Some(_) => Some((
+                        Lint::UnusedAssignment,
+                        format!("'{}' is assigned again before being read", id),
+                    )),
+                    None => Some((
+                        Lint::UnusedVariable,
+                        format!("'{}' is assigned but never used", id),
+                    )),
+                }
+            }
+            _ => continue,
+        };
+        if let Some((lint, message)) = unused {
+            warnings.push(Warning {
+                lint,
+                message,
+                span: write.span.clone(),
+            });
+        }
+        // Assigning a builtin is an error, see `check`
+        if write.role == Role::Bind && is_builtin(id) {
+            warnings.push(Warning {
+                lint: Lint::ShadowedBuiltin,
+                message: format!("'{}' has the name of a builtin function", id),
+                span: write.span.clone(),
+            });
+        }
+    }
+}
+
+/** Check if a node is a literal zero, maybe in parentheses */
+fn is_zero(node: &Node, source: &str) -> bool {
+    match node.kind() {
+        NodeKind::Nb => node.text(source).parse::<f64>() == Ok(0.),
+        NodeKind::Paren => node
+            .nodes()
+            .next()
+            .is_some_and(|node| is_zero(node, source)),
+        _ => false,
+    }
+}
+
+/** Warn about the expressions of a node and its children */
+fn lint_node(node: &Node, source: &str, warnings: &mut Vec<Warning>) {
+    let children: Vec<_> = node.nodes().collect();
+    match node.kind() {
+        NodeKind::Binary => {
+            let op = node.children().iter().find_map(|child| match child {
+                Element::Leaf(leaf) if leaf.kind() == LeafKind::Op => Some(leaf.text(source)),
+                _ => None,
+            });
+            if let (Some("/" | "%"), [_, rhs]) = (op, children.as_slice()) {
+                if is_zero(rhs, source) {
+                    warnings.push(Warning {
+                        lint: Lint::DivisionByZero,
+                        message: "Division by zero".into(),
+                        span: node.span(),
+                    });
+                }
+            }
+        }
+        NodeKind::Call => {
+            let name = node.leaves()[0].text(source);
+            if let (Some(fun), [arg]) = (function(name), children.as_slice()) {
+                // The argument is constant if it computes without any variable
+                let value = evaluate(&mut Context::empty(), arg.text(source))
+                    .ok()
+                    .and_then(|outcome| outcome.value)
+                    .and_then(|value| value.as_f64());
+                if let Some(result) = value.map(fun).filter(|result| !result.is_finite()) {
+                    warnings.push(Warning {
+                        lint: Lint::NanCall,
+                        message: format!("'{}' returns {}", node.text(source), result),
+                        span: node.span(),
+                    });
+                }
+            }
+        }
+        // The value of a statement followed by another one is lost, unless it is read by $
+        NodeKind::Line => {
+            for pair in children.windows(2) {
+                let is_value = !matches!(
+                    pair[0].kind(),
+                    NodeKind::Assign
+                        | NodeKind::Const
+                        | NodeKind::Solve
+                        | NodeKind::Print
+                        | NodeKind::Equation
+                        | NodeKind::Error
+                );
+                let reads_last = pair[1].leaves().iter().any(|leaf| leaf.text(source) == "$");
+                if is_value && !reads_last {
+                    warnings.push(Warning {
+                        lint: Lint::UnusedValue,
+                        message: format!("The value of '{}' is never used", pair[0].text(source)),
+                        span: pair[0].span(),
+                    });
+                }
+            }
+        }
+        _ => {}
+    }
+    for child in children {
+        lint_node(child, source, warnings);
+    }
+}
+
+/** Lint a document, except the lints allowed everywhere, returning warnings in source order */
+pub fn lint(source: &str, allow: &[Lint]) -> Vec<Warning> {
+    let document = parse_cst(source);
+    let occurrences = resolve_tree(source, &document);
+    let mut warnings = Vec::new();
+    lint_variables(source, &occurrences, &mut warnings);
+    lint_node(&document, source, &mut warnings);
+
+    // Lints allowed by a comment apply to the next statement
+    let mut suppressed: Vec<(Range<usize>, Vec<Lint>)> = Vec::new();
+    let mut pending = Vec::new();
+    for line in document.nodes() {
+        let leaves = line.leaves();
+        match leaves
+            .iter()
+            .find(|leaf| leaf.kind() != LeafKind::Whitespace)
+        {
+            None => {}
+            Some(leaf) if leaf.kind() == LeafKind::Comment => {
+                pending.extend(allowed(leaf.text(source)).unwrap_or_default())
+            }
+            Some(_) => suppressed.push((line.span(), std::mem::take(&mut pending))),
+        }
+    }
+    warnings.retain(|warning| {
+        let is_suppressed = suppressed.iter().any(|(span, lints)| {
+            span.contains(&warning.span.start) && lints.contains(&warning.lint)
+        });
+        !allow.contains(&warning.lint) && !is_suppressed
+    });
+    warnings.sort_by_key(|warning| (warning.span.start, warning.lint));
+    warnings
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /** Lint a source, listing the lints with the text they point at */
+    fn lints(source: &str) -> Vec<(Lint, &str)> {
+        lint(source, &[])
+            .into_iter()
+            .map(|warning| (warning.lint, &source[warning.span]))
+            .collect()
+    }
+
+    #[test]
+    fn test_lint_variables() {
+        assert_eq!(lints("a = 1\nb = a*2\nb"), []);
+        assert_eq!(lints("a = 1\nb = 2\nb"), [(Lint::UnusedVariable, "a")]);
+        assert_eq!(lints("a = 1\na = 2\na"), [(Lint::UnusedAssignment, "a")]);
+        // Reading the previous value while assigning it again
+        assert_eq!(lints("a = 1\na = a + 1\na"), []);
+        assert_eq!(lints("a = 1; a"), []);
+        assert_eq!(
+            lints("x = let k = 2 in 3\nx"),
+            [(Lint::UnusedVariable, "k")]
+        );
+        assert_eq!(lints("x = y where y = 2\nx"), []);
+        assert_eq!(lints("sqrt = 2\nsqrt"), []);
+        assert_eq!(
+            lints("y = let ln = 1 in ln\ny"),
+            [(Lint::ShadowedBuiltin, "ln")]
+        );
+        assert_eq!(
+            lints("integrate(exp^2, exp, 0, 1)"),
+            [(Lint::ShadowedBuiltin, "exp")]
+        );
+        assert_eq!(lints("a + b = 3\na - b = 1\nsolve a, b\na + b"), []);
+    }
+
+    #[test]
+    fn test_lint_expressions() {
+        assert_eq!(
+            lints("x = 1\nx/0 + x%(0.0) + x/0.5"),
+            [
+                (Lint::DivisionByZero, "x/0"),
+                (Lint::DivisionByZero, "x%(0.0)")
+            ]
+        );
+        assert_eq!(
+            lints("sqrt(-1) + ln(0) + ln(2*PI) + acos(2)"),
+            [
+                (Lint::NanCall, "sqrt(-1)"),
+                (Lint::NanCall, "ln(0)"),
+                (Lint::NanCall, "acos(2)")
+            ]
+        );
+        assert_eq!(lints("x = -1\nsqrt(x)"), []);
+        assert_eq!(lints("1 + 2; 3; $ + 1"), [(Lint::UnusedValue, "1 + 2")]);
+        assert_eq!(lints("x = 1; \"{x}\"; x + 1"), []);
+    }
+
+    #[test]
+    fn test_allow() {
+        let source = "# allow(unused-variable, nan-call)\na = sqrt(-1)\n\nb = 1";
+        assert_eq!(lints(source), [(Lint::UnusedVariable, "b")]);
+        let source = "# allow(unused-variable)\n\n# Unused\nb = 1\nc = 1";
+        assert_eq!(lints(source), [(Lint::UnusedVariable, "c")]);
+        assert_eq!(lint("a = 1\nb = 1/0", &[Lint::UnusedVariable]).len(), 1);
+        assert_eq!(
+            Lint::from_id("division-by-zero"),
+            Some(Lint::DivisionByZero)
+        );
+        for lint in Lint::ALL {
+            assert_eq!(Lint::from_id(lint.id()), Some(lint));
+        }
+    }
+}
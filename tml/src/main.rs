@@ -1,15 +1,32 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, io::Read};
 
-use rustyline::{highlight::Highlighter, Config, Editor};
-use rustyline_derive::{Completer, Helper, Hinter, Validator};
+use rustyline::{
+    highlight::Highlighter,
+    validate::{ValidationContext, ValidationResult, Validator},
+    Config, Editor,
+};
+use rustyline_derive::{Completer, Helper, Hinter};
 use tml::{
+    check::check,
+    formatter::format_source,
     highlighter::{self, AnsiHighlighter},
-    interpreter::{compute, Context},
+    interpreter::{compute, is_incomplete, Context},
 };
 
-#[derive(Helper, Completer, Hinter, Validator)]
+#[derive(Helper, Completer, Hinter)]
 struct TmlHelper;
 
+impl Validator for TmlHelper {
+    // Continue reading lines while the statement is incomplete
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(if is_incomplete(ctx.input()) {
+            ValidationResult::Incomplete
+        } else {
+            ValidationResult::Valid(None)
+        })
+    }
+}
+
 impl Highlighter for TmlHelper {
     fn highlight<'l>(&self, line: &'l str, _: usize) -> std::borrow::Cow<'l, str> {
         let mut buf = String::new();
@@ -22,36 +39,154 @@ impl Highlighter for TmlHelper {
     }
 }
 
+/** Set an option of the context settings */
+fn set_option(ctx: &mut Context, option: &str, value: &str) -> Result<(), String> {
+    let mut settings = ctx.settings();
+    settings.set(option, value)?;
+    ctx.set_settings(settings);
+    Ok(())
+}
+
+/** Format files in place, or stdin to stdout without files. With '--check' nothing is
+written, and the exit code is 1 if a source is not formatted. */
+fn format_command(args: impl Iterator<Item = String>) -> i32 {
+    let (mut check, mut files) = (false, Vec::new());
+    for arg in args {
+        match arg.as_str() {
+            "--check" => check = true,
+            _ => files.push(arg),
+        }
+    }
+    if files.is_empty() {
+        let mut source = String::new();
+        if let Err(err) = std::io::stdin().read_to_string(&mut source) {
+            eprintln!("\x1b[0;31m{}\x1b[0m", err);
+            return 2;
+        }
+        let formatted = format_source(&source);
+        if check {
+            return (formatted != source) as i32;
+        }
+        print!("{}", formatted);
+        return 0;
+    }
+    let mut code = 0;
+    for file in files {
+        let source = match std::fs::read_to_string(&file) {
+            Ok(source) => source,
+            Err(err) => {
+                eprintln!("\x1b[0;31m{}: {}\x1b[0m", file, err);
+                code = 2;
+                continue;
+            }
+        };
+        let formatted = format_source(&source);
+        if formatted == source {
+            continue;
+        }
+        if check {
+            println!("{} is not formatted", file);
+            code = code.max(1);
+        } else if let Err(err) = std::fs::write(&file, formatted) {
+            eprintln!("\x1b[0;31m{}: {}\x1b[0m", file, err);
+            code = 2;
+        }
+    }
+    code
+}
+
+/** Check files, or stdin without files, printing every problem as 'file:line:column: message'.
+Nothing is computed, the exit code is 1 if a problem is found. */
+fn check_command(args: impl Iterator<Item = String>) -> i32 {
+    let mut sources = Vec::new();
+    let files: Vec<_> = args.collect();
+    if files.is_empty() {
+        let mut source = String::new();
+        if let Err(err) = std::io::stdin().read_to_string(&mut source) {
+            eprintln!("\x1b[0;31m{}\x1b[0m", err);
+            return 2;
+        }
+        sources.push(("<stdin>".to_string(), source));
+    }
+    let mut code = 0;
+    for file in files {
+        match std::fs::read_to_string(&file) {
+            Ok(source) => sources.push((file, source)),
+            Err(err) => {
+                eprintln!("\x1b[0;31m{}: {}\x1b[0m", file, err);
+                code = 2;
+            }
+        }
+    }
+    for (file, source) in sources {
+        for problem in check(&source) {
+            let before = &source[..problem.span.start];
+            let line = before.matches('\n').count() + 1;
+            let column = before[before.rfind('\n').map_or(0, |i| i + 1)..]
+                .chars()
+                .count()
+                + 1;
+            println!("{}:{}:{}: {}", file, line, column, problem.message);
+            code = code.max(1);
+        }
+    }
+    code
+}
+
 fn main() {
-    let args: String = std::env::args()
-        .skip(1)
-        .fold(String::new(), |buf, elem| buf + " " + &elem);
+    let mut ctx = Context::empty();
+    let mut args = std::env::args().skip(1).peekable();
+    // 'tml fmt [--check] [files]' formats sources
+    if args.next_if(|arg| arg == "fmt").is_some() {
+        std::process::exit(format_command(args));
+    }
+    // 'tml check [files]' reports the problems of sources without running them
+    if args.next_if(|arg| arg == "check").is_some() {
+        std::process::exit(check_command(args));
+    }
+    // Leading '--option value' pairs set the settings
+    while let Some(option) = args.next_if(|arg| arg.starts_with("--")) {
+        let value = args.next().unwrap_or_default();
+        if let Err(err) = set_option(&mut ctx, &option[2..], &value) {
+            println!("\x1b[0;31m{}\x1b[0m", err);
+            return;
+        }
+    }
+    let args: String = args.fold(String::new(), |buf, elem| buf + " " + &elem);
     if args.is_empty() {
         // Start repl
         let config = Config::builder().auto_add_history(true).build();
         let mut editor = Editor::with_config(config);
         editor.set_helper(Some(TmlHelper));
-        let mut ctx = Context::empty();
-        loop {
-            match editor.readline("> ") {
-                Ok(line) => match compute(&mut ctx, &line) {
-                    Ok(result) => {
-                        if !result.is_empty() {
-                            let mut buf = String::new();
-                            highlighter::highlight(&mut buf, &result, AnsiHighlighter).unwrap();
-                            println!("{}", buf);
-                        }
+        while let Ok(line) = editor.readline("> ") {
+            // ':option value' sets the settings
+            if let Some(command) = line.trim().strip_prefix(':') {
+                // ':constants' lists the constants
+                if command == "constants" {
+                    for (id, value) in ctx.constants() {
+                        println!("{} = {}", id, value.to_code());
+                    }
+                    continue;
+                }
+                let (option, value) = command.split_once(' ').unwrap_or((command, ""));
+                if let Err(err) = set_option(&mut ctx, option, value.trim()) {
+                    println!("\x1b[0;31m{}\x1b[0m", err);
+                }
+                continue;
+            }
+            match compute(&mut ctx, &line) {
+                Ok(result) => {
+                    if !result.is_empty() {
+                        let mut buf = String::new();
+                        highlighter::highlight(&mut buf, &result, AnsiHighlighter).unwrap();
+                        println!("{}", buf);
                     }
-                    Err(err) => println!("\x1b[0;31m{}\x1b[0m", err),
-                },
-                Err(_) => {
-                    break;
                 }
+                Err(err) => println!("\x1b[0;31m{}\x1b[0m", err),
             }
         }
     } else {
         // Execute single line
-        let mut ctx = Context::empty();
         match compute(&mut ctx, &args) {
             Ok(result) => {
                 if !result.is_empty() {
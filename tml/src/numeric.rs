@@ -0,0 +1,349 @@
+use crate::{
+    bytecode::{compile_literal, Program},
+    interpreter::{compute_literal, Context},
+    parser::{BinOp, Literal},
+    report::Error,
+    symbolic,
+};
+
+/* Numerical analysis over literals. The literal is re-evaluated for each sample with the
+variable bound to the sample value, by its compiled program when it can be compiled as the
+program gives the same values faster. */
+
+/** Absolute tolerance of the integral */
+const TOLERANCE: f64 = 1e-10;
+/** Relative tolerance of the integral, for large values */
+const RELATIVE_TOLERANCE: f64 = 1e-12;
+/** Maximum recursion depth of the adaptive quadrature */
+const MAX_DEPTH: u32 = 50;
+/** Maximum number of evaluations of the integrand */
+const MAX_EVALS: usize = 1_000_000;
+/** Maximum number of Newton iterations */
+const MAX_ITER: usize = 100;
+/** Number of samples used to search sign changes in an interval */
+const SAMPLES: usize = 1000;
+/** Maximum number of roots found in an interval */
+const MAX_ROOTS: usize = 20;
+
+/** A literal sampled over a variable */
+struct Function<'a, 'b> {
+    lit: &'b Literal<'a>,
+    x: &'b str,
+    // The literal compiled when possible
+    program: Option<Program>,
+}
+
+impl<'a, 'b> Function<'a, 'b> {
+    fn new(ctx: &Context, lit: &'b Literal<'a>, x: &'b str) -> Self {
+        Function {
+            lit,
+            x,
+            program: compile_literal(ctx, lit, &[x]).ok(),
+        }
+    }
+
+    /** Evaluate the literal with x bound to a value */
+    fn at(&self, ctx: &mut Context, at: f64) -> Result<f64, Error> {
+        match &self.program {
+            Some(program) => Ok(program.run(&[at])?),
+            None => ctx.with_var(self.x, at, |ctx| compute_literal(ctx, self.lit)),
+        }
+    }
+}
+
+/** A definite integral result */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Integral {
+    pub value: f64,
+    pub error: f64,
+}
+
+struct Quadrature<'a, 'b> {
+    ctx: &'b mut Context,
+    f: Function<'a, 'b>,
+    evals: usize,
+    error: f64,
+}
+
+impl Quadrature<'_, '_> {
+    fn eval(&mut self, at: f64) -> Result<f64, Error> {
+        self.evals += 1;
+        if self.evals > MAX_EVALS {
+            return Err(
+                format!("Integral did not converge after {} evaluations", MAX_EVALS).into(),
+            );
+        }
+        let nb = self.f.at(self.ctx, at)?;
+        if nb.is_finite() {
+            Ok(nb)
+        } else {
+            Err(format!(
+                "Improper integral, the integrand is {:e} at {} = {:e}",
+                nb, self.f.x, at
+            )
+            .into())
+        }
+    }
+
+    /** Simpson's rule over [a, b], returning the middle sample and the estimate */
+    fn simpson(&mut self, a: f64, fa: f64, b: f64, fb: f64) -> Result<(f64, f64, f64), Error> {
+        let m = (a + b) / 2.;
+        let fm = self.eval(m)?;
+        Ok((m, fm, (b - a) / 6. * (fa + 4. * fm + fb)))
+    }
+
+    /** Adaptive Simpson's rule, refine each half until its estimate is stable */
+    fn adapt(
+        &mut self,
+        (a, fa): (f64, f64),
+        (m, fm): (f64, f64),
+        (b, fb): (f64, f64),
+        whole: f64,
+        tolerance: f64,
+        depth: u32,
+    ) -> Result<f64, Error> {
+        let (lm, flm, left) = self.simpson(a, fa, m, fm)?;
+        let (rm, frm, right) = self.simpson(m, fm, b, fb)?;
+        let delta = left + right - whole;
+        if delta.abs() <= 15. * tolerance.max(RELATIVE_TOLERANCE * (left + right).abs()) {
+            self.error += delta.abs() / 15.;
+            return Ok(left + right + delta / 15.);
+        }
+        if depth == 0 || m <= a || m >= b {
+            return Err(format!(
+                "Integral did not converge around {} = {}, estimated error is {:e}",
+                self.f.x,
+                m,
+                delta.abs() / 15.
+            )
+            .into());
+        }
+        Ok(
+            self.adapt((a, fa), (lm, flm), (m, fm), left, tolerance / 2., depth - 1)?
+                + self.adapt(
+                    (m, fm),
+                    (rm, frm),
+                    (b, fb),
+                    right,
+                    tolerance / 2.,
+                    depth - 1,
+                )?,
+        )
+    }
+}
+
+/** Compute the definite integral of a literal over x from a to b */
+pub fn integrate(
+    ctx: &mut Context,
+    lit: &Literal,
+    x: &str,
+    a: f64,
+    b: f64,
+) -> Result<Integral, Error> {
+    if !a.is_finite() || !b.is_finite() {
+        return Err(format!(
+            "Improper integral, bounds must be finite but got {:e} and {:e}",
+            a, b
+        )
+        .into());
+    }
+    if a > b {
+        return integrate(ctx, lit, x, b, a).map(|it| Integral {
+            value: -it.value,
+            error: it.error,
+        });
+    }
+    let mut quad = Quadrature {
+        f: Function::new(ctx, lit, x),
+        ctx,
+        evals: 0,
+        error: 0.,
+    };
+    // Start from a few intervals so periodic integrands are not missed by the first samples
+    const INTERVALS: usize = 4;
+    let step = (b - a) / INTERVALS as f64;
+    let mut value = 0.;
+    let mut start = (a, quad.eval(a)?);
+    for i in 1..=INTERVALS {
+        let end = if i == INTERVALS {
+            b
+        } else {
+            a + step * i as f64
+        };
+        let end = (end, quad.eval(end)?);
+        let (m, fm, whole) = quad.simpson(start.0, start.1, end.0, end.1)?;
+        value += quad.adapt(
+            start,
+            (m, fm),
+            end,
+            whole,
+            TOLERANCE / INTERVALS as f64,
+            MAX_DEPTH,
+        )?;
+        start = end;
+    }
+    Ok(Integral {
+        value,
+        error: quad.error,
+    })
+}
+
+/** Transform an equation into a literal that is zero at its roots */
+pub fn residual<'a>(eq: &Literal<'a>) -> Literal<'a> {
+    match eq {
+        Literal::Equation(sides) => Literal::BinaryOp(BinOp::Sub, sides.clone()),
+        lit => lit.clone(),
+    }
+}
+
+/** Check a candidate root, rejecting poles where the sign also changes */
+fn is_root(fx: f64, scale: f64) -> bool {
+    fx.is_finite() && fx.abs() <= 1e-9 * scale.max(1.)
+}
+
+/** Find a root of f near a guess, using Newton's method then bracketing as a fallback */
+pub fn find_root(ctx: &mut Context, f: &Literal, x: &str, guess: f64) -> Result<f64, Error> {
+    let f = Function::new(ctx, f, x);
+    let f_guess = f.at(ctx, guess)?;
+    if f_guess == 0. {
+        return Ok(guess);
+    }
+    let scale = if f_guess.is_finite() {
+        f_guess.abs()
+    } else {
+        1.
+    };
+    if let Some(root) = newton(ctx, &f, guess)? {
+        if is_root(f.at(ctx, root)?, scale) {
+            return Ok(root);
+        }
+    }
+
+    // Search a sign change on both sides of the guess with a growing step
+    let mut step = 0.1 * guess.abs().max(1.);
+    let (mut left, mut right) = ((guess, f_guess), (guess, f_guess));
+    for _ in 0..64 {
+        for (side, dir) in [(&mut right, 1.), (&mut left, -1.)] {
+            let next = guess + dir * step;
+            let f_next = f.at(ctx, next)?;
+            if f_next == 0. {
+                return Ok(next);
+            }
+            if let Some(root) = bisect(ctx, &f, *side, (next, f_next))? {
+                if is_root(f.at(ctx, root)?, scale) {
+                    return Ok(root);
+                }
+            }
+            *side = (next, f_next);
+        }
+        step *= 2.;
+    }
+    Err(format!("Failed to find a root near {} = {}", x, guess).into())
+}
+
+/** Find every root of f in [a, b] by searching sign changes */
+pub fn find_roots(
+    ctx: &mut Context,
+    f: &Literal,
+    x: &str,
+    a: f64,
+    b: f64,
+) -> Result<Vec<f64>, Error> {
+    if !a.is_finite() || !b.is_finite() {
+        return Err(format!("The interval must be finite but got {} and {}", a, b).into());
+    }
+    let (a, b) = if a > b { (b, a) } else { (a, b) };
+    let f = Function::new(ctx, f, x);
+    let step = (b - a) / SAMPLES as f64;
+    let mut roots: Vec<f64> = Vec::new();
+    let mut prev = (a, f.at(ctx, a)?);
+    for i in 1..=SAMPLES {
+        let at = if i == SAMPLES { b } else { a + step * i as f64 };
+        let next = (at, f.at(ctx, at)?);
+        let root = if prev.1 == 0. {
+            Some(prev.0)
+        } else if next.1 == 0. {
+            Some(next.0)
+        } else {
+            let scale = prev.1.abs().max(next.1.abs());
+            bisect(ctx, &f, prev, next)?
+                .filter(|root| matches!(f.at(ctx, *root), Ok(fx) if is_root(fx, scale)))
+        };
+        if let Some(root) = root {
+            if roots.last() != Some(&root) {
+                if roots.len() == MAX_ROOTS {
+                    return Err(format!(
+                        "Found more than {} roots, narrow the interval",
+                        MAX_ROOTS
+                    )
+                    .into());
+                }
+                roots.push(root);
+            }
+        }
+        prev = next;
+    }
+    Ok(roots)
+}
+
+/** Newton's method using the symbolic derivative, or a finite difference when f cannot be
+differentiated */
+fn newton(ctx: &mut Context, f: &Function, guess: f64) -> Result<Option<f64>, Error> {
+    let derivative = symbolic::diff(f.lit, f.x).ok();
+    let derivative = derivative
+        .as_ref()
+        .map(|derivative| Function::new(ctx, derivative, f.x));
+    let mut current = guess;
+    for _ in 0..MAX_ITER {
+        let fx = f.at(ctx, current)?;
+        if fx == 0. {
+            return Ok(Some(current));
+        }
+        let dfx = match &derivative {
+            Some(derivative) => derivative.at(ctx, current)?,
+            None => {
+                let h = 1e-7 * current.abs().max(1.);
+                (f.at(ctx, current + h)? - f.at(ctx, current - h)?) / (2. * h)
+            }
+        };
+        let next = current - fx / dfx;
+        if !next.is_finite() {
+            return Ok(None);
+        }
+        if (next - current).abs() <= 4. * f64::EPSILON * next.abs().max(1e-300) {
+            return Ok(Some(next));
+        }
+        current = next;
+    }
+    Ok(Some(current))
+}
+
+/** Bisect a bracket until the interval cannot shrink, None if it does not bracket a sign change */
+fn bisect(
+    ctx: &mut Context,
+    f: &Function,
+    (mut a, mut fa): (f64, f64),
+    (mut b, mut fb): (f64, f64),
+) -> Result<Option<f64>, Error> {
+    if !(fa.is_finite() && fb.is_finite()) || fa.signum() == fb.signum() {
+        return Ok(None);
+    }
+    loop {
+        let m = a + (b - a) / 2.;
+        if m == a || m == b {
+            return Ok(Some(if fa.abs() <= fb.abs() { a } else { b }));
+        }
+        let fm = f.at(ctx, m)?;
+        if fm == 0. {
+            return Ok(Some(m));
+        }
+        if fm.is_nan() {
+            return Ok(None);
+        }
+        if fm.signum() == fa.signum() {
+            (a, fa) = (m, fm);
+        } else {
+            (b, fb) = (m, fm);
+        }
+    }
+}
@@ -0,0 +1,242 @@
+use crate::{
+    interpreter::{compute_value, function, Context, Value, BINDING_FUNCTIONS},
+    parser::{BinOp, Fragment, Literal, UnOp},
+};
+
+/* Optimisation of an expression computed many times while a variable varies, as by integrate
+or solve. The parts that do not read the variable are computed once, which folds the constant
+parts, and identities are simplified only where IEEE arithmetic gives the same bits, like x*1 but
+not x + 0 which turns -0 into 0. Invariant parts are computed by the interpreter itself, so the
+result of the expression is unchanged. Decimal numbers are not optimised, as a decimal cannot be
+stored in a literal. */
+
+/** Check if a literal reads one of the names */
+fn reads(lit: &Literal, names: &[&str]) -> bool {
+    match lit {
+        Literal::Nb(..) | Literal::Str(_) => false,
+        Literal::Var(id) => names.contains(id),
+        Literal::UnaryOp(_, lit) => reads(lit, names),
+        Literal::BinaryOp(_, lits) | Literal::Equation(lits) => {
+            reads(&lits.0, names) || reads(&lits.1, names)
+        }
+        Literal::Template(fragments) => fragments
+            .iter()
+            .any(|fragment| matches!(fragment, Fragment::Expr(lit, _) if reads(lit, names))),
+        // The variable of diff, integrate and solve is local to its expression
+        Literal::Fun(name, args) => match args.as_slice() {
+            [expr, Literal::Var(var), rest @ ..] if BINDING_FUNCTIONS.contains(name) => {
+                let inner: Vec<_> = names.iter().copied().filter(|id| id != var).collect();
+                reads(expr, &inner) || rest.iter().any(|arg| reads(arg, names))
+            }
+            args => args.iter().any(|arg| reads(arg, names)),
+        },
+        Literal::Let(bindings, body, _) => {
+            let mut names = names.to_vec();
+            for (id, lit) in bindings {
+                if reads(lit, &names) {
+                    return true;
+                }
+                names.retain(|name| name != id);
+            }
+            reads(body, &names)
+        }
+    }
+}
+
+/** Check if a literal can only compute to a number, if it computes */
+fn is_number(lit: &Literal, bound: &[&str]) -> bool {
+    match lit {
+        Literal::Nb(..) | Literal::UnaryOp(..) => true,
+        // The varying variable, unless a local binding shadows it
+        Literal::Var(id) => bound.iter().rposition(|name| name == id) == Some(0),
+        // Only two strings add to a string
+        Literal::BinaryOp(BinOp::Add, lits) => {
+            is_number(&lits.0, bound) || is_number(&lits.1, bound)
+        }
+        Literal::BinaryOp(..) => true,
+        Literal::Fun(name, _) => function(name).is_some(),
+        _ => false,
+    }
+}
+
+/** Check if a literal is a number with the exact bits of another */
+fn is_nb(lit: &Literal, nb: f64) -> bool {
+    matches!(lit, Literal::Nb(value, _) if value.to_bits() == nb.to_bits())
+}
+
+struct Optimizer<'c> {
+    ctx: &'c mut Context,
+    // The varying variable followed by the local bindings in scope
+    bound: Vec<String>,
+}
+
+impl Optimizer<'_> {
+    fn bound(&self) -> Vec<&str> {
+        self.bound.iter().map(String::as_str).collect()
+    }
+
+    fn literal<'a>(&mut self, lit: &Literal<'a>) -> Literal<'a> {
+        // An invariant part is computed once
+        if !matches!(lit, Literal::Nb(..)) && !reads(lit, &self.bound()) {
+            if let Ok(Value::Nb(nb)) = compute_value(self.ctx, lit) {
+                return Literal::Nb(nb, None);
+            }
+        }
+        match lit {
+            Literal::UnaryOp(op, lit) => {
+                let lit = self.literal(lit);
+                self.unary(*op, lit)
+            }
+            Literal::BinaryOp(op, lits) => {
+                let (l, r) = (self.literal(&lits.0), self.literal(&lits.1));
+                self.binary(*op, l, r)
+            }
+            Literal::Equation(lits) => {
+                Literal::Equation(Box::new((self.literal(&lits.0), self.literal(&lits.1))))
+            }
+            Literal::Template(fragments) => Literal::Template(
+                fragments
+                    .iter()
+                    .map(|fragment| match fragment {
+                        Fragment::Expr(lit, spec) => Fragment::Expr(self.literal(lit), *spec),
+                        fragment => fragment.clone(),
+                    })
+                    .collect(),
+            ),
+            // A derivative is symbolic, its expression is kept as written
+            Literal::Fun("diff", args) => Literal::Fun("diff", args.clone()),
+            Literal::Fun(name, args) => match args.as_slice() {
+                [expr, var @ Literal::Var(id), rest @ ..] if BINDING_FUNCTIONS.contains(name) => {
+                    self.bound.push(id.to_string());
+                    let mut optimized = vec![self.literal(expr), var.clone()];
+                    self.bound.pop();
+                    optimized.extend(rest.iter().map(|arg| self.literal(arg)));
+                    Literal::Fun(name, optimized)
+                }
+                args => Literal::Fun(name, args.iter().map(|arg| self.literal(arg)).collect()),
+            },
+            Literal::Let(bindings, body, is_where) => {
+                let depth = self.bound.len();
+                let bindings = bindings
+                    .iter()
+                    .map(|(id, lit)| {
+                        let lit = self.literal(lit);
+                        self.bound.push(id.to_string());
+                        (*id, lit)
+                    })
+                    .collect();
+                let body = self.literal(body);
+                self.bound.truncate(depth);
+                Literal::Let(bindings, Box::new(body), *is_where)
+            }
+            lit => lit.clone(),
+        }
+    }
+
+    fn unary<'a>(&self, op: UnOp, lit: Literal<'a>) -> Literal<'a> {
+        match (op, lit) {
+            // +x and --x are x
+            (UnOp::Add, lit) if is_number(&lit, &self.bound()) => lit,
+            (UnOp::Sub, Literal::UnaryOp(UnOp::Sub, lit)) if is_number(&lit, &self.bound()) => *lit,
+            (op, lit) => Literal::UnaryOp(op, Box::new(lit)),
+        }
+    }
+
+    fn binary<'a>(&self, op: BinOp, l: Literal<'a>, r: Literal<'a>) -> Literal<'a> {
+        let bound = self.bound();
+        match op {
+            BinOp::Mul | BinOp::Div | BinOp::Pow if is_nb(&r, 1.) && is_number(&l, &bound) => l,
+            BinOp::Mul if is_nb(&l, 1.) && is_number(&r, &bound) => r,
+            // x - 0 and x + -0 are x, even for x = -0
+            BinOp::Sub if is_nb(&r, 0.) && is_number(&l, &bound) => l,
+            BinOp::Add if is_nb(&r, -0.) && is_number(&l, &bound) => l,
+            BinOp::Add if is_nb(&l, -0.) && is_number(&r, &bound) => r,
+            op => Literal::BinaryOp(op, Box::new((l, r))),
+        }
+    }
+}
+
+/** Optimise an expression computed many times while a variable varies */
+pub(crate) fn optimize<'a>(ctx: &mut Context, lit: &Literal<'a>, x: &str) -> Literal<'a> {
+    if ctx.settings().decimal {
+        return lit.clone();
+    }
+    let mut optimizer = Optimizer {
+        ctx,
+        bound: vec![x.to_string()],
+    };
+    optimizer.literal(lit)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        interpreter::{compute, compute_literal},
+        testing::{bits, expression, literal},
+    };
+    use proptest::prelude::*;
+
+    /** Compute a literal with x bound, as a numerical method does */
+    fn sample(ctx: &mut Context, lit: &Literal, x: f64) -> Result<u64, String> {
+        ctx.with_var("x", x, |ctx| compute_literal(ctx, lit))
+            .map_err(String::from)
+            .map(bits)
+    }
+
+    #[test]
+    fn test_optimize() {
+        let mut ctx = Context::empty();
+        compute(&mut ctx, "r = 3").unwrap();
+        compute(&mut ctx, "s = \"a\"").unwrap();
+        let cases = [
+            ("2*PI*r*x", "18.84955592153876*x"),
+            ("x*(1 + 1) + sqrt(r + 1)", "x*2 + 2"),
+            ("x*1 + x/1 + 1*x + x^1", "x + x + x + x"),
+            ("(x - 0) + -(-x) + (x + -0)", "x + x + x"),
+            // Not an identity for -0 or with strings
+            ("x + 0", "x + 0"),
+            ("0 + x", "0 + x"),
+            ("s*1 + x", "s*1 + x"),
+            ("let x = s in x*1", "let x = s in x*1"),
+            ("let a = 2*r in a*x + r^2", "let a = 6 in a*x + 9"),
+            ("x*integrate(t*r, t, 0, 2)", "x*6"),
+            ("integrate(t*x, t, 0, 2*r)", "integrate(t*x, t, 0, 6)"),
+            ("diff(x^2*r, x, 1)", "6"),
+            ("unknown*x*1", "unknown*x"),
+        ];
+        for (code, expected) in cases {
+            let lit = literal(code);
+            let optimized = optimize(&mut ctx, &lit, "x");
+            assert_eq!(optimized.to_string(), expected, "{}", code);
+            for x in [-2., -0., 0., 0.5, 3.] {
+                assert_eq!(
+                    sample(&mut ctx, &optimized, x),
+                    sample(&mut ctx, &lit, x),
+                    "{} at {}",
+                    code,
+                    x
+                );
+            }
+        }
+
+        // Decimal numbers are not optimised
+        let mut settings = ctx.settings();
+        settings.decimal = true;
+        ctx.set_settings(settings);
+        let lit = literal("x*(0.1 + 0.2)");
+        assert_eq!(optimize(&mut ctx, &lit, "x"), lit);
+    }
+
+    proptest! {
+        #[test]
+        fn optimize_unchanged(code in expression(false), x in prop::sample::select(vec![-1.5, -0., 0., 2.])) {
+            let mut ctx = Context::empty();
+            compute(&mut ctx, "y = 1.5").unwrap();
+            compute(&mut ctx, "s = \"a\"").unwrap();
+            let lit = literal(&code);
+            let optimized = optimize(&mut ctx, &lit, "x");
+            prop_assert_eq!(sample(&mut ctx, &optimized, x), sample(&mut ctx, &lit, x));
+        }
+    }
+}
@@ -1,13 +1,22 @@
-use std::convert::{TryFrom, TryInto};
+use std::{
+    convert::{TryFrom, TryInto},
+    fmt,
+    ops::Range,
+    rc::Rc,
+};
 
-use crate::lexer::{Lexer, Op, Sep, Token, TokenKind};
+use crate::{
+    lexer::{Lexer, Op, Sep, Template, Token, TokenKind},
+    report::Error,
+};
+use self_cell::self_cell;
 
 /** The parser is responsible to line into usable type. The design is inspired by the following
 excellent article: https://matklad.github.io/2020/04/13/simple-but-powerful-pratt-parsing.html  */
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Line<'a> {
-    Expr(Expression<'a>),
+    Exprs(Vec<Expression<'a>>), // Statements separated by ';'
     Comment(usize),
     Empty,
 }
@@ -61,97 +70,265 @@ impl TryFrom<Op> for BinOp {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expression<'a> {
     Assign(&'a str, Literal<'a>),
+    Const(&'a str, Literal<'a>),    // const g = 9.81
+    Override(&'a str, Literal<'a>), // override PI = 3.14
     Literal(Literal<'a>),
-    Print(Vec<Print<'a>>),
+    Print(Vec<Literal<'a>>),
+    Equation(Literal<'a>, Literal<'a>),
+    Solve(Vec<&'a str>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal<'a> {
-    Nb(f64),
+    Nb(f64, Option<&'a str>), // Value and source text, None for a computed number
     UnaryOp(UnOp, Box<Literal<'a>>),
     BinaryOp(BinOp, Box<(Literal<'a>, Literal<'a>)>),
-    Fun(&'a str, Box<Literal<'a>>),
+    Fun(&'a str, Vec<Literal<'a>>),
     Var(&'a str),
+    Str(&'a str),
+    Template(Vec<Fragment<'a>>),
+    Equation(Box<(Literal<'a>, Literal<'a>)>),
+    Let(Vec<(&'a str, Literal<'a>)>, Box<Literal<'a>>, bool), // Bindings, scope, written with where
 }
 
+/** A part of a string with interpolated expressions */
 #[derive(Debug, Clone, PartialEq)]
-pub enum Print<'a> {
-    Literal(Literal<'a>),
+pub enum Fragment<'a> {
     Str(&'a str),
+    Expr(Literal<'a>, Option<&'a str>), // Expression and its format spec
+}
+
+self_cell!(
+    /** A parsed line owning its source */
+    pub(crate) struct ParsedLine {
+        owner: String,
+        #[covariant]
+        dependent: Line,
+    }
+    impl {Debug}
+);
+
+impl ParsedLine {
+    pub(crate) fn parse(source: &str) -> Result<Self, Error> {
+        Self::try_new(source.into(), |source| parse(Lexer::load(source)))
+    }
+
+    /** Span of a part of the source, None if it is not a slice of the source */
+    pub(crate) fn span(&self, part: &str) -> Option<Range<usize>> {
+        let source = self.borrow_owner();
+        let start = (part.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+        (start + part.len() <= source.len()).then(|| start..start + part.len())
+    }
+}
+
+/** A statement of a parsed line, kept by the context after the line is computed */
+#[derive(Debug, Clone)]
+pub(crate) struct Statement {
+    line: Rc<ParsedLine>,
+    index: usize,
+}
+
+impl Statement {
+    /** Every statement of a parsed line */
+    pub(crate) fn all(line: Rc<ParsedLine>) -> Vec<Statement> {
+        let len = match line.borrow_dependent() {
+            Line::Exprs(exprs) => exprs.len(),
+            Line::Comment(_) | Line::Empty => 0,
+        };
+        (0..len)
+            .map(|index| Statement {
+                line: line.clone(),
+                index,
+            })
+            .collect()
+    }
+
+    pub(crate) fn expression(&self) -> &Expression<'_> {
+        match self.line.borrow_dependent() {
+            Line::Exprs(exprs) => &exprs[self.index],
+            Line::Comment(_) | Line::Empty => unreachable!("A statement comes from an expression"),
+        }
+    }
+}
+
+impl PartialEq for Statement {
+    fn eq(&self, other: &Self) -> bool {
+        self.expression() == other.expression()
+    }
 }
 
 /** Parse a line from tokens */
-pub fn parse<'a>(mut lexer: Lexer<'a>) -> Result<Line<'a>, String> {
+pub fn parse<'a>(mut lexer: Lexer<'a>) -> Result<Line<'a>, Error> {
     let peek = lexer.peek();
-    let line = match peek.kind() {
-        TokenKind::Sep(Sep::Comment) => Line::Comment(peek.span().start),
-        TokenKind::Eof => Line::Empty,
-        _ => {
-            let expr = match peek.kind() {
-                TokenKind::Str => Expression::Print(parse_print(&mut lexer)?),
-                TokenKind::Id => {
-                    let id = lexer.next().splice();
-                    match lexer.next().kind() {
-                        TokenKind::Op(Op::Eq) => {
-                            Expression::Assign(id, parser_literal(&mut lexer, 0)?)
-                        }
-                        _ => {
-                            lexer.reset();
-                            Expression::Literal(parser_literal(&mut lexer, 0)?)
-                        }
+    if peek.kind() == TokenKind::Sep(Sep::Comment) {
+        return Ok(Line::Comment(peek.span().start));
+    }
+    let mut exprs = Vec::new();
+    loop {
+        match lexer.peek().kind() {
+            TokenKind::Eof => break,
+            // Empty statements are skipped
+            TokenKind::Sep(Sep::Semi) => {
+                lexer.next();
+                continue;
+            }
+            _ => exprs.push(parse_statement(&mut lexer)?),
+        }
+        let token = lexer.next();
+        match token.kind() {
+            TokenKind::Eof => break,
+            TokenKind::Sep(Sep::Semi) => {}
+            _ => return Err(token.err_there("Incomplete expression")),
+        }
+    }
+    Ok(if exprs.is_empty() {
+        Line::Empty
+    } else {
+        Line::Exprs(exprs)
+    })
+}
+
+/** Parse a statement from tokens */
+fn parse_statement<'a>(lexer: &mut Lexer<'a>) -> Result<Expression<'a>, Error> {
+    Ok(match lexer.peek().kind() {
+        TokenKind::Str | TokenKind::Template(Template::Start) => {
+            Expression::Print(parse_print(lexer)?)
+        }
+        TokenKind::Id => {
+            let start = lexer.clone();
+            let id = lexer.next().splice();
+            match lexer.peek().kind() {
+                TokenKind::Op(Op::Eq) => {
+                    lexer.next();
+                    Expression::Assign(id, parser_literal(lexer, 0)?)
+                }
+                TokenKind::Id if id == "solve" => Expression::Solve(parse_unknowns(lexer)?),
+                // const g = 9.81 or override PI = 3.14
+                TokenKind::Id if id == "const" || id == "override" => {
+                    let name = lexer.next().splice();
+                    expect_kind(
+                        lexer.next(),
+                        TokenKind::Op(Op::Eq),
+                        "Expected '=' after the constant name",
+                    )?;
+                    let lit = parser_literal(lexer, 0)?;
+                    if id == "const" {
+                        Expression::Const(name, lit)
+                    } else {
+                        Expression::Override(name, lit)
                     }
                 }
-                _ => Expression::Literal(parser_literal(&mut lexer, 0)?),
-            };
-            expect_kind(lexer.next(), TokenKind::Eof, "Incomplete expression")?;
-            Line::Expr(expr)
+                _ => {
+                    *lexer = start;
+                    parse_equation(lexer)?
+                }
+            }
         }
-    };
-    Ok(line)
+        _ => parse_equation(lexer)?,
+    })
 }
 
 /** Check token's kind */
-fn expect_kind<'a, 'b>(
-    token: Token<'a>,
-    kind: TokenKind,
-    msg: &'b str,
-) -> Result<Token<'a>, String> {
+fn expect_kind<'a>(token: Token<'a>, kind: TokenKind, msg: &str) -> Result<Token<'a>, Error> {
     if token.kind() != kind {
-        return Err(token.err_there(msg));
+        Err(token.err_there(msg))
     } else {
-        return Ok(token);
+        Ok(token)
+    }
+}
+
+/** Parse a literal, or an equation if it is followed by '=' */
+fn parse_equation<'a>(lexer: &mut Lexer<'a>) -> Result<Expression<'a>, Error> {
+    let lhs = parser_literal(lexer, 0)?;
+    if lexer.peek().kind() == TokenKind::Op(Op::Eq) {
+        lexer.next();
+        return Ok(Expression::Equation(lhs, parser_literal(lexer, 0)?));
+    }
+    Ok(Expression::Literal(lhs))
+}
+
+/** Parse the comma separated unknowns of a solve statement */
+fn parse_unknowns<'a>(lexer: &mut Lexer<'a>) -> Result<Vec<&'a str>, Error> {
+    let mut unknowns =
+        vec![expect_kind(lexer.next(), TokenKind::Id, "Expected an unknown")?.splice()];
+    while lexer.peek().kind() == TokenKind::Sep(Sep::Comma) {
+        lexer.next();
+        unknowns.push(expect_kind(lexer.next(), TokenKind::Id, "Expected an unknown")?.splice());
     }
+    Ok(unknowns)
 }
 
 /** Parse a print parts from tokens */
-fn parse_print<'a>(lexer: &mut Lexer<'a>) -> Result<Vec<Print<'a>>, String> {
+fn parse_print<'a>(lexer: &mut Lexer<'a>) -> Result<Vec<Literal<'a>>, Error> {
     let mut buf = Vec::new();
-    loop {
-        let token = lexer.peek();
-
-        buf.push(match token.kind() {
-            TokenKind::Str => {
-                if !token.splice().ends_with('"') || token.splice().len() < 2 {
-                    return Err(token
-                        .after()
-                        .err_there("Missing string end, '\"' is missing"));
-                }
-                Print::Str(lexer.next().splice().trim_matches('"'))
-            }
-            TokenKind::Eof => return Ok(buf),
-            _ => Print::Literal(parser_literal(lexer, 0)?),
-        });
+    while !matches!(
+        lexer.peek().kind(),
+        TokenKind::Eof | TokenKind::Sep(Sep::Semi)
+    ) {
+        buf.push(parser_literal(lexer, 0)?);
     }
+    Ok(buf)
 }
 
 /** Parse a literal from tokens */
-fn parser_literal<'a>(lexer: &mut Lexer<'a>, min_bp: u8) -> Result<Literal<'a>, String> {
+fn parser_literal<'a>(lexer: &mut Lexer<'a>, min_bp: u8) -> Result<Literal<'a>, Error> {
     let token = lexer.next();
     let mut lhs = match token.kind() {
-        TokenKind::Nb => match token.splice().parse::<f64>() {
-            Ok(nb) => Literal::Nb(nb),
-            Err(_) => return Err(token.err_there("Invalid Number")),
-        },
+        TokenKind::Nb => {
+            let nb = match token.splice().parse::<f64>() {
+                Ok(nb) => Literal::Nb(nb, Some(token.splice())),
+                Err(_) => return Err(token.err_there("Invalid Number")),
+            };
+            // A number directly followed by a variable or a block is multiplied with it: 2x
+            let peek = lexer.peek();
+            let implicit = matches!(peek.kind(), TokenKind::Id | TokenKind::Sep(Sep::Open));
+            if implicit && peek.span().start == token.span().end {
+                let rhs = parser_literal(lexer, infix_binding_power(BinOp::Mul))?;
+                Literal::BinaryOp(BinOp::Mul, Box::new((nb, rhs)))
+            } else {
+                nb
+            }
+        }
+        TokenKind::Str => {
+            let str = token.splice();
+            if !str.ends_with('"') || str.len() < 2 {
+                return Err(token
+                    .after()
+                    .err_there("Missing string end, '\"' is missing"));
+            }
+            Literal::Str(&str[1..str.len() - 1])
+        }
+        TokenKind::Template(Template::Start) => {
+            let mut fragments = Vec::new();
+            let mut token = token;
+            loop {
+                let str = token.splice();
+                fragments.push(Fragment::Str(&str[1..str.len() - 1]));
+                if token.kind() == TokenKind::Template(Template::End) {
+                    break;
+                }
+                let lit = parser_literal(lexer, 0)?;
+                let spec = match lexer.peek().kind() {
+                    TokenKind::Spec => Some(&lexer.next().splice()[1..]),
+                    _ => None,
+                };
+                fragments.push(Fragment::Expr(lit, spec));
+                token = lexer.next();
+                match token.kind() {
+                    TokenKind::Template(Template::Middle) => {}
+                    TokenKind::Template(Template::End) => {
+                        let str = token.splice();
+                        if !str.ends_with('"') || str.len() < 2 {
+                            return Err(token
+                                .after()
+                                .err_there("Missing string end, '\"' is missing"));
+                        }
+                    }
+                    _ => return Err(token.err_there("Missing interpolation end '}'")),
+                }
+            }
+            Literal::Template(fragments)
+        }
         TokenKind::Sep(Sep::Open) => {
             let lhs = parser_literal(lexer, 0)?;
             expect_kind(
@@ -161,19 +338,32 @@ fn parser_literal<'a>(lexer: &mut Lexer<'a>, min_bp: u8) -> Result<Literal<'a>,
             )?;
             lhs
         }
+        // let a = 1, b = 2 in a*b
+        TokenKind::Id if token.splice() == "let" && lexer.peek().kind() == TokenKind::Id => {
+            let bindings = parse_bindings(lexer)?;
+            let token = lexer.next();
+            if !is_keyword(&token, "in") {
+                return Err(token.err_there("Expected 'in' after the bindings"));
+            }
+            Literal::Let(bindings, Box::new(parser_literal(lexer, 0)?), false)
+        }
         TokenKind::Id => {
-            let id = token.splice().into();
+            let id = token.splice();
             let peek = lexer.peek();
             if peek.kind() == TokenKind::Sep(Sep::Open) {
                 lexer.next();
 
-                let expr = parser_literal(lexer, 0)?;
+                let mut args = vec![parse_arg(lexer)?];
+                while lexer.peek().kind() == TokenKind::Sep(Sep::Comma) {
+                    lexer.next();
+                    args.push(parse_arg(lexer)?);
+                }
                 expect_kind(
                     lexer.next(),
                     TokenKind::Sep(Sep::Close),
                     "Missing function invocation end ')'",
                 )?;
-                Literal::Fun(id, Box::new(expr))
+                Literal::Fun(id, args)
             } else {
                 Literal::Var(id)
             }
@@ -188,13 +378,10 @@ fn parser_literal<'a>(lexer: &mut Lexer<'a>, min_bp: u8) -> Result<Literal<'a>,
         _ => return Err(token.err_there("Incomplete expression")),
     };
 
-    loop {
-        let op = match lexer.peek().kind() {
-            TokenKind::Op(op) => match op.try_into() {
-                Ok(op) => op,
-                Err(_) => break,
-            },
-            _ => break,
+    while let TokenKind::Op(op) = lexer.peek().kind() {
+        let op = match op.try_into() {
+            Ok(op) => op,
+            Err(_) => break,
         };
 
         let bp = infix_binding_power(op);
@@ -207,6 +394,57 @@ fn parser_literal<'a>(lexer: &mut Lexer<'a>, min_bp: u8) -> Result<Literal<'a>,
         lhs = Literal::BinaryOp(op, Box::new((lhs, rhs)))
     }
 
+    // a*b where a = 1, b = 2
+    if min_bp == 0 && is_keyword(lexer.peek(), "where") {
+        lexer.next();
+        lhs = Literal::Let(parse_bindings(lexer)?, Box::new(lhs), true);
+    }
+
+    Ok(lhs)
+}
+
+fn is_keyword(token: &Token, keyword: &str) -> bool {
+    token.kind() == TokenKind::Id && token.splice() == keyword
+}
+
+/** Parse local bindings, a comma continues them only if another binding follows */
+fn parse_bindings<'a>(lexer: &mut Lexer<'a>) -> Result<Vec<(&'a str, Literal<'a>)>, Error> {
+    let mut bindings = Vec::new();
+    loop {
+        let id = expect_kind(lexer.next(), TokenKind::Id, "Expected a name to bind")?.splice();
+        expect_kind(
+            lexer.next(),
+            TokenKind::Op(Op::Eq),
+            "Expected '=' after the name",
+        )?;
+        bindings.push((id, parser_literal(lexer, 0)?));
+        let mut ahead = lexer.clone();
+        let next = [
+            ahead.next().kind(),
+            ahead.next().kind(),
+            ahead.next().kind(),
+        ];
+        if next
+            != [
+                TokenKind::Sep(Sep::Comma),
+                TokenKind::Id,
+                TokenKind::Op(Op::Eq),
+            ]
+        {
+            return Ok(bindings);
+        }
+        lexer.next();
+    }
+}
+
+/** Parse a function argument, which can be an equation */
+fn parse_arg<'a>(lexer: &mut Lexer<'a>) -> Result<Literal<'a>, Error> {
+    let lhs = parser_literal(lexer, 0)?;
+    if lexer.peek().kind() == TokenKind::Op(Op::Eq) {
+        lexer.next();
+        let rhs = parser_literal(lexer, 0)?;
+        return Ok(Literal::Equation(Box::new((lhs, rhs))));
+    }
     Ok(lhs)
 }
 
@@ -223,3 +461,131 @@ fn infix_binding_power(op: BinOp) -> u8 {
         BinOp::Pow => 3,
     }
 }
+
+/** Format a statement as code */
+impl fmt::Display for Expression<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expression::Assign(id, lit) => write!(f, "{} = {}", id, lit),
+            Expression::Const(id, lit) => write!(f, "const {} = {}", id, lit),
+            Expression::Override(id, lit) => write!(f, "override {} = {}", id, lit),
+            Expression::Literal(lit) => write!(f, "{}", lit),
+            Expression::Print(parts) => {
+                for (i, part) in parts.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(" ")?;
+                    }
+                    write!(f, "{}", part)?;
+                }
+                Ok(())
+            }
+            Expression::Equation(lhs, rhs) => write!(f, "{} = {}", lhs, rhs),
+            Expression::Solve(unknowns) => write!(f, "solve {}", unknowns.join(", ")),
+        }
+    }
+}
+
+/** Format a literal as code, only adding the parentheses required to parse it back */
+impl fmt::Display for Literal<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Literal::Nb(_, Some(text)) => f.write_str(text),
+            Literal::Nb(nb, None) => write!(f, "{}", nb),
+            Literal::Var(id) => f.write_str(id),
+            Literal::Str(str) => write!(f, "\"{}\"", str),
+            Literal::Template(fragments) => {
+                f.write_str("\"")?;
+                for fragment in fragments {
+                    match fragment {
+                        Fragment::Str(str) => f.write_str(str)?,
+                        Fragment::Expr(lit, spec) => {
+                            write!(f, "{{{}", lit)?;
+                            if let Some(spec) = spec {
+                                write!(f, ":{}", spec)?;
+                            }
+                            f.write_str("}")?;
+                        }
+                    }
+                }
+                f.write_str("\"")
+            }
+            Literal::Equation(sides) => write!(f, "{} = {}", sides.0, sides.1),
+            Literal::Let(bindings, body, is_where) => {
+                if *is_where {
+                    write!(f, "{} where ", body)?;
+                } else {
+                    f.write_str("let ")?;
+                }
+                for (i, (id, lit)) in bindings.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{} = {}", id, lit)?;
+                }
+                if !is_where {
+                    write!(f, " in {}", body)?;
+                }
+                Ok(())
+            }
+            Literal::Fun(name, args) => {
+                write!(f, "{}(", name)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                f.write_str(")")
+            }
+            Literal::UnaryOp(op, hs) => {
+                f.write_str(match op {
+                    UnOp::Add => "+",
+                    UnOp::Sub => "-",
+                })?;
+                match hs.as_ref() {
+                    Literal::BinaryOp(..) | Literal::UnaryOp(..) | Literal::Let(..) => {
+                        write!(f, "({})", hs)
+                    }
+                    Literal::Nb(nb, _) if nb.is_sign_negative() => write!(f, "({})", hs),
+                    _ => write!(f, "{}", hs),
+                }
+            }
+            Literal::BinaryOp(op, lits) => {
+                let bp = infix_binding_power(*op);
+                fmt_operand(f, &lits.0, bp, false)?;
+                f.write_str(match op {
+                    BinOp::Add => " + ",
+                    BinOp::Sub => " - ",
+                    BinOp::Mul => "*",
+                    BinOp::Div => "/",
+                    BinOp::Mod => "%",
+                    BinOp::Pow => "^",
+                })?;
+                fmt_operand(f, &lits.1, bp, true)
+            }
+        }
+    }
+}
+
+/** Format a binary operand, every binary operator being left associative */
+fn fmt_operand(f: &mut fmt::Formatter<'_>, lit: &Literal, bp: u8, rhs: bool) -> fmt::Result {
+    let wrap = match lit {
+        Literal::BinaryOp(op, _) => {
+            let lit_bp = infix_binding_power(*op);
+            lit_bp < bp || (rhs && lit_bp == bp)
+        }
+        // -x^2 is parsed as (-x)^2, make it explicit
+        Literal::UnaryOp(..) => !rhs && bp == infix_binding_power(BinOp::Pow),
+        Literal::Nb(nb, _) => {
+            nb.is_sign_negative() && !rhs && bp == infix_binding_power(BinOp::Pow)
+        }
+        // The body of local bindings extends as far as possible
+        Literal::Let(..) => true,
+        _ => false,
+    };
+    if wrap {
+        write!(f, "({})", lit)
+    } else {
+        write!(f, "{}", lit)
+    }
+}
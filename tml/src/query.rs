@@ -0,0 +1,388 @@
+use crate::{
+    document::Document,
+    interpreter::{statements, Context},
+    lexer::{Lexer, Sep, TokenKind},
+    resolve::{resolve, Occurrence, Role, Symbol},
+};
+use std::ops::Range;
+
+/* Editor queries at a cursor position: completion and hover. The worksheet state at a
+statement comes from the document, replaying the cached statements above it. Offsets are byte
+offsets in the document source. */
+
+/** Signature and description of the builtin functions */
+const SIGNATURES: [(&str, &str, &str); 25] = [
+    (
+        "floor",
+        "floor(x)",
+        "Largest integer less than or equal to x",
+    ),
+    (
+        "ceil",
+        "ceil(x)",
+        "Smallest integer greater than or equal to x",
+    ),
+    (
+        "round",
+        "round(x[, digits[, rule]])",
+        "Round to the nearest integer, or to a number of digits with a rounding rule",
+    ),
+    ("trunc", "trunc(x)", "Integer part of x"),
+    ("fract", "fract(x)", "Fractional part of x"),
+    ("sqrt", "sqrt(x)", "Square root of x"),
+    ("exp", "exp(x)", "Exponential of x"),
+    ("ln", "ln(x)", "Natural logarithm of x"),
+    ("log2", "log2(x)", "Base 2 logarithm of x"),
+    ("log10", "log10(x)", "Base 10 logarithm of x"),
+    ("cos", "cos(x)", "Cosine of x in radians"),
+    ("sin", "sin(x)", "Sine of x in radians"),
+    ("tan", "tan(x)", "Tangent of x in radians"),
+    ("acos", "acos(x)", "Arccosine of x in radians"),
+    ("asin", "asin(x)", "Arcsine of x in radians"),
+    ("atan", "atan(x)", "Arctangent of x in radians"),
+    (
+        "diff",
+        "diff(expr, x[, at])",
+        "Derivative of expr with respect to x, as an expression or at a point",
+    ),
+    (
+        "integrate",
+        "integrate(expr, x, a, b)",
+        "Integral of expr with respect to x from a to b",
+    ),
+    (
+        "solve",
+        "solve(lhs = rhs, x[, guess | a, b])",
+        "Root of an equation near a guess, or every root between a and b",
+    ),
+    ("len", "len(str)", "Number of characters of a string"),
+    ("upper", "upper(str)", "String in uppercase"),
+    ("lower", "lower(str)", "String in lowercase"),
+    ("str", "str(x)", "Value converted to a string"),
+    ("num", "num(str)", "String converted to a number"),
+    (
+        "substr",
+        "substr(str, start[, len])",
+        "Characters of a string from start, up to len characters",
+    ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum CompletionKind {
+    Variable,
+    Constant,
+    Function,
+}
+
+impl CompletionKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            CompletionKind::Variable => "variable",
+            CompletionKind::Constant => "constant",
+            CompletionKind::Function => "function",
+        }
+    }
+}
+
+/** A completion candidate, detailed by its value or signature */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Completion {
+    pub label: String,
+    pub kind: CompletionKind,
+    pub detail: String,
+}
+
+/** Information about the name under the cursor */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hover {
+    pub span: Range<usize>,
+    pub contents: String,
+}
+
+/** List the statements of a source with their start offset */
+fn located_statements(source: &str) -> impl Iterator<Item = (usize, &str)> {
+    let mut start = 0;
+    statements(source).map(move |(statement, lines)| {
+        let located = (start, statement);
+        for _ in 0..lines {
+            start += source[start..]
+                .find('\n')
+                .map_or(source.len() - start, |i| i + 1);
+        }
+        located
+    })
+}
+
+/** Find the statement containing an offset, returning its index and start offset */
+fn statement_at(source: &str, offset: usize) -> Option<(usize, usize, &str)> {
+    located_statements(source)
+        .enumerate()
+        .find(|(_, (start, statement))| offset <= start + statement.len())
+        .filter(|(_, (start, _))| offset >= *start)
+        .map(|(idx, (start, statement))| (idx, start, statement))
+}
+
+/** Find the identifier under a statement offset, with the identifier followed by '(' */
+fn id_at(statement: &str, offset: usize) -> Option<(Range<usize>, bool)> {
+    let mut lexer = Lexer::load(statement);
+    loop {
+        let token = lexer.next();
+        let span = token.span().clone();
+        match token.kind() {
+            TokenKind::Eof => return None,
+            TokenKind::Sep(Sep::Comment) => return None,
+            _ if span.end < offset => continue,
+            _ if span.start > offset => return None,
+            TokenKind::Id => {
+                let is_call = lexer.peek().kind() == TokenKind::Sep(Sep::Open);
+                return Some((span, is_call));
+            }
+            // The cursor at the end of another token may be at the start of an identifier
+            _ if span.end == offset => continue,
+            _ => return None,
+        }
+    }
+}
+
+/** Find the resolved name under a document offset */
+fn occurrence_at(source: &str, offset: usize) -> Option<Occurrence<'_>> {
+    resolve(source)
+        .into_iter()
+        .find(|occurrence| occurrence.span.contains(&offset) || occurrence.span.end == offset)
+}
+
+/** Check if the cursor is inside a string or a comment, where nothing completes */
+fn in_text(statement: &str, offset: usize) -> bool {
+    let mut lexer = Lexer::load(statement);
+    loop {
+        let token = lexer.next();
+        let span = token.span();
+        match token.kind() {
+            TokenKind::Eof => return false,
+            TokenKind::Sep(Sep::Comment) => return span.start < offset,
+            TokenKind::Str | TokenKind::Template(_) | TokenKind::Spec
+                if span.start < offset && offset < span.end =>
+            {
+                return true
+            }
+            _ => {}
+        }
+    }
+}
+
+fn value_detail(ctx: &Context, id: &str) -> Option<(CompletionKind, String)> {
+    let format = ctx.number_format();
+    if let Some(value) = ctx.variable(id) {
+        return Some((CompletionKind::Variable, value.to_code_with(&format)));
+    }
+    ctx.constants()
+        .into_iter()
+        .find(|(name, _)| name == id)
+        .map(|(_, value)| (CompletionKind::Constant, value.to_code_with(&format)))
+}
+
+/** List the names completing the identifier before the cursor: variables defined above the
+statement, constants and builtin functions */
+pub fn complete(document: &mut Document, source: &str, offset: usize) -> Vec<Completion> {
+    document.update(source);
+    let (idx, start, statement) = match statement_at(source, offset) {
+        Some(found) => found,
+        None => return Vec::new(),
+    };
+    let offset = offset - start;
+    if in_text(statement, offset) {
+        return Vec::new();
+    }
+    let prefix = match id_at(statement, offset) {
+        Some((span, _)) => &statement[span.start..offset],
+        None => "",
+    };
+
+    let ctx = document.context_before(idx);
+    let format = ctx.number_format();
+    let variables = ctx.variables().into_iter().map(|(id, value)| Completion {
+        label: id,
+        kind: CompletionKind::Variable,
+        detail: value.to_code_with(&format),
+    });
+    let constants = ctx.constants().into_iter().map(|(id, value)| Completion {
+        label: id,
+        kind: CompletionKind::Constant,
+        detail: value.to_code_with(&format),
+    });
+    let functions = SIGNATURES.iter().map(|(name, signature, _)| Completion {
+        label: name.to_string(),
+        kind: CompletionKind::Function,
+        detail: signature.to_string(),
+    });
+    let mut completions: Vec<_> = variables
+        .chain(constants)
+        .chain(functions)
+        .filter(|completion| completion.label.starts_with(prefix))
+        .collect();
+    completions.sort_by(|a, b| (a.kind, &a.label).cmp(&(b.kind, &b.label)));
+    completions
+}
+
+/** Describe the name under the cursor: the current value of a variable or a constant, or the
+signature and description of a builtin function. A local binding has no worksheet value. */
+pub fn hover(document: &mut Document, source: &str, offset: usize) -> Option<Hover> {
+    document.update(source);
+    let (idx, _, _) = statement_at(source, offset)?;
+    let occurrence = occurrence_at(source, offset)?;
+    let contents = match occurrence.symbol {
+        Symbol::Function(id) => {
+            let (_, signature, description) = SIGNATURES.iter().find(|(name, _, _)| *name == id)?;
+            format!("{}\n{}", signature, description)
+        }
+        Symbol::Global(id) => {
+            let ctx = document.context_before(idx);
+            match value_detail(&ctx, id)? {
+                (CompletionKind::Constant, value) => format!("const {} = {}", id, value),
+                (_, value) => format!("{} = {}", id, value),
+            }
+        }
+        Symbol::Local(_) => return None,
+    };
+    Some(Hover {
+        span: occurrence.span,
+        contents,
+    })
+}
+
+/** Find the definition of the name under the cursor: the binding of a local, or the name in
+the last statement above assigning a worksheet variable */
+pub fn definition(source: &str, offset: usize) -> Option<Range<usize>> {
+    let occurrences = resolve(source);
+    let occurrence = occurrences
+        .iter()
+        .find(|occurrence| occurrence.span.contains(&offset) || occurrence.span.end == offset)?;
+    let definition = match occurrence.symbol {
+        Symbol::Local(_) => occurrences
+            .iter()
+            .find(|other| other.symbol == occurrence.symbol && other.role == Role::Bind),
+        Symbol::Global(_) => occurrences.iter().rev().find(|other| {
+            other.symbol == occurrence.symbol
+                && other.role == Role::Write
+                && other.statement < occurrence.statement
+        }),
+        Symbol::Function(_) => None,
+    };
+    definition.map(|definition| definition.span.clone())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::{is_builtin, FUNCTIONS, SPECIAL_FUNCTIONS};
+
+    const SOURCE: &str = "rate = 0.05\nconst fee = 2\nra\ntotal = (rate +\n  sq(4))\n\"x {ra}\"";
+
+    fn labels(completions: &[Completion]) -> Vec<&str> {
+        completions.iter().map(|c| c.label.as_str()).collect()
+    }
+
+    #[test]
+    fn test_signatures() {
+        for (name, signature, _) in SIGNATURES {
+            assert!(is_builtin(name), "{}", name);
+            assert!(signature.starts_with(&format!("{}(", name)));
+        }
+        let builtins = FUNCTIONS
+            .iter()
+            .map(|(name, _)| *name)
+            .chain(SPECIAL_FUNCTIONS);
+        for name in builtins {
+            assert!(SIGNATURES.iter().any(|(n, _, _)| *n == name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_complete() {
+        let mut document = Document::default();
+        let at = |pattern: &str| SOURCE.find(pattern).unwrap() + pattern.len();
+
+        let completions = complete(&mut document, SOURCE, at("\nra"));
+        assert_eq!(labels(&completions), ["rate"]);
+        assert_eq!(completions[0].kind, CompletionKind::Variable);
+        assert_eq!(completions[0].detail, "0.05");
+        // Only variables defined above the statement
+        assert_eq!(
+            labels(&complete(&mut document, SOURCE, at("ra"))),
+            [] as [&str; 0]
+        );
+        // Across the lines of a statement
+        let completions = complete(&mut document, SOURCE, at("sq"));
+        assert_eq!(labels(&completions), ["sqrt"]);
+        assert_eq!(completions[0].detail, "sqrt(x)");
+        // Every name after an operator
+        let completions = complete(&mut document, SOURCE, at("(rate +"));
+        assert_eq!(completions.len(), 1 + 3 + SIGNATURES.len());
+        assert_eq!(labels(&completions)[..4], ["rate", "E", "PI", "fee"]);
+        // Nothing in strings, interpolated expressions complete
+        assert!(complete(&mut document, SOURCE, at("\"x")).is_empty());
+        assert_eq!(
+            labels(&complete(&mut document, SOURCE, at("{ra"))),
+            ["rate"]
+        );
+    }
+
+    #[test]
+    fn test_definition() {
+        let source = "x = 1\ny = (2 +\n  x)\nconst k = y\nx = k + x\nsolve z\nx * y";
+        let at = |pattern: &str| source.find(pattern).unwrap();
+        let def = |pattern: &str| definition(source, at(pattern));
+        assert_eq!(def("x)"), Some(0..1));
+        assert_eq!(def("y\nx ="), Some(at("y =")..at("y =") + 1));
+        assert_eq!(def("k + x"), Some(at("k = y")..at("k = y") + 1));
+        assert_eq!(def("x\nsolve"), Some(0..1));
+        assert_eq!(def("x * y"), Some(at("x = k")..at("x = k") + 1));
+        // Undefined variables and builtins have no definition
+        assert_eq!(def("x = 1"), None);
+        assert_eq!(def("z"), None);
+        assert_eq!(def("2 +"), None);
+
+        // Local bindings are defined where they are bound
+        let source = "x = 1\ny = let x = 2 in x\nintegrate(t^2, t, 0, 1); t";
+        let at = |pattern: &str| source.find(pattern).unwrap();
+        let def = |pattern: &str| definition(source, at(pattern));
+        assert_eq!(def("x\n"), Some(at("x = 2")..at("x = 2") + 1));
+        assert_eq!(def("t^2"), Some(at("t, 0")..at("t, 0") + 1));
+        assert_eq!(definition(source, source.len()), None);
+    }
+
+    #[test]
+    fn test_hover() {
+        let mut document = Document::default();
+        let source = "rate = 0.05\nconst fee = 2\nrate * fee + sqrt(4)\nrate = 1\nrate";
+        let hover_at = |document: &mut Document, pattern: &str| {
+            hover(document, source, source.find(pattern).unwrap())
+        };
+        let found = hover_at(&mut document, "rate * fee").unwrap();
+        assert_eq!(found.contents, "rate = 0.05");
+        assert_eq!(found.span, 26..30);
+        let found = hover_at(&mut document, "fee +").unwrap();
+        assert_eq!(found.contents, "const fee = 2");
+        let found = hover_at(&mut document, "sqrt").unwrap();
+        assert_eq!(found.contents, "sqrt(x)\nSquare root of x");
+        assert_eq!(hover_at(&mut document, "\nrate = 1"), None);
+        assert_eq!(
+            hover(&mut document, source, source.len()).unwrap().contents,
+            "rate = 1"
+        );
+        assert_eq!(hover_at(&mut document, "0.05"), None);
+
+        // Local bindings shadow the worksheet variables
+        let source = "x = 1\nt = 2\ny = let x = 2 in x\nintegrate(t^2, t, 0, 1) + t";
+        let hover_at = |document: &mut Document, pattern: &str| {
+            let offset = source.find(pattern).unwrap();
+            hover(document, source, offset).map(|found| found.contents)
+        };
+        assert_eq!(hover_at(&mut document, "x\nintegrate"), None);
+        assert_eq!(hover_at(&mut document, "x = 2"), None);
+        assert_eq!(hover_at(&mut document, "t^2"), None);
+        assert_eq!(hover_at(&mut document, "t, 0"), None);
+        let found = hover(&mut document, source, source.len()).unwrap();
+        assert_eq!(found.contents, "t = 2");
+    }
+}
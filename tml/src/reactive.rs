@@ -0,0 +1,93 @@
+use crate::parser::Statement;
+use std::collections::HashMap;
+
+/* Dependency graph of the reactive mode. Each variable assigned from a formula remembers its
+assignment and the variables it reads, so it can be recomputed when one of them changes. */
+
+/** Formula of a variable, the assignment statement sharing its parsed line */
+struct Formula {
+    assignment: Statement,
+    deps: Vec<String>,
+}
+
+#[derive(Default)]
+pub(crate) struct Graph {
+    formulas: HashMap<String, Formula>,
+}
+
+impl Graph {
+    /** Record the formula of a variable */
+    pub fn set(&mut self, id: &str, assignment: Statement, deps: Vec<String>) {
+        self.formulas
+            .insert(id.into(), Formula { assignment, deps });
+    }
+
+    /** Forget the formula of a variable, which now holds a plain value */
+    pub fn remove(&mut self, id: &str) {
+        self.formulas.remove(id);
+    }
+
+    pub fn assignment(&self, id: &str) -> Option<&Statement> {
+        self.formulas.get(id).map(|formula| &formula.assignment)
+    }
+
+    /** Check that a variable reading deps would not depend on itself */
+    pub fn check_cycle(&self, id: &str, deps: &[String]) -> Result<(), String> {
+        let mut path = vec![id];
+        for dep in deps {
+            if self.reaches(dep, id, &mut path) {
+                return Err(format!("Circular dependency: {}", path.join(" -> ")));
+            }
+        }
+        Ok(())
+    }
+
+    /** Search a path from a variable to a target through the formulas */
+    fn reaches<'a>(&'a self, from: &'a str, target: &str, path: &mut Vec<&'a str>) -> bool {
+        path.push(from);
+        if from == target {
+            return true;
+        }
+        if let Some(formula) = self.formulas.get(from) {
+            for dep in &formula.deps {
+                if self.reaches(dep, target, path) {
+                    return true;
+                }
+            }
+        }
+        path.pop();
+        false
+    }
+
+    /** List the variables depending on some variables, in an order where each one comes
+    after its own dependencies */
+    pub fn downstream(&self, ids: &[&str]) -> Vec<String> {
+        let mut order = Vec::new();
+        for id in ids {
+            if !order.iter().any(|visited| visited == id) {
+                self.visit(id, &mut order);
+            }
+        }
+        order.retain(|visited| !ids.contains(&visited.as_str()));
+        order.reverse();
+        order
+    }
+
+    /** Depth first search of the dependents, in post order */
+    fn visit(&self, id: &str, order: &mut Vec<String>) {
+        let mut dependents: Vec<_> = self
+            .formulas
+            .iter()
+            .filter(|(_, formula)| formula.deps.iter().any(|dep| dep == id))
+            .map(|(dependent, _)| dependent)
+            .collect();
+        // Sorted for a deterministic order
+        dependents.sort();
+        for dependent in dependents {
+            if !order.contains(dependent) {
+                self.visit(dependent, order);
+            }
+        }
+        order.push(id.into());
+    }
+}
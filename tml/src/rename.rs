@@ -0,0 +1,186 @@
+use crate::{
+    interpreter::{constant, is_builtin},
+    lexer::{Lexer, TokenKind},
+    resolve::{resolve, Occurrence, Symbol},
+};
+use std::ops::Range;
+
+/* Renaming of a variable across a document, every occurrence resolving to the same variable
+is renamed, see `resolve`. A rename is refused if it would make a name resolve differently. */
+
+/** Keywords of the language, which cannot be used as new names */
+const KEYWORDS: [&str; 6] = ["let", "in", "where", "const", "override", "solve"];
+
+/** A replacement of a span of the source */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextEdit {
+    pub span: Range<usize>,
+    pub text: String,
+}
+
+/** Group the occurrences by symbol, each one mapped to the index of its first occurrence */
+fn partition(occurrences: &[Occurrence]) -> Vec<usize> {
+    occurrences
+        .iter()
+        .map(|occurrence| {
+            occurrences
+                .iter()
+                .position(|other| other.symbol == occurrence.symbol)
+                .unwrap()
+        })
+        .collect()
+}
+
+fn is_builtin_name(id: &str) -> bool {
+    id == "$" || constant(id).is_some() || is_builtin(id)
+}
+
+/** Find the renamable name under the cursor */
+fn occurrence_at(occurrences: &[Occurrence], offset: usize) -> Result<usize, String> {
+    let idx = occurrences
+        .iter()
+        .position(|occurrence| occurrence.span.contains(&offset) || occurrence.span.end == offset)
+        .ok_or("No variable to rename here")?;
+    match occurrences[idx].symbol {
+        Symbol::Function(id) => Err(format!("Cannot rename the builtin '{}'", id)),
+        Symbol::Global(id) if is_builtin_name(id) => {
+            Err(format!("Cannot rename the builtin '{}'", id))
+        }
+        _ => Ok(idx),
+    }
+}
+
+/** Check a rename is possible at an offset, returning the span of the name */
+pub fn prepare_rename(source: &str, offset: usize) -> Result<Range<usize>, String> {
+    let occurrences = resolve(source);
+    let idx = occurrence_at(&occurrences, offset)?;
+    Ok(occurrences[idx].span.clone())
+}
+
+/** Rename the variable under the cursor everywhere it is defined and used, returning the edits
+in source order */
+pub fn rename(source: &str, offset: usize, name: &str) -> Result<Vec<TextEdit>, String> {
+    let mut lexer = Lexer::load(name);
+    let token = lexer.next();
+    if token.kind() != TokenKind::Id || token.span().len() != name.len() {
+        return Err(format!("'{}' is not a valid name", name));
+    }
+    if KEYWORDS.contains(&name) || is_builtin_name(name) {
+        return Err(format!("'{}' is a reserved name", name));
+    }
+
+    let occurrences = resolve(source);
+    let idx = occurrence_at(&occurrences, offset)?;
+    let symbol = occurrences[idx].symbol;
+    let edits: Vec<_> = occurrences
+        .iter()
+        .filter(|occurrence| occurrence.symbol == symbol)
+        .map(|occurrence| TextEdit {
+            span: occurrence.span.clone(),
+            text: name.into(),
+        })
+        .collect();
+
+    // Every name must keep referring to the same thing
+    let renamed = apply(source, &edits);
+    let after = resolve(&renamed);
+    if partition(&after) != partition(&occurrences) {
+        return Err(format!(
+            "Cannot rename to '{}', it would conflict with another '{}'",
+            name, name
+        ));
+    }
+    Ok(edits)
+}
+
+/** Apply edits sorted in source order */
+pub fn apply(source: &str, edits: &[TextEdit]) -> String {
+    let mut buf = String::new();
+    let mut offset = 0;
+    for edit in edits {
+        buf.push_str(&source[offset..edit.span.start]);
+        buf.push_str(&edit.text);
+        offset = edit.span.end;
+    }
+    buf.push_str(&source[offset..]);
+    buf
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /** Rename the name at the first match of a pattern in the source */
+    fn renamed(source: &str, at: &str, name: &str) -> Result<String, String> {
+        let offset = source.find(at).unwrap();
+        rename(source, offset, name).map(|edits| apply(source, &edits))
+    }
+
+    #[test]
+    fn test_rename() {
+        let source = "rate = 0.05\ntotal = 1000*(1 +\n  rate)\n\"{rate:.2}\"\nrate = rate*2";
+        assert_eq!(
+            renamed(source, "rate", "r").unwrap(),
+            "r = 0.05\ntotal = 1000*(1 +\n  r)\n\"{r:.2}\"\nr = r*2"
+        );
+        assert_eq!(
+            renamed("const g = 9.81\nf = m*g", "g", "gravity").unwrap(),
+            "const gravity = 9.81\nf = m*gravity"
+        );
+        assert_eq!(
+            renamed("a + b = 3\na - b = 1\nsolve a, b", "b =", "c").unwrap(),
+            "a + c = 3\na - c = 1\nsolve a, c"
+        );
+        // The cursor at the end of the name
+        assert_eq!(rename("ab = 1", 2, "c").unwrap()[0].span, 0..2);
+        assert_eq!(prepare_rename("x = 1 + y", 8), Ok(8..9));
+    }
+
+    #[test]
+    fn test_rename_scopes() {
+        let source = "x = 2\ny = let x = 3 in x*2 + 1\nz = x where x = x + 1\nd = diff(x^2, x, x)";
+        assert_eq!(
+            renamed(source, "x", "w").unwrap(),
+            "w = 2\ny = let x = 3 in x*2 + 1\nz = x where x = w + 1\nd = diff(x^2, x, w)"
+        );
+        assert_eq!(
+            renamed(source, "x*2", "k").unwrap(),
+            "x = 2\ny = let k = 3 in k*2 + 1\nz = x where x = x + 1\nd = diff(x^2, x, x)"
+        );
+        assert_eq!(
+            renamed(source, "x where", "k").unwrap(),
+            "x = 2\ny = let x = 3 in x*2 + 1\nz = k where k = x + 1\nd = diff(x^2, x, x)"
+        );
+        assert_eq!(
+            renamed(source, "x^2", "t").unwrap(),
+            "x = 2\ny = let x = 3 in x*2 + 1\nz = x where x = x + 1\nd = diff(t^2, t, x)"
+        );
+        // Sequential bindings see the previous ones
+        assert_eq!(
+            renamed("let a = 1, b = a in a + b", "a", "c").unwrap(),
+            "let c = 1, b = c in c + b"
+        );
+    }
+
+    #[test]
+    fn test_rename_refused() {
+        let err = |source: &str, at: &str, name: &str| renamed(source, at, name).unwrap_err();
+        assert_eq!(err("x = PI", "PI", "p"), "Cannot rename the builtin 'PI'");
+        assert_eq!(
+            err("x = sqrt(2)", "sqrt", "s"),
+            "Cannot rename the builtin 'sqrt'"
+        );
+        assert_eq!(err("x = 1 + 2", "+", "s"), "No variable to rename here");
+        assert_eq!(err("x = 1", "x", "E"), "'E' is a reserved name");
+        assert_eq!(err("x = 1", "x", "where"), "'where' is a reserved name");
+        assert_eq!(err("x = 1", "x", "a b"), "'a b' is not a valid name");
+        assert_eq!(err("x = 1", "x", "1a"), "'1a' is not a valid name");
+        // Merging two variables or capturing one by a local binding
+        assert_eq!(
+            err("x = 1\ny = 2", "x", "y"),
+            "Cannot rename to 'y', it would conflict with another 'y'"
+        );
+        assert!(renamed("y = 1\nz = let a = 2 in a + y", "a", "y").is_err());
+        assert!(renamed("x = 1\nz = let y = 2 in x + y", "x", "y").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{
+    highlighter::{spans, SpanKind},
+    interpreter::{evaluate, statements, Context, Outcome},
+};
+use std::ops::Range;
+
+/* Structured results for editors rendering lines with their own components instead of the
+formatted text. Spans are byte ranges in the source of the line. */
+
+/** An error with the span of the source it points at, if any */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub message: String,
+    pub span: Option<Range<usize>>,
+}
+
+impl Error {
+    /** An error pointing at a span of the source */
+    pub fn at(message: impl Into<String>, span: Range<usize>) -> Self {
+        Error {
+            message: message.into(),
+            span: Some(span),
+        }
+    }
+
+    /** Point at a span, unless the error already points at a more precise one */
+    pub(crate) fn or_at(self, span: Option<Range<usize>>) -> Self {
+        Error {
+            span: self.span.or(span),
+            ..self
+        }
+    }
+
+    /** Format the error as text, followed by the source line it points at and a caret line
+    under its span */
+    pub fn to_text(&self, source: &str) -> String {
+        let span = match &self.span {
+            Some(span) => span.start.min(source.len())..span.end.min(source.len()),
+            None => return self.message.clone(),
+        };
+        let line_start = source[..span.start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[span.start..]
+            .find('\n')
+            .map_or(source.len(), |i| span.start + i);
+        format!(
+            "{}\n{}\n{:>4$}{:^>5$}",
+            self.message,
+            source[line_start..line_end].trim_end_matches('\r'),
+            "",
+            "^",
+            span.start - line_start,
+            span.len().min(line_end - span.start).max(1)
+        )
+    }
+}
+
+impl From<String> for Error {
+    fn from(message: String) -> Self {
+        Error {
+            message,
+            span: None,
+        }
+    }
+}
+
+impl From<&str> for Error {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+impl From<Error> for String {
+    fn from(err: Error) -> Self {
+        err.message
+    }
+}
+
+/** Result of a line with its tokens */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    pub outcome: Result<Outcome, Error>,
+    pub tokens: Vec<(SpanKind, Range<usize>)>,
+}
+
+/** Compute a line and report its result */
+pub fn report(ctx: &mut Context, line: &str) -> Report {
+    Report {
+        outcome: evaluate(ctx, line),
+        tokens: spans(line),
+    }
+}
+
+/** Report of a statement in a document */
+#[derive(Debug, Clone, PartialEq)]
+pub struct StatementReport {
+    // Index of the first line of the statement
+    pub line: usize,
+    // Number of lines spanned by the statement
+    pub lines: usize,
+    pub report: Report,
+}
+
+/** Compute every statement of a document and report their results */
+pub fn report_batch(ctx: &mut Context, source: &str) -> Vec<StatementReport> {
+    let mut line = 0;
+    statements(source)
+        .map(|(statement, lines)| {
+            let report = StatementReport {
+                line,
+                lines,
+                report: report(ctx, statement),
+            };
+            line += lines;
+            report
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::interpreter::{Kind, Value};
+
+    #[test]
+    fn test_report() {
+        let mut ctx = Context::empty();
+        let outcome = |ctx: &mut Context, line: &str| report(ctx, line).outcome.unwrap();
+        let cases = [
+            ("", Kind::Empty, None, ""),
+            ("# Note", Kind::Comment, None, ""),
+            ("1 + 2", Kind::Value, Some(Value::Nb(3.)), "3"),
+            ("x = 4", Kind::Assignment, Some(Value::Nb(4.)), "x = 4"),
+            (
+                "const k = 2",
+                Kind::Assignment,
+                Some(Value::Nb(2.)),
+                "k = 2",
+            ),
+            (
+                "x = 1; \"x is {x}\"",
+                Kind::Print,
+                Some(Value::Str("x is 1".into())),
+                "\"x is 1\"",
+            ),
+            ("diff(x^2, x)", Kind::Expression, None, "2*x"),
+            ("solve(x^2 = 1, x, -2, 2)", Kind::Roots, None, "x = -1, 1"),
+            ("a + b = 3", Kind::Equation, None, "a + b = 3"),
+            ("a - b = 1", Kind::Equation, None, "a - b = 1"),
+            ("solve a, b", Kind::Solution, None, "a = 2, b = 1"),
+        ];
+        for (line, kind, value, text) in cases {
+            let outcome = outcome(&mut ctx, line);
+            assert_eq!(outcome.kind, kind, "{}", line);
+            assert_eq!(outcome.value, value, "{}", line);
+            assert_eq!(outcome.text, text, "{}", line);
+        }
+
+        let tokens = report(&mut ctx, "y = sqrt(2)").tokens;
+        assert_eq!(
+            tokens,
+            [
+                (SpanKind::Var, 0..1),
+                (SpanKind::Op, 2..3),
+                (SpanKind::Fun, 4..8),
+                (SpanKind::Nb, 9..10),
+            ]
+        );
+        let tokens = report(&mut ctx, "# c").tokens;
+        assert_eq!(tokens, [(SpanKind::Comment, 0..3)]);
+    }
+
+    #[test]
+    fn test_error_span() {
+        let mut ctx = Context::empty();
+        let error = |ctx: &mut Context, line: &str| report(ctx, line).outcome.unwrap_err();
+        let err = error(&mut ctx, "1 + * 2");
+        assert_eq!(err.span, Some(4..5), "{}", err.message);
+        assert!(!err.message.contains('^'));
+        let err = error(&mut ctx, "unknown + 1");
+        assert_eq!(err.span, Some(0..7));
+        assert_eq!(err.message, "Unknown variable 'unknown'");
+        // Runtime errors point at the name involved
+        let cases = [
+            ("x = 2; y = 1 + sqrt(x, 2)", 15..19),
+            ("const k = 1; k = 2", 13..14),
+            ("let PI = 3 in PI", 4..6),
+            ("(1 + 1)*f(2)", 8..9),
+            // Inside a numerical method, the failing operand or the bad variable
+            ("solve(w = 2, v)", 6..7),
+            ("1 + integrate(t, PI, 0, 1)", 17..19),
+            ("diff(t, 2*t, 1)", 0..4),
+        ];
+        for (line, span) in cases {
+            let err = error(&mut ctx, line);
+            assert_eq!(err.span, Some(span), "{}", err.message);
+        }
+        assert_eq!(error(&mut ctx, "\"1\" + 1").span, None);
+
+        let reports = report_batch(&mut ctx, "a = 1\nb = (a +\n  ) 2\nb");
+        let lines: Vec<_> = reports.iter().map(|r| (r.line, r.lines)).collect();
+        assert_eq!(lines, [(0, 1), (1, 2), (3, 1)]);
+        let err = reports[1].report.outcome.as_ref().unwrap_err();
+        assert_eq!(err.span, Some(11..12), "{}", err.message);
+
+        // A repeated line is located in each of its occurrences
+        let reports = report_batch(&mut ctx, "z = 1 + w\nz = 1 + w");
+        for report in &reports {
+            let err = report.report.outcome.as_ref().unwrap_err();
+            assert_eq!(err.span, Some(8..9), "{}", err.message);
+        }
+        assert_eq!(reports.len(), 2);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{
+    cst::{parse_cst, Element, LeafKind, Node, NodeKind},
+    interpreter::BINDING_FUNCTIONS,
+};
+use std::ops::Range;
+
+/* Name resolution on the concrete syntax tree. Worksheet variables and constants share a single
+namespace over the whole document, while the bindings of let and where, and the variable of
+diff, integrate and solve, are local to their expression and shadow it. */
+
+/** What a name refers to */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Symbol<'a> {
+    Global(&'a str),
+    Local(usize), // Offset of the binding
+    Function(&'a str),
+}
+
+/** How a name is used */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Role {
+    Write, // Assigned to a worksheet variable or constant
+    Bind,  // Bound to a local
+    Read,
+    Call,
+}
+
+/** A name in the source with what it refers to */
+#[derive(Debug, Clone)]
+pub(crate) struct Occurrence<'a> {
+    pub span: Range<usize>,
+    pub symbol: Symbol<'a>,
+    pub role: Role,
+    // Index of the statement, the statements of a line separated by ';' count separately
+    pub statement: usize,
+}
+
+/** Names resolved while walking the tree */
+struct Resolver<'a> {
+    source: &'a str,
+    // Local bindings in scope, innermost last
+    scopes: Vec<(&'a str, usize)>,
+    statement: usize,
+    occurrences: Vec<Occurrence<'a>>,
+}
+
+impl<'a> Resolver<'a> {
+    fn record(&mut self, span: Range<usize>, symbol: Symbol<'a>, role: Role) {
+        self.occurrences.push(Occurrence {
+            span,
+            symbol,
+            role,
+            statement: self.statement,
+        });
+    }
+
+    /** Record a reference to a name, local if it is bound */
+    fn reference(&mut self, span: Range<usize>) {
+        let id = &self.source[span.clone()];
+        let symbol = match self.scopes.iter().rev().find(|(name, _)| *name == id) {
+            Some((_, offset)) => Symbol::Local(*offset),
+            None => Symbol::Global(id),
+        };
+        self.record(span, symbol, Role::Read);
+    }
+
+    /** Bind a name until the scope is left */
+    fn bind(&mut self, span: Range<usize>) {
+        self.scopes.push((&self.source[span.clone()], span.start));
+        self.record(span.clone(), Symbol::Local(span.start), Role::Bind);
+    }
+
+    /** Identifier leaves directly under a node */
+    fn ids(node: &Node) -> Vec<Range<usize>> {
+        node.children()
+            .iter()
+            .filter_map(|child| match child {
+                Element::Leaf(leaf) if leaf.kind() == LeafKind::Id => Some(leaf.span()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn nodes(&mut self, nodes: &[&Node]) {
+        for node in nodes {
+            self.node(node);
+        }
+    }
+
+    /** Bind the names of binding nodes in order, each value seeing the previous names */
+    fn bindings(&mut self, bindings: &[&Node]) {
+        for binding in bindings {
+            self.nodes(&binding.nodes().collect::<Vec<_>>());
+            if let Some(name) = Self::ids(binding).pop() {
+                self.bind(name);
+            }
+        }
+    }
+
+    fn node(&mut self, node: &Node) {
+        let children: Vec<_> = node.nodes().collect();
+        let depth = self.scopes.len();
+        match node.kind() {
+            NodeKind::Var => {
+                for span in Self::ids(node) {
+                    self.reference(span);
+                }
+            }
+            NodeKind::Assign | NodeKind::Const | NodeKind::Solve => {
+                for span in Self::ids(node) {
+                    let symbol = Symbol::Global(&self.source[span.clone()]);
+                    self.record(span, symbol, Role::Write);
+                }
+                self.nodes(&children);
+            }
+            // let a = 1 in a or a where a = 1, the body is computed after the bindings
+            NodeKind::Let | NodeKind::Where => {
+                let (bindings, body): (Vec<_>, Vec<_>) = children
+                    .into_iter()
+                    .partition(|child| child.kind() == NodeKind::Binding);
+                self.bindings(&bindings);
+                self.nodes(&body);
+            }
+            NodeKind::Call => {
+                let name = Self::ids(node).remove(0);
+                let id = &self.source[name.clone()];
+                self.record(name, Symbol::Function(id), Role::Call);
+                match children.as_slice() {
+                    // diff(x^2, x), the variable is bound in the expression only
+                    [expr, var, rest @ ..]
+                        if BINDING_FUNCTIONS.contains(&id) && var.kind() == NodeKind::Var =>
+                    {
+                        if let Some(span) = Self::ids(var).pop() {
+                            self.bind(span);
+                        }
+                        self.node(expr);
+                        self.scopes.truncate(depth);
+                        self.nodes(rest);
+                    }
+                    args => self.nodes(args),
+                }
+            }
+            NodeKind::Line => {
+                for statement in children {
+                    self.statement += 1;
+                    self.node(statement);
+                }
+            }
+            // Names in invalid code are not resolved
+            NodeKind::Error => {}
+            _ => self.nodes(&children),
+        }
+        self.scopes.truncate(depth);
+    }
+}
+
+/** Resolve every name of a source, in source order */
+pub(crate) fn resolve(source: &str) -> Vec<Occurrence<'_>> {
+    resolve_tree(source, &parse_cst(source))
+}
+
+/** Resolve every name of a parsed source, in source order */
+pub(crate) fn resolve_tree<'a>(source: &'a str, document: &Node) -> Vec<Occurrence<'a>> {
+    let mut resolver = Resolver {
+        source,
+        scopes: Vec::new(),
+        statement: 0,
+        occurrences: Vec::new(),
+    };
+    resolver.node(document);
+    let mut occurrences = resolver.occurrences;
+    occurrences.sort_by_key(|occurrence| occurrence.span.start);
+    occurrences
+}
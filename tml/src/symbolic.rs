@@ -0,0 +1,442 @@
+use crate::{
+    interpreter::{function, variable},
+    parser::{BinOp, Fragment, Literal, UnOp},
+};
+
+/* Symbolic manipulation of literals. Derivatives are built by applying the usual
+differentiation rules, then simplified to keep them readable. */
+
+/** Differentiate a literal with respect to x and simplify the result */
+pub fn diff<'a>(lit: &Literal<'a>, x: &str) -> Result<Literal<'a>, String> {
+    derivative(lit, x).map(simplify)
+}
+
+/** Check if a literal depends on x */
+pub(crate) fn depends(lit: &Literal, x: &str) -> bool {
+    match lit {
+        Literal::Nb(..) | Literal::Str(_) => false,
+        Literal::Template(fragments) => fragments
+            .iter()
+            .any(|fragment| matches!(fragment, Fragment::Expr(lit, _) if depends(lit, x))),
+        Literal::Var(id) => *id == x,
+        Literal::UnaryOp(_, hs) => depends(hs, x),
+        Literal::BinaryOp(_, lits) | Literal::Equation(lits) => {
+            depends(&lits.0, x) || depends(&lits.1, x)
+        }
+        Literal::Fun(_, args) => args.iter().any(|arg| depends(arg, x)),
+        Literal::Let(bindings, body, _) => {
+            bindings.iter().any(|(_, lit)| depends(lit, x))
+                || (depends(body, x) && bindings.iter().all(|(id, _)| *id != x))
+        }
+    }
+}
+
+/** Collect the free variables of a literal, ignoring the ones bound by local bindings or by
+builtins like integrate */
+pub(crate) fn free_variables<'a>(lit: &Literal<'a>) -> Vec<&'a str> {
+    let mut vars = Vec::new();
+    collect_free(lit, &mut Vec::new(), &mut vars);
+    vars
+}
+
+fn collect_free<'a>(lit: &Literal<'a>, bound: &mut Vec<&'a str>, vars: &mut Vec<&'a str>) {
+    match lit {
+        Literal::Nb(..) | Literal::Str(_) => {}
+        Literal::Var(id) => {
+            if !bound.contains(id) && !vars.contains(id) {
+                vars.push(id)
+            }
+        }
+        Literal::Template(fragments) => {
+            for fragment in fragments {
+                if let Fragment::Expr(lit, _) = fragment {
+                    collect_free(lit, bound, vars);
+                }
+            }
+        }
+        Literal::UnaryOp(_, hs) => collect_free(hs, bound, vars),
+        Literal::BinaryOp(_, lits) | Literal::Equation(lits) => {
+            collect_free(&lits.0, bound, vars);
+            collect_free(&lits.1, bound, vars);
+        }
+        // The variable of diff, integrate and solve is bound in their first argument
+        Literal::Fun("diff" | "integrate" | "solve", args) if args.len() >= 2 => {
+            match &args[1] {
+                Literal::Var(x) => {
+                    bound.push(x);
+                    collect_free(&args[0], bound, vars);
+                    bound.pop();
+                }
+                arg => {
+                    collect_free(&args[0], bound, vars);
+                    collect_free(arg, bound, vars);
+                }
+            }
+            for arg in &args[2..] {
+                collect_free(arg, bound, vars);
+            }
+        }
+        Literal::Fun(_, args) => {
+            for arg in args {
+                collect_free(arg, bound, vars);
+            }
+        }
+        Literal::Let(bindings, body, _) => {
+            let len = bound.len();
+            for (id, lit) in bindings {
+                collect_free(lit, bound, vars);
+                bound.push(id);
+            }
+            collect_free(body, bound, vars);
+            bound.truncate(len);
+        }
+    }
+}
+
+/** Apply differentiation rules, the result is not simplified */
+fn derivative<'a>(lit: &Literal<'a>, x: &str) -> Result<Literal<'a>, String> {
+    if !depends(lit, x) {
+        return Ok(nb(0.));
+    }
+    Ok(match lit {
+        Literal::Nb(..) | Literal::Str(_) => nb(0.),
+        Literal::Var(_) => nb(1.),
+        Literal::UnaryOp(op, hs) => match op {
+            UnOp::Add => derivative(hs, x)?,
+            UnOp::Sub => neg(derivative(hs, x)?),
+        },
+        Literal::BinaryOp(op, lits) => {
+            let (u, v) = (&lits.0, &lits.1);
+            let (du, dv) = (derivative(u, x)?, derivative(v, x)?);
+            match op {
+                BinOp::Add => bin(BinOp::Add, du, dv),
+                BinOp::Sub => bin(BinOp::Sub, du, dv),
+                // (uv)' = u'v + uv'
+                BinOp::Mul => bin(
+                    BinOp::Add,
+                    bin(BinOp::Mul, du, v.clone()),
+                    bin(BinOp::Mul, u.clone(), dv),
+                ),
+                // (u/v)' = (u'v - uv') / v^2
+                BinOp::Div => bin(
+                    BinOp::Div,
+                    bin(
+                        BinOp::Sub,
+                        bin(BinOp::Mul, du, v.clone()),
+                        bin(BinOp::Mul, u.clone(), dv),
+                    ),
+                    bin(BinOp::Pow, v.clone(), nb(2.)),
+                ),
+                // u%v = u - v*trunc(u/v) and trunc is flat almost everywhere
+                BinOp::Mod => bin(
+                    BinOp::Sub,
+                    du,
+                    bin(
+                        BinOp::Mul,
+                        dv,
+                        fun("trunc", bin(BinOp::Div, u.clone(), v.clone())),
+                    ),
+                ),
+                BinOp::Pow if !depends(v, x) => bin(
+                    BinOp::Mul,
+                    bin(
+                        BinOp::Mul,
+                        v.clone(),
+                        bin(BinOp::Pow, u.clone(), bin(BinOp::Sub, v.clone(), nb(1.))),
+                    ),
+                    du,
+                ),
+                BinOp::Pow if !depends(u, x) => bin(
+                    BinOp::Mul,
+                    bin(BinOp::Mul, lit.clone(), fun("ln", u.clone())),
+                    dv,
+                ),
+                // (u^v)' = u^v * (v'ln(u) + vu'/u)
+                BinOp::Pow => bin(
+                    BinOp::Mul,
+                    lit.clone(),
+                    bin(
+                        BinOp::Add,
+                        bin(BinOp::Mul, dv, fun("ln", u.clone())),
+                        bin(BinOp::Div, bin(BinOp::Mul, v.clone(), du), u.clone()),
+                    ),
+                ),
+            }
+        }
+        Literal::Equation(_) => return Err("Cannot differentiate an equation".into()),
+        Literal::Template(_) => return Err("Cannot differentiate a string".into()),
+        Literal::Let(..) => return Err("Cannot differentiate local bindings".into()),
+        Literal::Fun("diff", args) if args.len() == 2 => {
+            let inner = diff(&args[0], variable(&args[1])?)?;
+            derivative(&inner, x)?
+        }
+        Literal::Fun(name, args) => match args.as_slice() {
+            [u] if function(name).is_some() => {
+                let du = derivative(u, x)?;
+                let u = u.clone();
+                match *name {
+                    "floor" | "ceil" | "round" | "trunc" => nb(0.),
+                    "fract" => du,
+                    "sqrt" => bin(BinOp::Div, du, bin(BinOp::Mul, nb(2.), fun("sqrt", u))),
+                    "exp" => bin(BinOp::Mul, fun("exp", u), du),
+                    "ln" => bin(BinOp::Div, du, u),
+                    "log2" => bin(BinOp::Div, du, bin(BinOp::Mul, u, fun("ln", nb(2.)))),
+                    "log10" => bin(BinOp::Div, du, bin(BinOp::Mul, u, fun("ln", nb(10.)))),
+                    "cos" => neg(bin(BinOp::Mul, fun("sin", u), du)),
+                    "sin" => bin(BinOp::Mul, fun("cos", u), du),
+                    "tan" => bin(BinOp::Div, du, bin(BinOp::Pow, fun("cos", u), nb(2.))),
+                    "acos" => neg(bin(
+                        BinOp::Div,
+                        du,
+                        fun("sqrt", bin(BinOp::Sub, nb(1.), bin(BinOp::Pow, u, nb(2.)))),
+                    )),
+                    "asin" => bin(
+                        BinOp::Div,
+                        du,
+                        fun("sqrt", bin(BinOp::Sub, nb(1.), bin(BinOp::Pow, u, nb(2.)))),
+                    ),
+                    "atan" => bin(
+                        BinOp::Div,
+                        du,
+                        bin(BinOp::Add, nb(1.), bin(BinOp::Pow, u, nb(2.))),
+                    ),
+                    _ => return Err(format!("No differentiation rule for '{}'", name)),
+                }
+            }
+            _ => return Err(format!("Cannot differentiate '{}'", lit)),
+        },
+    })
+}
+
+/** Simplify a literal bottom-up, folding constants and removing neutral elements */
+pub fn simplify(lit: Literal) -> Literal {
+    match lit {
+        Literal::UnaryOp(op, hs) => match op {
+            UnOp::Add => simplify(*hs),
+            UnOp::Sub => neg(simplify(*hs)),
+        },
+        Literal::BinaryOp(op, lits) => {
+            let (l, r) = *lits;
+            simplify_binary(op, simplify(l), simplify(r))
+        }
+        Literal::Equation(sides) => {
+            let (l, r) = *sides;
+            Literal::Equation(Box::new((simplify(l), simplify(r))))
+        }
+        Literal::Fun(name, args) => {
+            let args: Vec<_> = args.into_iter().map(simplify).collect();
+            match (function(name), args.as_slice()) {
+                (Some(fun), [Literal::Nb(value, _)]) if is_round(fun(*value)) => nb(fun(*value)),
+                _ => Literal::Fun(name, args),
+            }
+        }
+        lit => lit,
+    }
+}
+
+fn simplify_binary<'a>(op: BinOp, l: Literal<'a>, r: Literal<'a>) -> Literal<'a> {
+    if let (Literal::Nb(a, _), Literal::Nb(b, _)) = (&l, &r) {
+        let (a, b) = (*a, *b);
+        let folded = match op {
+            BinOp::Add => a + b,
+            BinOp::Sub => a - b,
+            BinOp::Mul => a * b,
+            BinOp::Mod => a % b,
+            // Keep fractions and irrational powers readable, reduced fractions are kept as is
+            BinOp::Div if is_round(a / b) => a / b,
+            BinOp::Div => return reduce(a, b),
+            BinOp::Pow => Some(a.powf(b))
+                .filter(|nb| is_round(*nb))
+                .unwrap_or(f64::NAN),
+        };
+        if folded.is_finite() {
+            return nb(folded);
+        }
+    }
+    // Combine like terms: 2*x - x => x
+    if matches!(op, BinOp::Add | BinOp::Sub) {
+        let ((a, u), (b, v)) = (coefficient(&l), coefficient(&r));
+        if u == v && !matches!(u, Literal::Nb(..)) {
+            let sum = if op == BinOp::Add { a + b } else { a - b };
+            return simplify_binary(BinOp::Mul, nb(sum), u.clone());
+        }
+    }
+    match op {
+        BinOp::Add if is(&l, 0.) => r,
+        BinOp::Add if is(&r, 0.) => l,
+        BinOp::Add => match r {
+            Literal::UnaryOp(UnOp::Sub, v) => bin(BinOp::Sub, l, *v),
+            Literal::Nb(value, _) if value < 0. => bin(BinOp::Sub, l, nb(-value)),
+            r => bin(BinOp::Add, l, r),
+        },
+        BinOp::Sub if is(&r, 0.) => l,
+        BinOp::Sub if is(&l, 0.) => neg(r),
+        BinOp::Sub if l == r => nb(0.),
+        BinOp::Sub => match r {
+            Literal::UnaryOp(UnOp::Sub, v) => bin(BinOp::Add, l, *v),
+            Literal::Nb(value, _) if value < 0. => bin(BinOp::Add, l, nb(-value)),
+            r => bin(BinOp::Sub, l, r),
+        },
+        BinOp::Mul if is(&l, 0.) || is(&r, 0.) => nb(0.),
+        BinOp::Mul if is(&l, 1.) => r,
+        BinOp::Mul if is(&r, 1.) => l,
+        BinOp::Mul if is(&l, -1.) => neg(r),
+        BinOp::Mul if is(&r, -1.) => neg(l),
+        BinOp::Mul => match (l, r) {
+            (Literal::UnaryOp(UnOp::Sub, u), r) => neg(simplify_binary(BinOp::Mul, *u, r)),
+            (l, Literal::UnaryOp(UnOp::Sub, v)) => neg(simplify_binary(BinOp::Mul, l, *v)),
+            // Move coefficients first: x*2 => 2*x
+            (l, r @ Literal::Nb(..)) if !matches!(l, Literal::Nb(..)) => {
+                simplify_binary(BinOp::Mul, r, l)
+            }
+            // Merge coefficients: 2*(3*x) => 6*x
+            (a @ Literal::Nb(..), Literal::BinaryOp(BinOp::Mul, lits))
+                if matches!(lits.0, Literal::Nb(..)) =>
+            {
+                let (b, v) = *lits;
+                simplify_binary(BinOp::Mul, simplify_binary(BinOp::Mul, a, b), v)
+            }
+            (l, r) => bin(BinOp::Mul, l, r),
+        },
+        BinOp::Div if is(&r, 1.) => l,
+        BinOp::Div if is(&l, 0.) => nb(0.),
+        BinOp::Div if l == r => nb(1.),
+        BinOp::Div => match (l, r) {
+            (Literal::UnaryOp(UnOp::Sub, u), r) => neg(simplify_binary(BinOp::Div, *u, r)),
+            (l, Literal::UnaryOp(UnOp::Sub, v)) => neg(simplify_binary(BinOp::Div, l, *v)),
+            (l, r) => bin(BinOp::Div, l, r),
+        },
+        BinOp::Pow if is(&r, 0.) || is(&l, 1.) => nb(1.),
+        BinOp::Pow if is(&r, 1.) => l,
+        _ => bin(op, l, r),
+    }
+}
+
+/** Reduce a fraction of integers by their greatest common divisor: 6/9 => 2/3 */
+fn reduce<'a>(a: f64, b: f64) -> Literal<'a> {
+    // Integers beyond 2^53 are not exact, their fraction is kept as is
+    const EXACT: f64 = 9007199254740992.;
+    if !(is_round(a) && is_round(b)) || a.abs() > EXACT || b.abs() > EXACT || b == 0. {
+        return bin(BinOp::Div, nb(a), nb(b));
+    }
+    let (mut x, mut y) = (a.abs(), b.abs());
+    while y != 0. {
+        (x, y) = (y, x % y);
+    }
+    let gcd = x.copysign(b);
+    bin(BinOp::Div, nb(a / gcd), nb(b / gcd))
+}
+
+/** Split a term into its coefficient and the rest: -2*x => (-2, x) */
+fn coefficient<'l, 'a>(lit: &'l Literal<'a>) -> (f64, &'l Literal<'a>) {
+    match lit {
+        Literal::UnaryOp(UnOp::Sub, hs) => {
+            let (c, rest) = coefficient(hs);
+            (-c, rest)
+        }
+        Literal::BinaryOp(BinOp::Mul, lits) => match &lits.0 {
+            Literal::Nb(c, _) => (*c, &lits.1),
+            _ => (1., lit),
+        },
+        lit => (1., lit),
+    }
+}
+
+fn is(lit: &Literal, value: f64) -> bool {
+    matches!(lit, Literal::Nb(nb, _) if *nb == value)
+}
+
+fn is_round(nb: f64) -> bool {
+    nb.is_finite() && nb.fract() == 0.
+}
+
+fn nb<'a>(nb: f64) -> Literal<'a> {
+    Literal::Nb(nb, None)
+}
+
+fn neg(lit: Literal) -> Literal {
+    match lit {
+        Literal::Nb(value, _) => nb(-value),
+        Literal::UnaryOp(UnOp::Sub, hs) => *hs,
+        // Negate the coefficient: -(2*x) => -2*x
+        Literal::BinaryOp(op @ (BinOp::Mul | BinOp::Div), lits)
+            if matches!(lits.0, Literal::Nb(..)) =>
+        {
+            let (l, r) = *lits;
+            bin(op, neg(l), r)
+        }
+        lit => Literal::UnaryOp(UnOp::Sub, Box::new(lit)),
+    }
+}
+
+fn bin<'a>(op: BinOp, l: Literal<'a>, r: Literal<'a>) -> Literal<'a> {
+    Literal::BinaryOp(op, Box::new((l, r)))
+}
+
+fn fun<'a>(name: &'a str, arg: Literal<'a>) -> Literal<'a> {
+    Literal::Fun(name, vec![arg])
+}
+
+#[cfg(test)]
+mod test {
+    use crate::interpreter::{compute, Context, FUNCTIONS};
+    use crate::lexer::Lexer;
+    use crate::parser::{parse, Expression, Line, Literal};
+    use crate::symbolic::diff;
+
+    fn parse_lit(str: &str) -> Literal<'_> {
+        match parse(Lexer::load(str)) {
+            Ok(Line::Exprs(mut exprs)) if exprs.len() == 1 => match exprs.remove(0) {
+                Expression::Literal(lit) => lit,
+                other => panic!("{:?}", other),
+            },
+            other => panic!("{:?}", other),
+        }
+    }
+
+    fn assert_diff(str: &str, expected: &str) {
+        let result = diff(&parse_lit(str), "x");
+        assert!(result.is_ok(), "{:?}", result);
+        assert_eq!(result.unwrap().to_string(), expected)
+    }
+
+    #[test]
+    fn test_rules() {
+        assert_diff("3", "0");
+        assert_diff("x", "1");
+        assert_diff("y*x", "y");
+        assert_diff("x^2*sin(x)", "2*x*sin(x) + x^2*cos(x)");
+        assert_diff("x^3 - 2*x", "3*x^2 - 2");
+        assert_diff("cos(2*x)", "-2*sin(2*x)");
+        assert_diff("1/x", "-1/x^2");
+        assert_diff("2^x", "2^x*ln(2)");
+        assert_diff("x^x", "x^x*(ln(x) + 1)");
+        assert_diff("diff(x^3, x)", "6*x");
+        // Fractions are reduced and like terms combined
+        assert_diff("2*x/3", "2/3");
+        assert_diff("-4*x/6", "-2/3");
+        assert_diff("x*(-x)", "-2*x");
+        assert_diff("x*x", "2*x");
+    }
+
+    #[test]
+    fn test_builtins() {
+        // Compare every builtin derivative with a central finite difference
+        for (name, _) in FUNCTIONS {
+            let mut ctx = Context::empty();
+            let at = 0.3;
+            let h = 1e-6;
+            let value = |ctx: &mut Context, x: f64| {
+                compute(ctx, &format!("{}({})", name, x))
+                    .unwrap()
+                    .parse::<f64>()
+                    .unwrap()
+            };
+            let expected = (value(&mut ctx, at + h) - value(&mut ctx, at - h)) / (2. * h);
+            let result = compute(&mut ctx, &format!("diff({}(x), x, {})", name, at));
+            assert!(result.is_ok(), "{}: {:?}", name, result);
+            let result = result.unwrap().parse::<f64>().unwrap();
+            assert!((result - expected).abs() < 1e-6, "{}: {}", name, result);
+        }
+    }
+}
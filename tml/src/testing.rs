@@ -0,0 +1,75 @@
+use crate::{
+    lexer::Lexer,
+    parser::{parse, Expression, Line, Literal},
+};
+use proptest::{prelude::*, strategy::Union};
+
+/* Helpers shared by the tests of the modules computing literals in several ways, which must
+give the same results as the interpreter. */
+
+/** Parse a source holding a single literal */
+pub(crate) fn literal(code: &str) -> Literal<'_> {
+    match parse(Lexer::load(code)) {
+        Ok(Line::Exprs(mut exprs)) => match exprs.remove(0) {
+            Expression::Literal(lit) => lit,
+            expr => panic!("Expected a literal, got {:?}", expr),
+        },
+        line => panic!("Expected a literal, got {:?}", line),
+    }
+}
+
+/** Bits of a number, every NaN having the same bits */
+pub(crate) fn bits(nb: f64) -> u64 {
+    if nb.is_nan() {
+        f64::NAN.to_bits()
+    } else {
+        nb.to_bits()
+    }
+}
+
+/** Random expressions of x, y and PI. Unless they are only numerical, they also read the
+string s and integrate over y, which the bytecode cannot compile. */
+pub(crate) fn expression(numerical: bool) -> impl Strategy<Value = String> {
+    let mut names = vec!["x", "y", "PI"];
+    if !numerical {
+        names.push("s");
+    }
+    let mut leaves: Vec<_> = names
+        .into_iter()
+        .map(|name| Just(name.to_string()).boxed())
+        .collect();
+    leaves.push(
+        prop::sample::select(vec!["0", "1", "2", "0.5", "(10^308)", "(-0)", "3"])
+            .prop_map(String::from)
+            .boxed(),
+    );
+    Union::new(leaves).prop_recursive(4, 32, 2, move |inner| {
+        let mut branches = vec![
+            (
+                inner.clone(),
+                prop::sample::select(vec!["+", "-", "*", "/", "%", "^"]),
+                inner.clone(),
+            )
+                .prop_map(|(l, op, r)| format!("({}{}{})", l, op, r))
+                .boxed(),
+            inner.clone().prop_map(|e| format!("-{}", e)).boxed(),
+            (
+                prop::sample::select(vec!["sqrt", "ln", "sin", "floor", "exp"]),
+                inner.clone(),
+            )
+                .prop_map(|(f, e)| format!("{}({})", f, e))
+                .boxed(),
+            (inner.clone(), inner.clone())
+                .prop_map(|(v, e)| format!("(let x = {} in {})", v, e))
+                .boxed(),
+        ];
+        if !numerical {
+            branches.push(
+                (inner.clone(), inner)
+                    .prop_map(|(e, b)| format!("integrate({}, y, 0, {})", e, b))
+                    .boxed(),
+            );
+        }
+        Union::new(branches)
+    })
+}
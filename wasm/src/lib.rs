@@ -1,5 +1,14 @@
-use std::fmt::Write;
-use tml::{highlighter::HtmlHighlighter, interpreter::Context};
+use std::{
+    cell::{Cell, RefCell},
+    fmt::Write,
+};
+use tml::{
+    highlighter::{spans, HtmlHighlighter, SpanKind},
+    interpreter::{statements, Context, Settings, Value},
+    lint::Lint,
+    query,
+    report::{report, report_batch, Report},
+};
 use wasm_bindgen::prelude::*;
 use wee_alloc;
 
@@ -12,16 +21,207 @@ pub fn start() {
     console_error_panic_hook::set_once();
 }
 
-/** Execute a single line */
-#[wasm_bindgen]
-pub fn execute(line: &str) -> String {
+thread_local! {
+    // Settings shared by every execution
+    static SETTINGS: Cell<Settings> = Cell::new(Settings::default());
+    // Document of the queries, evaluated incrementally between calls
+    static QUERIES: RefCell<tml::document::Document> = RefCell::default();
+}
+
+/** Create a context using the current settings */
+fn context() -> Context {
     let mut ctx = Context::empty();
-    match tml::interpreter::compute(&mut ctx, line) {
+    ctx.set_settings(SETTINGS.with(Cell::get));
+    ctx
+}
+
+/** Set an option of the settings shared by every execution, either of the number format:
+digits, decimals, notation, group or trim, or of the evaluation: decimal, rounding or
+reactive */
+#[wasm_bindgen]
+pub fn set_option(option: &str, value: &str) -> Result<(), JsValue> {
+    let mut settings = SETTINGS.with(Cell::get);
+    settings
+        .set(option, value)
+        .map_err(|err| JsValue::from_str(&err))?;
+    SETTINGS.with(|cell| cell.set(settings));
+    Ok(())
+}
+
+/** Compute a line, returning the highlighted result or the error */
+fn compute_html(ctx: &mut Context, line: &str) -> String {
+    match tml::interpreter::compute(ctx, line) {
         Ok(e) => highlight(&e),
         Err(e) => format!("<span class=\"error\">{}</span>", e),
     }
 }
 
+/** Execute a single line */
+#[wasm_bindgen]
+pub fn execute(line: &str) -> String {
+    compute_html(&mut context(), line)
+}
+
+/** Convert a value to a JS number or string */
+fn to_js(value: &Value) -> JsValue {
+    match value {
+        Value::Str(str) => JsValue::from_str(str),
+        nb => JsValue::from_f64(nb.as_f64().unwrap()),
+    }
+}
+
+fn set(object: &js_sys::Object, key: &str, value: impl Into<JsValue>) {
+    js_sys::Reflect::set(object, &JsValue::from_str(key), &value.into()).unwrap();
+}
+
+/** Convert a byte offset to an UTF-16 offset, as used by JS strings */
+fn utf16(source: &str, offset: usize) -> u32 {
+    source[..offset].encode_utf16().count() as u32
+}
+
+fn span_to_js(object: &js_sys::Object, source: &str, span: &std::ops::Range<usize>) {
+    set(object, "start", utf16(source, span.start));
+    set(object, "end", utf16(source, span.end));
+}
+
+fn tokens_to_js(source: &str, tokens: &[(SpanKind, std::ops::Range<usize>)]) -> js_sys::Array {
+    tokens
+        .iter()
+        .map(|(kind, span)| {
+            let token = js_sys::Object::new();
+            set(&token, "kind", kind.name());
+            span_to_js(&token, source, span);
+            JsValue::from(token)
+        })
+        .collect()
+}
+
+/** Convert a report to an object { kind, value, text, error, tokens }, the kind being
+"error" on failure with error { message, start, end } */
+fn report_to_js(source: &str, report: &Report) -> js_sys::Object {
+    let object = js_sys::Object::new();
+    match &report.outcome {
+        Ok(outcome) => {
+            set(&object, "kind", outcome.kind.name());
+            set(&object, "value", outcome.value.as_ref().map_or(JsValue::NULL, to_js));
+            set(&object, "text", outcome.text.as_str());
+            set(&object, "error", JsValue::NULL);
+        }
+        Err(err) => {
+            let error = js_sys::Object::new();
+            set(&error, "message", err.message.as_str());
+            match &err.span {
+                Some(span) => span_to_js(&error, source, span),
+                None => {
+                    set(&error, "start", JsValue::NULL);
+                    set(&error, "end", JsValue::NULL);
+                }
+            }
+            set(&object, "kind", "error");
+            set(&object, "value", JsValue::NULL);
+            set(&object, "text", err.message.as_str());
+            set(&object, "error", error);
+        }
+    }
+    set(&object, "tokens", tokens_to_js(source, &report.tokens));
+    object
+}
+
+/** Execute a single line, returning a structured result */
+#[wasm_bindgen]
+pub fn evaluate(line: &str) -> JsValue {
+    report_to_js(line, &report(&mut context(), line)).into()
+}
+
+/** Execute multiple lines, returning a structured result per statement with the index of its
+first line and its number of lines. Spans are relative to the statement. */
+#[wasm_bindgen]
+pub fn evaluate_batch(lines: &str) -> js_sys::Array {
+    let sources: Vec<_> = statements(lines).map(|(source, _)| source).collect();
+    report_batch(&mut context(), lines)
+        .iter()
+        .zip(sources)
+        .map(|(statement, source)| {
+            let object = report_to_js(source, &statement.report);
+            set(&object, "line", statement.line as u32);
+            set(&object, "lines", statement.lines as u32);
+            JsValue::from(object)
+        })
+        .collect()
+}
+
+/** List the tokens of a line with their kind */
+#[wasm_bindgen]
+pub fn tokenize(line: &str) -> js_sys::Array {
+    tokens_to_js(line, &spans(line))
+}
+
+/** Evaluation session keeping its variables between executions */
+#[wasm_bindgen]
+pub struct Session {
+    ctx: Context,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Session {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Session { ctx: context() }
+    }
+
+    /** Execute a single line, using the current settings */
+    pub fn execute(&mut self, line: &str) -> String {
+        self.ctx.set_settings(SETTINGS.with(Cell::get));
+        compute_html(&mut self.ctx, line)
+    }
+
+    /** Execute a single line, returning a structured result */
+    pub fn evaluate(&mut self, line: &str) -> JsValue {
+        self.ctx.set_settings(SETTINGS.with(Cell::get));
+        report_to_js(line, &report(&mut self.ctx, line)).into()
+    }
+
+    /** Set a variable from a number or a string */
+    pub fn set_variable(&mut self, name: &str, value: JsValue) -> Result<(), JsValue> {
+        self.ctx.set_settings(SETTINGS.with(Cell::get));
+        let value = if let Some(nb) = value.as_f64() {
+            self.ctx.number(nb)
+        } else if let Some(str) = value.as_string() {
+            Ok(Value::Str(str))
+        } else {
+            Err(format!("Cannot set '{}', expected a number or a string", name))
+        };
+        value
+            .and_then(|value| self.ctx.set_variable(name, value))
+            .map_err(|err| JsValue::from_str(&err))
+    }
+
+    /** Value of a variable, undefined if it is not set */
+    pub fn get_variable(&self, name: &str) -> JsValue {
+        self.ctx.variable(name).map_or(JsValue::UNDEFINED, to_js)
+    }
+
+    /** Object mapping each variable to its value */
+    pub fn variables(&self) -> js_sys::Object {
+        let object = js_sys::Object::new();
+        for (name, value) in self.ctx.variables() {
+            js_sys::Reflect::set(&object, &JsValue::from_str(&name), &to_js(&value)).unwrap();
+        }
+        object
+    }
+
+    /** Forget every variable, constant and equation */
+    pub fn reset(&mut self) {
+        self.ctx = context();
+    }
+}
+
 #[wasm_bindgen]
 pub struct BatchResult {
     content: String,
@@ -39,30 +239,185 @@ impl BatchResult {
     }
 }
 
-/** Execute multiple line in a batch */
-#[wasm_bindgen]
-pub fn execute_batch(lines: &str) -> BatchResult {
-    let mut ctx = Context::empty();
-    lines.lines().fold(
+impl BatchResult {
+    fn new() -> Self {
         BatchResult {
             content: String::new(),
             lines_height: Vec::new(),
-        },
-        |mut acc, line| match tml::interpreter::compute(&mut ctx, line) {
+        }
+    }
+
+    /** Push the result of a statement, attached to its last line */
+    fn push(&mut self, result: Result<&str, &str>, nb_lines: usize) {
+        for _ in 1..nb_lines {
+            self.content.push('\n');
+            self.lines_height.push(1);
+        }
+        match result {
             Ok(line) => {
-                tml::highlighter::highlight(&mut acc.content, &line, HtmlHighlighter).unwrap();
-                acc.content.push('\n');
-                acc.lines_height.push(1);
-                return acc;
+                tml::highlighter::highlight(&mut self.content, line, HtmlHighlighter).unwrap();
+                self.content.push('\n');
+                self.lines_height.push(1);
             }
             Err(e) => {
-                writeln!(&mut acc.content, "<span class=\"error\">{}</span>", e).unwrap();
-                acc.lines_height
+                writeln!(&mut self.content, "<span class=\"error\">{}</span>", e).unwrap();
+                self.lines_height
                     .push(e.chars().filter(|c| *c == '\n').count() as u16 + 1);
-                acc
             }
-        },
-    )
+        }
+    }
+}
+
+/** Execute multiple line in a batch, the result of a statement spanning multiple lines is
+attached to its last line */
+#[wasm_bindgen]
+pub fn execute_batch(lines: &str) -> BatchResult {
+    let mut ctx = context();
+    let mut acc = BatchResult::new();
+    for (statement, nb_lines) in statements(lines) {
+        let result = tml::interpreter::compute(&mut ctx, statement);
+        acc.push(result.as_deref().map_err(String::as_str), nb_lines);
+    }
+    acc
+}
+
+/** Document of the editor, only the statements affected by an edit are computed again */
+#[wasm_bindgen]
+pub struct Document {
+    document: tml::document::Document,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[wasm_bindgen]
+impl Document {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Document {
+            document: tml::document::Document::new(SETTINGS.with(Cell::get)),
+        }
+    }
+
+    /** Update the document to the new code, using the current settings */
+    pub fn update(&mut self, code: &str) -> BatchResult {
+        self.document.set_settings(SETTINGS.with(Cell::get));
+        self.document.update(code);
+        let mut acc = BatchResult::new();
+        for statement in self.document.statements() {
+            let result = statement.result().map_err(|err| err.to_text(statement.source()));
+            acc.push(result.as_deref().map_err(String::as_str), statement.lines());
+        }
+        acc
+    }
+
+    /** List the completions at an offset of the code, see `complete` */
+    pub fn complete(&mut self, code: &str, offset: u32) -> js_sys::Array {
+        complete_in(&mut self.document, code, offset)
+    }
+
+    /** Describe the name at an offset of the code, see `hover` */
+    pub fn hover(&mut self, code: &str, offset: u32) -> JsValue {
+        hover_in(&mut self.document, code, offset)
+    }
+}
+
+/** Convert an UTF-16 offset, as used by JS strings, to a byte offset */
+fn byte_offset(source: &str, offset: u32) -> usize {
+    let mut utf16 = 0;
+    for (idx, c) in source.char_indices() {
+        if utf16 >= offset as usize {
+            return idx;
+        }
+        utf16 += c.len_utf16();
+    }
+    source.len()
+}
+
+fn complete_in(document: &mut tml::document::Document, code: &str, offset: u32) -> js_sys::Array {
+    document.set_settings(SETTINGS.with(Cell::get));
+    query::complete(document, code, byte_offset(code, offset))
+        .into_iter()
+        .map(|completion| {
+            let object = js_sys::Object::new();
+            set(&object, "label", completion.label);
+            set(&object, "kind", completion.kind.name());
+            set(&object, "detail", completion.detail);
+            JsValue::from(object)
+        })
+        .collect()
+}
+
+fn hover_in(document: &mut tml::document::Document, code: &str, offset: u32) -> JsValue {
+    document.set_settings(SETTINGS.with(Cell::get));
+    match query::hover(document, code, byte_offset(code, offset)) {
+        Some(hover) => {
+            let object = js_sys::Object::new();
+            set(&object, "contents", hover.contents);
+            span_to_js(&object, code, &hover.span);
+            object.into()
+        }
+        None => JsValue::NULL,
+    }
+}
+
+/** List the completions of the name before an offset: builtins, constants and variables
+defined above the statement, as { label, kind, detail } */
+#[wasm_bindgen]
+pub fn complete(document: &str, offset: u32) -> js_sys::Array {
+    QUERIES.with(|queries| complete_in(&mut queries.borrow_mut(), document, offset))
+}
+
+/** Describe the name at an offset as { contents, start, end }, null if there is nothing */
+#[wasm_bindgen]
+pub fn hover(document: &str, offset: u32) -> JsValue {
+    QUERIES.with(|queries| hover_in(&mut queries.borrow_mut(), document, offset))
+}
+
+/** Rename the variable at an offset everywhere it is used, as a list of { start, end, text }
+edits in code order. Builtins and renames changing what a name refers to are refused. */
+#[wasm_bindgen]
+pub fn rename(code: &str, offset: u32, name: &str) -> Result<js_sys::Array, JsValue> {
+    let edits = tml::rename::rename(code, byte_offset(code, offset), name)
+        .map_err(|err| JsValue::from_str(&err))?;
+    Ok(edits
+        .into_iter()
+        .map(|edit| {
+            let object = js_sys::Object::new();
+            span_to_js(&object, code, &edit.span);
+            set(&object, "text", edit.text);
+            JsValue::from(object)
+        })
+        .collect())
+}
+
+/** Lint code as a list of { id, message, start, end } warnings in code order, except the lints
+allowed by a comma separated list of ids such as "unused-variable, nan-call" */
+#[wasm_bindgen]
+pub fn lint(code: &str, allow: &str) -> js_sys::Array {
+    let allow: Vec<_> = allow
+        .split(',')
+        .filter_map(|id| Lint::from_id(id.trim()))
+        .collect();
+    tml::lint::lint(code, &allow)
+        .into_iter()
+        .map(|warning| {
+            let object = js_sys::Object::new();
+            set(&object, "id", warning.lint.id());
+            set(&object, "message", warning.message);
+            span_to_js(&object, code, &warning.span);
+            JsValue::from(object)
+        })
+        .collect()
+}
+
+/** Format code, see `tml fmt` */
+#[wasm_bindgen]
+pub fn format(code: &str) -> String {
+    tml::formatter::format_source(code)
 }
 
 /** Highlight single line */
@@ -77,10 +432,7 @@ pub fn highlight(line: &str) -> String {
 #[wasm_bindgen]
 pub fn highlight_batch(lines: &str) -> BatchResult {
     lines.lines().fold(
-        BatchResult {
-            content: String::new(),
-            lines_height: Vec::new(),
-        },
+        BatchResult::new(),
         |mut acc, line| {
             tml::highlighter::highlight(&mut acc.content, line, HtmlHighlighter).unwrap();
             acc.content.push('\n');
@@ -40,6 +40,18 @@ b = 32
 "hypotenuse = sqrt(a*a+b*b)"
 hypotenuse = sqrt(a*a+b*b)
 
+# Solve a system of linear equations
+2x + 3y = 5
+x - y = 1
+solve x, y
+
+# Long formulas continue while parentheses are open or a line ends with an operator
+monthly = (
+  250000 * 0.004
+) / (1 - (1 + 0.004)^-240)
+total = monthly * 240 +
+  5000
+
 `;
 
 export { defaultCode };
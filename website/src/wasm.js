@@ -14,4 +14,14 @@ function crate() {
   return loaded;
 }
 
-export { load, crate };
+let shared;
+
+// Session shared by the examples which reference each other
+function session() {
+  if (!shared) {
+    shared = new loaded.Session();
+  }
+  return shared;
+}
+
+export { load, crate, session };